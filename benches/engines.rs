@@ -2,7 +2,7 @@
 extern crate criterion;
 
 use criterion::{BatchSize, Criterion, ParameterizedBenchmark};
-use kvs::engines::{KvStore, KvsEngine, SledStore};
+use kvs::engines::{KvStore, KvsEngine, LsmStore, SledStore};
 
 use rand::prelude::*;
 use std::iter;
@@ -19,7 +19,9 @@ fn set_bench(c: &mut Criterion) {
                 },
                 |(store, _temp_dir)| {
                     for i in 1..(1 << 8) {
-                        store.set(format!("key{}", i), "value".to_string()).unwrap();
+                        store
+                            .set(format!("key{}", i).into_bytes(), b"value".to_vec())
+                            .unwrap();
                     }
                 },
                 BatchSize::SmallInput,
@@ -35,7 +37,24 @@ fn set_bench(c: &mut Criterion) {
             },
             |(db, _temp_dir)| {
                 for i in 1..(1 << 8) {
-                    db.set(format!("key{}", i), "value".to_string()).unwrap();
+                    db.set(format!("key{}", i).into_bytes(), b"value".to_vec())
+                        .unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    })
+    .with_function("lsm", |b, _| {
+        b.iter_batched(
+            || {
+                let temp_dir = TempDir::new().unwrap();
+                (LsmStore::open(temp_dir.path()).unwrap(), temp_dir)
+            },
+            |(store, _temp_dir)| {
+                for i in 1..(1 << 8) {
+                    store
+                        .set(format!("key{}", i).into_bytes(), b"value".to_vec())
+                        .unwrap();
                 }
             },
             BatchSize::SmallInput,
@@ -53,28 +72,64 @@ fn get_bench(c: &mut Criterion) {
             let store = KvStore::open(temp_dir.path()).unwrap();
             for key_i in 1..(1 << i) {
                 store
-                    .set(format!("key{}", key_i), "value".to_string())
+                    .set(format!("key{}", key_i).into_bytes(), b"value".to_vec())
                     .unwrap();
             }
             let mut rng = SmallRng::from_seed([0; 16]);
             b.iter(|| {
                 store
-                    .get(format!("key{}", rng.gen_range(1, 1 << i)))
+                    .get(format!("key{}", rng.gen_range(1, 1 << i)).into_bytes())
                     .unwrap();
             })
         },
         vec![8, 12],
     )
+    .with_function("kvs-mmap", |b, i| {
+        // Small segments, so nearly every key is read from a mapped segment.
+        let temp_dir = TempDir::new().unwrap();
+        let store = KvStore::builder()
+            .mmap_reads(true)
+            .segment_max_size(4 * 1024)
+            .open(temp_dir.path())
+            .unwrap();
+        for key_i in 1..(1 << i) {
+            store
+                .set(format!("key{}", key_i).into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+        let mut rng = SmallRng::from_seed([0; 16]);
+        b.iter(|| {
+            store
+                .get(format!("key{}", rng.gen_range(1, 1 << i)).into_bytes())
+                .unwrap();
+        })
+    })
     .with_function("sled", |b, i| {
         let temp_dir = TempDir::new().unwrap();
         let db = SledStore::open(temp_dir.path()).unwrap();
         for key_i in 1..(1 << i) {
-            db.set(format!("key{}", key_i), "value".to_string())
+            db.set(format!("key{}", key_i).into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+        let mut rng = SmallRng::from_seed([0; 16]);
+        b.iter(|| {
+            db.get(format!("key{}", rng.gen_range(1, 1 << i)).into_bytes())
+                .unwrap();
+        })
+    })
+    .with_function("lsm", |b, i| {
+        let temp_dir = TempDir::new().unwrap();
+        let store = LsmStore::open(temp_dir.path()).unwrap();
+        for key_i in 1..(1 << i) {
+            store
+                .set(format!("key{}", key_i).into_bytes(), b"value".to_vec())
                 .unwrap();
         }
         let mut rng = SmallRng::from_seed([0; 16]);
         b.iter(|| {
-            db.get(format!("key{}", rng.gen_range(1, 1 << i))).unwrap();
+            store
+                .get(format!("key{}", rng.gen_range(1, 1 << i)).into_bytes())
+                .unwrap();
         })
     })
     .sample_size(33);
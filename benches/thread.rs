@@ -11,7 +11,7 @@ use kvs::protocol::Value;
 use kvs::server::KvsServer;
 use kvs::thread_pool::{RayonThreadPool, SharedQueueThreadPool, ThreadPool};
 
-use kvs::engines::{KvStore, SledStore};
+use kvs::engines::{KvStore, SledStore, SyncPolicy};
 use std::net::TcpListener;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
@@ -43,16 +43,19 @@ fn read_queued_kvstore(c: &mut Criterion) {
                 let mut client = create_client(addr).unwrap();
                 for key_i in 1..500 {
                     client
-                        .send_cmd(Command::Set(format!("key{}", key_i), "value".to_string()))
+                        .send_cmd(Command::Set(
+                            format!("key{}", key_i).into_bytes(),
+                            b"value".to_vec(),
+                        ))
                         .unwrap();
                 }
                 b.iter(|| {
                     for key_i in 1..500 {
                         match client
-                            .send_cmd(Command::Get(format!("key{}", key_i)))
+                            .send_cmd(Command::Get(format!("key{}", key_i).into_bytes()))
                             .unwrap()
                         {
-                            Value::String(result) => assert_eq!(result, "value"),
+                            Value::Bytes(result) => assert_eq!(result, b"value"),
                             _ => assert_eq!(1, 0),
                         }
                     }
@@ -95,15 +98,18 @@ fn write_queued_kvstore(c: &mut Criterion) {
                 b.iter(|| {
                     for key_i in 1..500 {
                         client
-                            .send_cmd(Command::Set(format!("key{}", key_i), "value".to_string()))
+                            .send_cmd(Command::Set(
+                                format!("key{}", key_i).into_bytes(),
+                                b"value".to_vec(),
+                            ))
                             .unwrap();
                     }
                     for key_i in 1..500 {
                         match client
-                            .send_cmd(Command::Get(format!("key{}", key_i)))
+                            .send_cmd(Command::Get(format!("key{}", key_i).into_bytes()))
                             .unwrap()
                         {
-                            Value::String(result) => assert_eq!(result, "value"),
+                            Value::Bytes(result) => assert_eq!(result, b"value"),
                             _ => assert_eq!(1, 0),
                         }
                     }
@@ -114,6 +120,63 @@ fn write_queued_kvstore(c: &mut Criterion) {
     group.finish();
 }
 
+// Writes synced before they are acknowledged, sent by one client per server thread so the
+// group commit can batch them.
+fn write_queued_kvstore_synced(c: &mut Criterion) {
+    let mut group = c.benchmark_group("w_sharedkvs_synced");
+    for threads in [1, 2, 4].iter() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            threads,
+            |b, &threads| {
+                let s = threads as u32;
+                let pool = SharedQueueThreadPool::new(s).unwrap();
+                let drain = Discard;
+                let _log = Logger::root(drain, o!());
+                let temp_dir = TempDir::new().unwrap();
+
+                let find_available_port =
+                    || (8000..14000).find(|port| TcpListener::bind(("127.0.0.1", *port)).is_ok());
+
+                let port = find_available_port().unwrap();
+                let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+
+                let engine = KvStore::builder()
+                    .sync_policy(SyncPolicy::Always)
+                    .open(temp_dir.path())
+                    .unwrap();
+                let mut server = KvsServer::new(addr, engine, pool, _log).unwrap();
+
+                std::thread::spawn(move || {
+                    server.listen_and_serve().unwrap();
+                });
+
+                b.iter(|| {
+                    let clients: Vec<_> = (0..threads)
+                        .map(|client_i| {
+                            std::thread::spawn(move || {
+                                let mut client = create_client(addr).unwrap();
+                                for key_i in 1..100 {
+                                    client
+                                        .send_cmd(Command::Set(
+                                            format!("key{}-{}", client_i, key_i).into_bytes(),
+                                            b"value".to_vec(),
+                                        ))
+                                        .unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+                    for client in clients {
+                        client.join().unwrap();
+                    }
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
 fn read_rayon_kvstore(c: &mut Criterion) {
     let mut group = c.benchmark_group("r_rayon_kvs");
     for threads in [1, 2, 4].iter() {
@@ -139,16 +202,19 @@ fn read_rayon_kvstore(c: &mut Criterion) {
                 let mut client = create_client(addr).unwrap();
                 for key_i in 1..500 {
                     client
-                        .send_cmd(Command::Set(format!("key{}", key_i), "value".to_string()))
+                        .send_cmd(Command::Set(
+                            format!("key{}", key_i).into_bytes(),
+                            b"value".to_vec(),
+                        ))
                         .unwrap();
                 }
                 b.iter(|| {
                     for key_i in 1..500 {
                         match client
-                            .send_cmd(Command::Get(format!("key{}", key_i)))
+                            .send_cmd(Command::Get(format!("key{}", key_i).into_bytes()))
                             .unwrap()
                         {
-                            Value::String(result) => assert_eq!(result, "value"),
+                            Value::Bytes(result) => assert_eq!(result, b"value"),
                             _ => assert_eq!(1, 0),
                         }
                     }
@@ -191,15 +257,18 @@ fn write_rayon_kvstore(c: &mut Criterion) {
                 b.iter(|| {
                     for key_i in 1..500 {
                         client
-                            .send_cmd(Command::Set(format!("key{}", key_i), "value".to_string()))
+                            .send_cmd(Command::Set(
+                                format!("key{}", key_i).into_bytes(),
+                                b"value".to_vec(),
+                            ))
                             .unwrap();
                     }
                     for key_i in 1..500 {
                         match client
-                            .send_cmd(Command::Get(format!("key{}", key_i)))
+                            .send_cmd(Command::Get(format!("key{}", key_i).into_bytes()))
                             .unwrap()
                         {
-                            Value::String(result) => assert_eq!(result, "value"),
+                            Value::Bytes(result) => assert_eq!(result, b"value"),
                             _ => assert_eq!(1, 0),
                         }
                     }
@@ -235,16 +304,19 @@ fn read_rayon_sled(c: &mut Criterion) {
                 let mut client = create_client(addr).unwrap();
                 for key_i in 1..500 {
                     client
-                        .send_cmd(Command::Set(format!("key{}", key_i), "value".to_string()))
+                        .send_cmd(Command::Set(
+                            format!("key{}", key_i).into_bytes(),
+                            b"value".to_vec(),
+                        ))
                         .unwrap();
                 }
                 b.iter(|| {
                     for key_i in 1..500 {
                         match client
-                            .send_cmd(Command::Get(format!("key{}", key_i)))
+                            .send_cmd(Command::Get(format!("key{}", key_i).into_bytes()))
                             .unwrap()
                         {
-                            Value::String(result) => assert_eq!(result, "value"),
+                            Value::Bytes(result) => assert_eq!(result, b"value"),
                             _ => assert_eq!(1, 0),
                         }
                     }
@@ -288,15 +360,18 @@ fn write_rayon_sled(c: &mut Criterion) {
                 b.iter(|| {
                     for key_i in 1..500 {
                         client
-                            .send_cmd(Command::Set(format!("key{}", key_i), "value".to_string()))
+                            .send_cmd(Command::Set(
+                                format!("key{}", key_i).into_bytes(),
+                                b"value".to_vec(),
+                            ))
                             .unwrap();
                     }
                     for key_i in 1..500 {
                         match client
-                            .send_cmd(Command::Get(format!("key{}", key_i)))
+                            .send_cmd(Command::Get(format!("key{}", key_i).into_bytes()))
                             .unwrap()
                         {
-                            Value::String(result) => assert_eq!(result, "value"),
+                            Value::Bytes(result) => assert_eq!(result, b"value"),
                             _ => assert_eq!(1, 0),
                         }
                     }
@@ -310,6 +385,7 @@ fn write_rayon_sled(c: &mut Criterion) {
 criterion_group!(
     benches,
     write_queued_kvstore,
+    write_queued_kvstore_synced,
     read_queued_kvstore,
     write_rayon_kvstore,
     read_rayon_kvstore,
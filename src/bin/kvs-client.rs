@@ -1,11 +1,14 @@
 #[macro_use]
 extern crate clap;
 use clap::{App, AppSettings};
-use kvs::client::create_client;
-use kvs::command::Command;
+use kvs::client::{create_client, reply_error};
+use kvs::command::{Command, ScanCursor};
 use kvs::error::{Error, ErrorKind, Result};
 use kvs::protocol::Value;
 use std::env;
+use std::io::{self, Write};
+use std::process;
+use std::time::Duration;
 
 fn main() -> Result<()> {
     let yaml = load_yaml!("client-cli.yml");
@@ -23,20 +26,73 @@ fn main() -> Result<()> {
     if let Some(matches) = matches.subcommand_matches("get") {
         addr = matches.value_of("addr");
         let key = matches.value_of("KEY").unwrap();
-        command = Some(Command::Get(key.to_string()));
+        command = Some(Command::Get(key.as_bytes().to_vec()));
     }
 
     if let Some(matches) = matches.subcommand_matches("set") {
         addr = matches.value_of("addr");
         let key = matches.value_of("KEY").unwrap();
         let value = matches.value_of("VALUE").unwrap();
-        command = Some(Command::Set(key.to_string(), value.to_string()));
+        let (key, value) = (key.as_bytes().to_vec(), value.as_bytes().to_vec());
+        command = Some(if matches.is_present("nx") {
+            Command::SetIfAbsent(key, value)
+        } else if matches.is_present("xx") {
+            Command::SetIfPresent(key, value)
+        } else if let Some(ttl) = matches.value_of("ttl") {
+            Command::SetWithTtl(key, value, ttl_millis(ttl))
+        } else {
+            Command::Set(key, value)
+        });
+    }
+
+    if let Some(matches) = matches.subcommand_matches("expire") {
+        addr = matches.value_of("addr");
+        let key = matches.value_of("KEY").unwrap();
+        let ttl = ttl_millis(matches.value_of("TTL").unwrap());
+        command = Some(Command::Expire(key.as_bytes().to_vec(), ttl));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("ttl") {
+        addr = matches.value_of("addr");
+        let key = matches.value_of("KEY").unwrap();
+        command = Some(Command::Ttl(key.as_bytes().to_vec()));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("persist") {
+        addr = matches.value_of("addr");
+        let key = matches.value_of("KEY").unwrap();
+        command = Some(Command::Persist(key.as_bytes().to_vec()));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("incr") {
+        addr = matches.value_of("addr");
+        let key = matches.value_of("KEY").unwrap().as_bytes().to_vec();
+        command = Some(match matches.value_of("by") {
+            Some(_delta) => {
+                let delta = value_t!(matches, "by", i64).unwrap_or_else(|e| e.exit());
+                Command::IncrBy(key, delta)
+            }
+            None => Command::Incr(key),
+        });
+    }
+
+    if let Some(matches) = matches.subcommand_matches("decr") {
+        addr = matches.value_of("addr");
+        let key = matches.value_of("KEY").unwrap();
+        command = Some(Command::Decr(key.as_bytes().to_vec()));
     }
 
     if let Some(matches) = matches.subcommand_matches("rm") {
         addr = matches.value_of("addr");
         let key = matches.value_of("KEY").unwrap();
-        command = Some(Command::Rm(key.to_string()));
+        command = Some(Command::Rm(key.as_bytes().to_vec()));
+    }
+
+    if let Some(matches) = matches.subcommand_matches("scan") {
+        let addr = matches.value_of("addr").unwrap();
+        let prefix = matches.value_of("PREFIX").unwrap_or("");
+        let count = value_t!(matches, "count", usize).unwrap_or_else(|e| e.exit());
+        return scan(addr, prefix.as_bytes().to_vec(), count);
     }
 
     if let Some(cmd) = command {
@@ -53,11 +109,24 @@ fn main() -> Result<()> {
                     Value::None => {
                         if let Some(_matches) = matches.subcommand_matches("get") {
                             println!("Key not found")
+                        } else if let Some(_matches) = matches.subcommand_matches("ttl") {
+                            println!("No expiry")
                         }
                     }
+                    Value::Integer(0) if matches.subcommand_matches("set").is_some() => {
+                        eprintln!("Key not set");
+                        process::exit(1);
+                    }
+                    Value::Integer(_) if matches.subcommand_matches("set").is_some() => {}
                     Value::Integer(i) => println!("{}", i),
-                    Value::String(s) => println!("{}", s),
-                    Value::Error(_) => return Err(Error::from(ErrorKind::KeyNotFound)),
+                    Value::Bytes(bytes) => {
+                        let mut stdout = io::stdout();
+                        stdout
+                            .write_all(&bytes)
+                            .and_then(|_| stdout.write_all(b"\n"))
+                            .map_err(|_err| Error::from(ErrorKind::UnknownError))?;
+                    }
+                    Value::Error(message) => return Err(reply_error(&message)),
                     _ => return Err(Error::from(ErrorKind::UnknownError)),
                 },
                 Err(err) => return Err(err),
@@ -66,3 +135,56 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
+
+// Milliseconds of a time to live such as `500ms`, `30s`, `5m` or `1h`. A bare number is a
+// number of seconds.
+fn ttl_millis(ttl: &str) -> u64 {
+    let split = ttl.find(|c: char| !c.is_ascii_digit()).unwrap_or(ttl.len());
+    let (amount, unit) = ttl.split_at(split);
+    let unit = match unit {
+        "ms" => Some(Duration::from_millis(1)),
+        "" | "s" => Some(Duration::from_secs(1)),
+        "m" => Some(Duration::from_secs(60)),
+        "h" => Some(Duration::from_secs(60 * 60)),
+        _ => None,
+    };
+    match (amount.parse::<u32>(), unit) {
+        (Ok(amount), Some(unit)) => (unit * amount).as_millis() as u64,
+        _ => clap::Error::with_description(
+            &format!(
+                "Invalid time to live '{}', expected e.g. 500ms, 30s, 5m or 1h",
+                ttl
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit(),
+    }
+}
+
+// Print every key starting with `prefix` and its value, one pair per line.
+fn scan(addr: &str, prefix: Vec<u8>, count: usize) -> Result<()> {
+    let mut client = match create_client(addr) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("Connection failed. {}", err);
+            return Ok(());
+        }
+    };
+    let mut stdout = io::stdout();
+    let mut cursor = ScanCursor::Start;
+    loop {
+        let (next, pairs) = client.scan(cursor, prefix.clone(), count)?;
+        for (key, value) in pairs {
+            stdout
+                .write_all(&key)
+                .and_then(|_| stdout.write_all(b" "))
+                .and_then(|_| stdout.write_all(&value))
+                .and_then(|_| stdout.write_all(b"\n"))
+                .map_err(|_err| Error::from(ErrorKind::UnknownError))?;
+        }
+        if next == ScanCursor::Start {
+            return Ok(());
+        }
+        cursor = next;
+    }
+}
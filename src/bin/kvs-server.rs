@@ -1,10 +1,16 @@
 #[macro_use]
 extern crate clap;
+use clap::ArgMatches;
 use clap::{App, AppSettings};
-use kvs::engines::{KvStore, KvsEngine, SledStore};
+use kvs::engines::{
+    is_lsm_store, CompactionStyle, CompactionTrigger, CompressionPolicy, EncryptionKey, KvStore,
+    KvStoreOptions, KvsEngine, LsmStore, LsmStoreOptions, MemStore, RecoveryMode, SledStore,
+    SyncPolicy,
+};
 use kvs::error::{Error, ErrorKind, Result};
 use kvs::server::KvsServer;
 use std::env;
+use std::fs;
 use std::net::ToSocketAddrs;
 use std::path::Path;
 
@@ -31,11 +37,12 @@ fn main() -> Result<()> {
         .get_matches();
 
     let addr = matches.value_of("addr").unwrap();
+    let dir = Path::new(matches.value_of("dir").unwrap());
     info!(_log, "Starting Kvs server version {}", crate_version!());
     info!(_log, "Listening on {}", addr);
 
     let engine_parameter = matches.value_of("engine");
-    let current_engine = current_eng();
+    let current_engine = current_eng(dir);
     let engine = match engine_parameter {
         Some("kvs") => match current_engine.as_ref().map(|s| &s[..]) {
             Some("kvs") | None => {
@@ -51,6 +58,18 @@ fn main() -> Result<()> {
             }
             _ => return Err(Error::from(ErrorKind::UncompatibleEngine)),
         },
+        Some("lsm") => match current_engine.as_ref().map(|s| &s[..]) {
+            Some("lsm") | None => {
+                info!(_log, "Using lsm engine");
+                Some("lsm")
+            }
+            _ => return Err(Error::from(ErrorKind::UncompatibleEngine)),
+        },
+        // Nothing is read from or written to the directory, whatever engine it holds.
+        Some("memory") => {
+            info!(_log, "Using memory engine");
+            Some("memory")
+        }
         Some(eng) => {
             error!(_log, "Wrong engine specified: {}", eng);
             return Err(Error::from(ErrorKind::InvalidEngine));
@@ -62,6 +81,7 @@ fn main() -> Result<()> {
             );
             match current_engine.as_ref().map(|s| &s[..]) {
                 Some("kvs") | None => Some("kvs"),
+                Some("lsm") => Some("lsm"),
                 _ => Some("sled"),
             }
         }
@@ -71,28 +91,168 @@ fn main() -> Result<()> {
 
     match engine {
         Some("kvs") => {
-            let engine = KvStore::open(".")?;
+            let mut options = kvs_options(&matches);
+            for key in encryption_keys(&matches)? {
+                options = options.encryption_key(key);
+            }
+            let engine = options.logger(_log.clone()).open(dir)?;
             run_with(addr, engine, pool, _log)?;
         }
         Some("sled") => {
-            let engine = SledStore::open(".")?;
+            if matches.is_present("create-dir") {
+                fs::create_dir_all(dir).map_err(|_err| Error::from(ErrorKind::FileError))?;
+            }
+            let sync_policy = sync_policy(&matches).unwrap_or(SyncPolicy::Always);
+            let compression = compression_policy(&matches).unwrap_or(CompressionPolicy::Off);
+            let engine = SledStore::open_with_compression(dir, sync_policy, compression)?;
             run_with(addr, engine, pool, _log)?;
         }
+        Some("lsm") => {
+            let engine = lsm_options(&matches).logger(_log.clone()).open(dir)?;
+            run_with(addr, engine, pool, _log)?;
+        }
+        Some("memory") => run_with(addr, MemStore::new(), pool, _log)?,
         _ => return Err(Error::from(ErrorKind::UnknownError)),
     }
 
     Ok(())
 }
 
-fn current_eng() -> Option<String> {
-    if Path::new("./kvs.log").exists() {
+// Options of the kvs engine given through the command line.
+fn kvs_options(matches: &ArgMatches) -> KvStoreOptions {
+    let mut options = KvStore::builder().create_dir(matches.is_present("create-dir"));
+    if matches.is_present("compaction-ops") {
+        let operations = value_t!(matches, "compaction-ops", u64).unwrap_or_else(|e| e.exit());
+        options = options.compaction_trigger(CompactionTrigger::Operations(operations));
+    }
+    if matches.is_present("compaction-ratio") {
+        let ratio = value_t!(matches, "compaction-ratio", f64).unwrap_or_else(|e| e.exit());
+        options = options.compaction_trigger(CompactionTrigger::DeadBytesRatio(ratio));
+    }
+    if matches.is_present("segment-size") {
+        let size = value_t!(matches, "segment-size", u64).unwrap_or_else(|e| e.exit());
+        options = options.segment_max_size(size);
+    }
+    if matches.is_present("blob-threshold") {
+        let size = value_t!(matches, "blob-threshold", u64).unwrap_or_else(|e| e.exit());
+        options = options.blob_threshold(size);
+    }
+    if matches.is_present("cache-size") {
+        let size = value_t!(matches, "cache-size", u64).unwrap_or_else(|e| e.exit());
+        options = options.cache_size(size);
+    }
+    if matches.is_present("mmap-reads") {
+        options = options.mmap_reads(true);
+    }
+    if matches.is_present("read-buffer") {
+        let size = value_t!(matches, "read-buffer", usize).unwrap_or_else(|e| e.exit());
+        options = options.read_buffer_size(size);
+    }
+    let recovery = match matches.value_of("recovery") {
+        Some("strict") => RecoveryMode::Strict,
+        _ => RecoveryMode::TruncateTail,
+    };
+    if let Some(sync_policy) = sync_policy(matches) {
+        options = options.sync_policy(sync_policy);
+    }
+    if let Some(compression) = compression_policy(matches) {
+        options = options.compression(compression);
+    }
+    options.recovery(recovery)
+}
+
+// Options of the lsm engine given through the command line.
+fn lsm_options(matches: &ArgMatches) -> LsmStoreOptions {
+    let mut options = LsmStore::builder().create_dir(matches.is_present("create-dir"));
+    if matches.is_present("memtable-size") {
+        let size = value_t!(matches, "memtable-size", u64).unwrap_or_else(|e| e.exit());
+        options = options.memtable_size(size);
+    }
+    if let Some("tiered") = matches.value_of("lsm-compaction") {
+        options = options.compaction_style(CompactionStyle::Tiered { tier_width: 4 });
+    }
+    if let Some(sync_policy) = sync_policy(matches) {
+        options = options.sync_policy(sync_policy);
+    }
+    options
+}
+
+// Sync policy given through the command line, if any.
+fn sync_policy(matches: &ArgMatches) -> Option<SyncPolicy> {
+    match matches.value_of("sync")? {
+        "always" => Some(SyncPolicy::Always),
+        "never" => Some(SyncPolicy::Never),
+        _ => {
+            let millis = value_t!(matches, "sync", u64).unwrap_or_else(|e| e.exit());
+            Some(SyncPolicy::EveryMillis(millis))
+        }
+    }
+}
+
+// Compression policy given through the command line, if any.
+fn compression_policy(matches: &ArgMatches) -> Option<CompressionPolicy> {
+    match matches.value_of("compression")? {
+        "off" => Some(CompressionPolicy::Off),
+        "always" => Some(CompressionPolicy::Always),
+        _ => {
+            let size = value_t!(matches, "compression", u64).unwrap_or_else(|e| e.exit());
+            Some(CompressionPolicy::Above(size))
+        }
+    }
+}
+
+// Encryption keys given through the command line as `ID:FILE`, the file holding the key as 64
+// hex digits.
+fn encryption_keys(matches: &ArgMatches) -> Result<Vec<EncryptionKey>> {
+    let values = match matches.values_of("encryption-key") {
+        Some(values) => values,
+        None => return Ok(Vec::new()),
+    };
+    values
+        .map(|value| {
+            let mut parts = value.splitn(2, ':');
+            let id = parts.next().and_then(|id| id.parse::<u32>().ok());
+            let (id, file) = match (id, parts.next()) {
+                (Some(id), Some(file)) => (id, file),
+                _ => return Err(Error::from(ErrorKind::ParsingError)),
+            };
+            let hex = fs::read_to_string(file).map_err(|_err| Error::from(ErrorKind::FileError))?;
+            let hex = hex.trim();
+            let mut key = [0; 32];
+            if hex.len() != 2 * key.len() || !hex.is_ascii() {
+                return Err(Error::from(ErrorKind::ParsingError));
+            }
+            for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+                let digits = std::str::from_utf8(digits).unwrap();
+                *byte = u8::from_str_radix(digits, 16)
+                    .map_err(|_err| Error::from(ErrorKind::ParsingError))?;
+            }
+            Ok(EncryptionKey::new(id, key))
+        })
+        .collect()
+}
+
+fn current_eng(dir: &Path) -> Option<String> {
+    if has_kvs_log(dir) {
         return Some("kvs".to_owned());
-    } else if Path::new("./db").exists() {
+    } else if is_lsm_store(dir) {
+        return Some("lsm".to_owned());
+    } else if dir.join("db").exists() {
         return Some("sled".to_owned());
     }
     None
 }
 
+// The kvs engine stores its log as `<id>.log` segments, older versions used a single `kvs.log`.
+fn has_kvs_log(dir: &Path) -> bool {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .any(|entry| entry.path().extension() == Some("log".as_ref())),
+        Err(_err) => false,
+    }
+}
+
 pub fn run_with<E: KvsEngine, P: ThreadPool, A: ToSocketAddrs, L: Into<slog::Logger>>(
     addr: A,
     engine: E,
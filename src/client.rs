@@ -1,8 +1,10 @@
 use crate::error::{Error, ErrorKind, Result};
 use std::net::ToSocketAddrs;
+use std::time::Duration;
 
-use crate::command::Command;
+use crate::command::{Command, ScanCursor};
 use crate::connection::Connection;
+use crate::engines::WriteBatch;
 use crate::protocol::Value;
 
 pub fn create_client<A: ToSocketAddrs>(address: A) -> Result<KvsClient> {
@@ -10,6 +12,22 @@ pub fn create_client<A: ToSocketAddrs>(address: A) -> Result<KvsClient> {
     Ok(client)
 }
 
+/// Error of the kind named in an error reply of the server.
+pub fn reply_error(message: &str) -> Error {
+    let kind = match message {
+        "KeyNotFound" => ErrorKind::KeyNotFound,
+        "DataTooLarge" => ErrorKind::DataTooLarge,
+        "InvalidCommand" => ErrorKind::InvalidCommand,
+        "NotAnInteger" => ErrorKind::NotAnInteger,
+        "IntegerOverflow" => ErrorKind::IntegerOverflow,
+        _ => ErrorKind::UnknownError,
+    };
+    Error::from(kind)
+}
+
+/// Cursor of the next page of a scan, with the keys and values of the current one.
+pub type ScanPage = (ScanCursor, Vec<(Vec<u8>, Vec<u8>)>);
+
 pub struct KvsClient {
     conn: Connection,
 }
@@ -26,6 +44,176 @@ impl KvsClient {
         self.read()
     }
 
+    /// Get the value of `key`, `None` when the key does not exist.
+    pub fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        match self.send_cmd(Command::Get(key))? {
+            Value::Bytes(value) => Ok(Some(value)),
+            Value::None => Ok(None),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Set the value of `key`.
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        match self.send_cmd(Command::Set(key, value))? {
+            Value::None => Ok(()),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Set the value of `key`, expiring after `ttl`.
+    pub fn set_with_ttl(&mut self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        match self.send_cmd(Command::SetWithTtl(key, value, ttl.as_millis() as u64))? {
+            Value::None => Ok(()),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Make `key` expire after `ttl`. Returns whether the key exists.
+    pub fn expire(&mut self, key: Vec<u8>, ttl: Duration) -> Result<bool> {
+        self.send_flag(Command::Expire(key, ttl.as_millis() as u64))
+    }
+
+    /// Get the time left before `key` expires, `None` when it never expires. Fails with
+    /// `KeyNotFound` when the key does not exist.
+    pub fn ttl(&mut self, key: Vec<u8>) -> Result<Option<Duration>> {
+        match self.send_cmd(Command::Ttl(key))? {
+            Value::Integer(millis) if millis >= 0 => Ok(Some(Duration::from_millis(millis as u64))),
+            Value::None => Ok(None),
+            Value::Error(message) => Err(reply_error(&message)),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Remove the expiry of `key`. Returns whether the key had one.
+    pub fn persist(&mut self, key: Vec<u8>) -> Result<bool> {
+        self.send_flag(Command::Persist(key))
+    }
+
+    /// Add `delta` to the integer stored in `key` and get the result, a missing key counting
+    /// as 0. Fails with `NotAnInteger` or `IntegerOverflow`, see `KvsEngine::increment`.
+    pub fn increment(&mut self, key: Vec<u8>, delta: i64) -> Result<i64> {
+        let command = match delta {
+            1 => Command::Incr(key),
+            -1 => Command::Decr(key),
+            delta => Command::IncrBy(key, delta),
+        };
+        match self.send_cmd(command)? {
+            Value::Integer(value) => Ok(value),
+            Value::Error(message) => Err(reply_error(&message)),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Set `key` to `new`, or remove it when `new` is `None`, if its current value is
+    /// `expected`. Returns whether the key was swapped.
+    pub fn compare_and_swap(
+        &mut self,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        self.send_flag(Command::CompareAndSwap(key, expected, new))
+    }
+
+    /// Set `key` only if it does not exist. Returns whether the key was set.
+    pub fn set_if_absent(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        self.send_flag(Command::SetIfAbsent(key, value))
+    }
+
+    /// Set `key` only if it already exists. Returns whether the key was set.
+    pub fn set_if_present(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        self.send_flag(Command::SetIfPresent(key, value))
+    }
+
+    // Send a command replying 1 when it did something and 0 otherwise.
+    fn send_flag(&mut self, command: Command) -> Result<bool> {
+        match self.send_cmd(command)? {
+            Value::Integer(flag) => Ok(flag != 0),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Remove `key`, failing with `KeyNotFound` when it does not exist.
+    pub fn remove(&mut self, key: Vec<u8>) -> Result<()> {
+        match self.send_cmd(Command::Rm(key))? {
+            Value::None => Ok(()),
+            Value::Error(_) => Err(Error::from(ErrorKind::KeyNotFound)),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Apply every write of `batch`, or none of them.
+    pub fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        let value = Value::Batch(batch.into_commands());
+        self.send(&value.encode())?;
+        match self.read()? {
+            Value::None => Ok(()),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Watch `key`: the next `exec` does nothing if the key is written in the meantime.
+    pub fn watch(&mut self, key: Vec<u8>) -> Result<()> {
+        match self.send_cmd(Command::Watch(key))? {
+            Value::None => Ok(()),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Open a transaction: the following `get`, `set` and `remove` are queued until `exec` or
+    /// `discard`, and `get` returns `None` until then.
+    pub fn multi(&mut self) -> Result<()> {
+        match self.send_cmd(Command::Multi)? {
+            Value::None => Ok(()),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Run the queued commands in a transaction and get their replies, or `None` when a
+    /// watched key was modified and nothing was run.
+    pub fn exec(&mut self) -> Result<Option<Vec<Value>>> {
+        match self.send_cmd(Command::Exec)? {
+            Value::Array(replies) => Ok(Some(replies)),
+            Value::None => Ok(None),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Drop the queued commands and stop watching keys.
+    pub fn discard(&mut self) -> Result<()> {
+        match self.send_cmd(Command::Discard)? {
+            Value::None => Ok(()),
+            _ => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+
+    /// Get the page of at most `count` keys starting with `prefix` that follows `cursor`, with
+    /// their values. The scan is over once the returned cursor is `ScanCursor::Start`.
+    pub fn scan(&mut self, cursor: ScanCursor, prefix: Vec<u8>, count: usize) -> Result<ScanPage> {
+        let reply = self.send_cmd(Command::Scan(cursor, prefix, count))?;
+        let mut reply = match reply {
+            Value::Array(reply) if reply.len() == 2 => reply.into_iter(),
+            _ => return Err(Error::from(ErrorKind::InvalidData)),
+        };
+        let cursor = match reply.next() {
+            Some(Value::Bytes(cursor)) => ScanCursor::decode(cursor)?,
+            _ => return Err(Error::from(ErrorKind::InvalidData)),
+        };
+        let mut values = match reply.next() {
+            Some(Value::Array(values)) => values.into_iter(),
+            _ => return Err(Error::from(ErrorKind::InvalidData)),
+        };
+        let mut pairs = Vec::new();
+        while let Some(key) = values.next() {
+            match (key, values.next()) {
+                (Value::Bytes(key), Some(Value::Bytes(value))) => pairs.push((key, value)),
+                _ => return Err(Error::from(ErrorKind::InvalidData)),
+            }
+        }
+        Ok((cursor, pairs))
+    }
+
     pub fn send(&mut self, value: &[u8]) -> Result<()> {
         self.conn.write(value)
     }
@@ -1,35 +1,243 @@
 use crate::error::{Error, ErrorKind, Result};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::fmt;
-use std::str::FromStr;
 
+/// Request sent to the server. Keys and values are arbitrary bytes.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Command {
-    Get(String),
-    Rm(String),
-    Set(String, String),
+    Get(Vec<u8>),
+    Rm(Vec<u8>),
+    Set(Vec<u8>, Vec<u8>),
+    /// Get a page of at most `count` keys starting with a prefix, with their values. The
+    /// reply holds the cursor of the next page followed by the keys and values.
+    Scan(ScanCursor, Vec<u8>, usize),
+    /// Swap the value of a key if it is the expected one, see `KvsEngine::compare_and_swap`.
+    /// The reply is 1 when the key was swapped and 0 otherwise, like for the conditional sets.
+    CompareAndSwap(Vec<u8>, Option<Vec<u8>>, Option<Vec<u8>>),
+    SetIfAbsent(Vec<u8>, Vec<u8>),
+    SetIfPresent(Vec<u8>, Vec<u8>),
+    /// Set a key expiring after the given number of milliseconds.
+    SetWithTtl(Vec<u8>, Vec<u8>, u64),
+    /// Make a key expire after the given number of milliseconds. The reply is 1 when the key
+    /// exists and 0 otherwise.
+    Expire(Vec<u8>, u64),
+    /// Get the milliseconds left before a key expires. The reply is empty for a key that
+    /// never expires, and an error for a missing key.
+    Ttl(Vec<u8>),
+    /// Remove the expiry of a key. The reply is 1 when the key had one and 0 otherwise.
+    Persist(Vec<u8>),
+    /// Add 1 to the integer stored in a key. The reply is the new value, see
+    /// `KvsEngine::increment`.
+    Incr(Vec<u8>),
+    /// Subtract 1 from the integer stored in a key.
+    Decr(Vec<u8>),
+    /// Add the given delta to the integer stored in a key.
+    IncrBy(Vec<u8>, i64),
+    /// Make the next `Exec` fail if a key is written before it, even back to the same value.
+    Watch(Vec<u8>),
+    /// Queue the following commands until `Exec` runs them in a transaction, or `Discard`
+    /// drops them.
+    Multi,
+    Exec,
+    Discard,
+}
+
+/// Position of a `Scan` in the keyspace.
+///
+/// A scan starts with `ScanCursor::Start` and ends when the server replies with it again.
+/// The cursor only remembers the last key returned, so the server keeps no state between
+/// pages and the scan is not disturbed by concurrent writes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ScanCursor {
+    Start,
+    After(Vec<u8>),
+}
+
+impl ScanCursor {
+    /// Encode the cursor for the wire, where it is opaque to clients.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            ScanCursor::Start => Vec::new(),
+            ScanCursor::After(key) => {
+                let mut bytes = Vec::with_capacity(key.len() + 1);
+                bytes.push(b'>');
+                bytes.extend_from_slice(key);
+                bytes
+            }
+        }
+    }
+
+    pub fn decode(mut bytes: Vec<u8>) -> Result<Self> {
+        match bytes.first() {
+            None => Ok(ScanCursor::Start),
+            Some(b'>') => Ok(ScanCursor::After(bytes.split_off(1))),
+            Some(_) => Err(Error::from(ErrorKind::InvalidCommand)),
+        }
+    }
+
+    /// Key after which the scan resumes.
+    pub fn into_key(self) -> Option<Vec<u8>> {
+        match self {
+            ScanCursor::Start => None,
+            ScanCursor::After(key) => Some(key),
+        }
+    }
+}
+
+impl Command {
+    /// Name of the command followed by its arguments, as sent on the wire.
+    pub fn args(&self) -> Vec<Cow<'_, [u8]>> {
+        match self {
+            Command::Get(key) => vec![Cow::from(&b"GET"[..]), Cow::from(&key[..])],
+            Command::Rm(key) => vec![Cow::from(&b"RM"[..]), Cow::from(&key[..])],
+            Command::Set(key, value) => vec![
+                Cow::from(&b"SET"[..]),
+                Cow::from(&key[..]),
+                Cow::from(&value[..]),
+            ],
+            Command::Scan(cursor, prefix, count) => vec![
+                Cow::from(&b"SCAN"[..]),
+                Cow::from(cursor.encode()),
+                Cow::from(&prefix[..]),
+                Cow::from(count.to_string().into_bytes()),
+            ],
+            Command::CompareAndSwap(key, expected, new) => vec![
+                Cow::from(&b"CAS"[..]),
+                Cow::from(&key[..]),
+                Cow::from(encode_option(expected)),
+                Cow::from(encode_option(new)),
+            ],
+            Command::SetIfAbsent(key, value) => vec![
+                Cow::from(&b"SETNX"[..]),
+                Cow::from(&key[..]),
+                Cow::from(&value[..]),
+            ],
+            Command::SetIfPresent(key, value) => vec![
+                Cow::from(&b"SETXX"[..]),
+                Cow::from(&key[..]),
+                Cow::from(&value[..]),
+            ],
+            Command::SetWithTtl(key, value, ttl) => vec![
+                Cow::from(&b"SETTTL"[..]),
+                Cow::from(&key[..]),
+                Cow::from(&value[..]),
+                Cow::from(ttl.to_string().into_bytes()),
+            ],
+            Command::Expire(key, ttl) => vec![
+                Cow::from(&b"EXPIRE"[..]),
+                Cow::from(&key[..]),
+                Cow::from(ttl.to_string().into_bytes()),
+            ],
+            Command::Ttl(key) => vec![Cow::from(&b"TTL"[..]), Cow::from(&key[..])],
+            Command::Persist(key) => vec![Cow::from(&b"PERSIST"[..]), Cow::from(&key[..])],
+            Command::Incr(key) => vec![Cow::from(&b"INCR"[..]), Cow::from(&key[..])],
+            Command::Decr(key) => vec![Cow::from(&b"DECR"[..]), Cow::from(&key[..])],
+            Command::IncrBy(key, delta) => vec![
+                Cow::from(&b"INCRBY"[..]),
+                Cow::from(&key[..]),
+                Cow::from(delta.to_string().into_bytes()),
+            ],
+            Command::Watch(key) => vec![Cow::from(&b"WATCH"[..]), Cow::from(&key[..])],
+            Command::Multi => vec![Cow::from(&b"MULTI"[..])],
+            Command::Exec => vec![Cow::from(&b"EXEC"[..])],
+            Command::Discard => vec![Cow::from(&b"DISCARD"[..])],
+        }
+    }
+
+    /// Build a command from its name followed by its arguments.
+    pub fn from_args(args: Vec<Vec<u8>>) -> Result<Self> {
+        let mut args = args.into_iter();
+        let name = args
+            .next()
+            .ok_or_else(|| Error::from(ErrorKind::InvalidCommand))?;
+        let mut arg = || {
+            args.next()
+                .ok_or_else(|| Error::from(ErrorKind::InvalidCommand))
+        };
+        let command = match &name[..] {
+            b"GET" => Command::Get(arg()?),
+            b"SET" => Command::Set(arg()?, arg()?),
+            b"RM" => Command::Rm(arg()?),
+            b"SCAN" => Command::Scan(ScanCursor::decode(arg()?)?, arg()?, parse_count(arg()?)?),
+            b"CAS" => {
+                Command::CompareAndSwap(arg()?, decode_option(arg()?)?, decode_option(arg()?)?)
+            }
+            b"SETNX" => Command::SetIfAbsent(arg()?, arg()?),
+            b"SETXX" => Command::SetIfPresent(arg()?, arg()?),
+            b"SETTTL" => Command::SetWithTtl(arg()?, arg()?, parse_millis(arg()?)?),
+            b"EXPIRE" => Command::Expire(arg()?, parse_millis(arg()?)?),
+            b"TTL" => Command::Ttl(arg()?),
+            b"PERSIST" => Command::Persist(arg()?),
+            b"INCR" => Command::Incr(arg()?),
+            b"DECR" => Command::Decr(arg()?),
+            b"INCRBY" => Command::IncrBy(arg()?, parse_delta(arg()?)?),
+            b"WATCH" => Command::Watch(arg()?),
+            b"MULTI" => Command::Multi,
+            b"EXEC" => Command::Exec,
+            b"DISCARD" => Command::Discard,
+            _ => return Err(Error::from(ErrorKind::InvalidCommand)),
+        };
+        match args.next() {
+            Some(_extra) => Err(Error::from(ErrorKind::InvalidCommand)),
+            None => Ok(command),
+        }
+    }
 }
 
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Command::Get(key) => write!(f, "GET {}", key),
-            Command::Rm(key) => write!(f, "RM {}", key),
-            Command::Set(key, value) => write!(f, "SET {} {}", key, value),
+        let args = self.args();
+        write!(f, "{}", String::from_utf8_lossy(&args[0]))?;
+        for arg in &args[1..] {
+            write!(f, " {}", String::from_utf8_lossy(arg))?;
         }
+        Ok(())
     }
 }
 
-impl FromStr for Command {
-    type Err = Error;
+// Count of a `Scan`, which has to return at least one key to make progress.
+fn parse_count(bytes: Vec<u8>) -> Result<usize> {
+    match String::from_utf8(bytes).map(|count| count.parse::<usize>()) {
+        Ok(Ok(count)) if count > 0 => Ok(count),
+        _ => Err(Error::from(ErrorKind::InvalidCommand)),
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self> {
-        let words = s.split_whitespace().collect::<Vec<_>>();
-        match words[0] {
-            "GET" => Ok(Command::Get(words[1].to_owned())),
-            "SET" => Ok(Command::Set(words[1].to_owned(), words[2].to_owned())),
-            "RM" => Ok(Command::Rm(words[1].to_owned())),
-            _ => Err(Error::from(ErrorKind::InvalidCommand)),
+// Time to live of `SetWithTtl` and `Expire`.
+fn parse_millis(bytes: Vec<u8>) -> Result<u64> {
+    match String::from_utf8(bytes).map(|millis| millis.parse::<u64>()) {
+        Ok(Ok(millis)) => Ok(millis),
+        _ => Err(Error::from(ErrorKind::InvalidCommand)),
+    }
+}
+
+// Delta of an `IncrBy`.
+fn parse_delta(bytes: Vec<u8>) -> Result<i64> {
+    match String::from_utf8(bytes).map(|delta| delta.parse::<i64>()) {
+        Ok(Ok(delta)) => Ok(delta),
+        _ => Err(Error::from(ErrorKind::InvalidCommand)),
+    }
+}
+
+// Optional value of a `CompareAndSwap`: empty for `None`, and prefixed with `=` otherwise so
+// an empty value can be told apart.
+fn encode_option(value: &Option<Vec<u8>>) -> Vec<u8> {
+    match value {
+        None => Vec::new(),
+        Some(value) => {
+            let mut bytes = Vec::with_capacity(value.len() + 1);
+            bytes.push(b'=');
+            bytes.extend_from_slice(value);
+            bytes
         }
     }
 }
+
+fn decode_option(mut bytes: Vec<u8>) -> Result<Option<Vec<u8>>> {
+    match bytes.first() {
+        None => Ok(None),
+        Some(b'=') => Ok(Some(bytes.split_off(1))),
+        Some(_) => Err(Error::from(ErrorKind::InvalidCommand)),
+    }
+}
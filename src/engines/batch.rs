@@ -0,0 +1,86 @@
+use crate::command::Command;
+use crate::error::{Error, ErrorKind, Result};
+
+/// Writes applied together by `KvsEngine::write_batch`: either all of them are visible, or
+/// none is, even if the process dies in the middle of the batch.
+///
+/// Unlike `KvsEngine::remove`, removing a key that does not exist is not an error inside a
+/// batch, so a batch never fails halfway because of its own content.
+///
+/// ```
+/// use kvs::engines::WriteBatch;
+/// let mut batch = WriteBatch::new();
+/// batch.set(b"user:1:name".to_vec(), b"alice".to_vec());
+/// batch.remove(b"user:1:mail".to_vec());
+/// assert_eq!(batch.len(), 2);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+/// Write of a `WriteBatch`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchOp {
+    Set(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    /// Set the value of `key` when the batch is written.
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> &mut Self {
+        self.ops.push(BatchOp::Set(key, value));
+        self
+    }
+
+    /// Remove `key` when the batch is written.
+    pub fn remove(&mut self, key: Vec<u8>) -> &mut Self {
+        self.ops.push(BatchOp::Remove(key));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Build a batch from the commands of a protocol frame, which can only be sets and removes.
+    pub fn from_commands(commands: Vec<Command>) -> Result<Self> {
+        let mut batch = WriteBatch::new();
+        for command in commands {
+            match command {
+                Command::Set(key, value) => batch.set(key, value),
+                Command::Rm(key) => batch.remove(key),
+                _ => return Err(Error::from(ErrorKind::InvalidCommand)),
+            };
+        }
+        Ok(batch)
+    }
+
+    /// Commands sending the batch over the protocol.
+    pub fn into_commands(self) -> Vec<Command> {
+        self.ops
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Set(key, value) => Command::Set(key, value),
+                BatchOp::Remove(key) => Command::Rm(key),
+            })
+            .collect()
+    }
+}
+
+impl IntoIterator for WriteBatch {
+    type Item = BatchOp;
+    type IntoIter = std::vec::IntoIter<BatchOp>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.into_iter()
+    }
+}
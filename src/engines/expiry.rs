@@ -0,0 +1,62 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default interval between two sweeps of the expired keys.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Handle of the thread that periodically removes the expired keys of an engine.
+///
+/// Dropping it, which happens when the last handle of the engine is dropped, stops the thread
+/// after the sweep in progress.
+pub struct Sweeper {
+    sender: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Sweeper {
+    pub fn spawn<F>(interval: Duration, mut sweep: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        // Nothing is ever sent, the channel only wakes up the thread when it is dropped.
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                sweep();
+            }
+        });
+
+        Sweeper {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Current time in milliseconds since the unix epoch, the unit of the expiry times.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Expiry time of a key expiring after `ttl`.
+pub fn expiry_time(ttl: Duration) -> u64 {
+    now_millis().saturating_add(ttl.as_millis() as u64)
+}
+
+/// Time left before `expires_at`.
+pub fn time_left(expires_at: u64) -> Duration {
+    Duration::from_millis(expires_at.saturating_sub(now_millis()))
+}
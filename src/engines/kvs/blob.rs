@@ -0,0 +1,274 @@
+use super::cipher::{FileCipher, Keyring, CIPHER_HEADER_LEN, TAG_LEN};
+use super::record::read_full;
+use super::segment::{LogFile, SegmentWriter};
+use crate::error::{Error, ErrorKind, Result};
+
+use crc32fast::Hasher;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Extension of the files holding the values stored out of the log.
+pub const BLOB_EXTENSION: &str = "blob";
+
+const BLOB_MAGIC: &[u8; 3] = b"KVB";
+const BLOB_VERSION: u8 = 1;
+// Version of the blob files whose records are encrypted.
+const ENCRYPTED_BLOB_VERSION: u8 = 2;
+
+/// Size of the blob file header: the magic bytes followed by the format version. The header of
+/// an encrypted blob file goes on with the cipher part of the segment headers.
+pub const BLOB_HEADER_LEN: u64 = 4;
+
+// crc32 (4) + key length (4) + value length (4)
+const BLOB_RECORD_HEADER_LEN: usize = 12;
+
+/// Position of a value inside the blob files.
+///
+/// Blob records are encoded as:
+///
+/// ```text
+/// crc32      u32   checksum of every following byte of the record
+/// key_len    u32
+/// value_len  u32
+/// key        [u8; key_len]
+/// value      [u8; value_len]
+/// ```
+///
+/// The key lets the collector find the index entry of each value it reads back. In an
+/// encrypted blob file the key and value are encrypted together like the records of the log,
+/// and `value_len` includes the authentication tag.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlobPointer {
+    pub file: u64,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Bytes written to a blob file and bytes of it no longer pointed by the index.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlobStats {
+    pub size: u64,
+    pub dead: u64,
+}
+
+impl BlobStats {
+    /// Whether enough of the file is dead for the collector to rewrite it.
+    pub fn is_garbage(&self, ratio: f64) -> bool {
+        self.dead as f64 >= ratio * self.size as f64
+    }
+}
+
+/// Path of the blob file `id` inside `dir`.
+pub fn blob_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.{}", id, BLOB_EXTENSION))
+}
+
+/// Sorted list of the ids of the blob files stored in `dir`.
+pub fn blob_ids(dir: &Path) -> Result<Vec<u64>> {
+    let mut ids: Vec<u64> = fs::read_dir(dir)
+        .map_err(|_err| Error::from(ErrorKind::FileError))?
+        .flat_map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension() == Some(BLOB_EXTENSION.as_ref()))
+        .flat_map(|path| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::parse::<u64>)
+        })
+        .flatten()
+        .collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Size in bytes of the records of the blob file `id`, without its header.
+pub fn blob_size(dir: &Path, id: u64, file: &LogFile) -> Result<u64> {
+    let header_len = match file.cipher {
+        Some(_) => BLOB_HEADER_LEN + CIPHER_HEADER_LEN as u64,
+        None => BLOB_HEADER_LEN,
+    };
+    fs::metadata(blob_path(dir, id))
+        .map(|metadata| metadata.len().saturating_sub(header_len))
+        .map_err(|_err| Error::from(ErrorKind::FileError))
+}
+
+/// Create the blob file `id` and open it for appending, its records being encrypted with
+/// `cipher`, if any.
+pub fn create_blob(dir: &Path, id: u64, cipher: Option<FileCipher>) -> Result<SegmentWriter> {
+    let mut header = BLOB_MAGIC.to_vec();
+    match &cipher {
+        Some(cipher) => {
+            header.push(ENCRYPTED_BLOB_VERSION);
+            header.extend_from_slice(&cipher.header());
+        }
+        None => header.push(BLOB_VERSION),
+    }
+    SegmentWriter::open_with_header(&blob_path(dir, id), id, &header, cipher)
+}
+
+/// Open the blob file `id` for positional reads, finding the cipher of its records in
+/// `keyring`.
+pub fn open_blob(dir: &Path, id: u64, keyring: &Keyring) -> Result<Arc<LogFile>> {
+    let mut file =
+        File::open(blob_path(dir, id)).map_err(|_err| Error::from(ErrorKind::FileError))?;
+    let (_header_len, cipher) = read_blob_header(&mut file, keyring)?;
+    Ok(Arc::new(LogFile::new(file, cipher)))
+}
+
+/// Encode the blob record of `value`, written at `position` of a blob file encrypted with
+/// `cipher`, if any.
+pub fn encode_blob(
+    key: &[u8],
+    value: &[u8],
+    cipher: Option<&FileCipher>,
+    position: u64,
+) -> Vec<u8> {
+    let tag_len = if cipher.is_some() { TAG_LEN } else { 0 };
+    let mut bytes = Vec::with_capacity(BLOB_RECORD_HEADER_LEN + key.len() + value.len() + tag_len);
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&((value.len() + tag_len) as u32).to_le_bytes());
+    let mut body = Vec::with_capacity(key.len() + value.len() + tag_len);
+    body.extend_from_slice(key);
+    body.extend_from_slice(value);
+    if let Some(cipher) = cipher {
+        cipher.seal(position, &bytes[4..], &mut body);
+    }
+    bytes.extend_from_slice(&body);
+    let crc = checksum(&bytes[4..]);
+    bytes[..4].copy_from_slice(&crc.to_le_bytes());
+    bytes
+}
+
+/// Read the value pointed by `pointer` from its blob file.
+pub fn read_blob(file: &LogFile, pointer: &BlobPointer) -> Result<Vec<u8>> {
+    let bytes = file.read_at(pointer.offset, pointer.len)?.into_owned();
+    decode_blob(bytes, file.cipher.as_ref(), pointer.offset).map(|(_key, value)| value)
+}
+
+// Check the header of a blob file, returning its length and the cipher of the file when it is
+// encrypted.
+fn read_blob_header<R: Read>(
+    reader: &mut R,
+    keyring: &Keyring,
+) -> Result<(u64, Option<FileCipher>)> {
+    let mut header = [0; BLOB_HEADER_LEN as usize];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_err| Error::from(ErrorKind::TornRecord))?;
+    if &header[..3] != BLOB_MAGIC {
+        return Err(Error::from(ErrorKind::CorruptedRecord));
+    }
+    match header[3] {
+        BLOB_VERSION => Ok((BLOB_HEADER_LEN, None)),
+        ENCRYPTED_BLOB_VERSION => {
+            let mut cipher = [0; CIPHER_HEADER_LEN];
+            reader
+                .read_exact(&mut cipher)
+                .map_err(|_err| Error::from(ErrorKind::TornRecord))?;
+            let header_len = BLOB_HEADER_LEN + CIPHER_HEADER_LEN as u64;
+            Ok((header_len, Some(keyring.cipher(&cipher)?)))
+        }
+        version => Err(Error::from(ErrorKind::InvalidFormatVersion(version))),
+    }
+}
+
+// Key and value of a blob record with its position.
+type BlobRecord = (Vec<u8>, Vec<u8>, BlobPointer);
+
+/// Reader going through the records of a blob file in order, used by the collector.
+pub struct BlobReader<R> {
+    id: u64,
+    reader: R,
+    offset: u64,
+    cipher: Option<FileCipher>,
+}
+
+impl<R: Read> BlobReader<R> {
+    /// Check the header of the blob file `id` read by `reader`, finding the cipher of its
+    /// records in `keyring`.
+    pub fn new(id: u64, mut reader: R, keyring: &Keyring) -> Result<Self> {
+        let (offset, cipher) = read_blob_header(&mut reader, keyring)?;
+        Ok(BlobReader {
+            id,
+            reader,
+            offset,
+            cipher,
+        })
+    }
+
+    /// Read the next record with its key, value and position.
+    ///
+    /// `Ok(None)` is returned at the end of the file. A damaged record, which is what a crash
+    /// in the middle of an append leaves at the end of the file, fails with `TornRecord` or
+    /// `CorruptedRecord`.
+    pub fn next_record(&mut self) -> Result<Option<BlobRecord>> {
+        let mut bytes = vec![0; BLOB_RECORD_HEADER_LEN];
+        match read_full(&mut self.reader, &mut bytes)? {
+            0 => return Ok(None),
+            BLOB_RECORD_HEADER_LEN => {}
+            _ => return Err(Error::from(ErrorKind::TornRecord)),
+        }
+        let body_len = (le_u32(&bytes[4..8]) as u64) + (le_u32(&bytes[8..12]) as u64);
+        let read = self
+            .reader
+            .by_ref()
+            .take(body_len)
+            .read_to_end(&mut bytes)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        if read as u64 != body_len {
+            return Err(Error::from(ErrorKind::TornRecord));
+        }
+
+        let pointer = BlobPointer {
+            file: self.id,
+            offset: self.offset,
+            len: bytes.len() as u64,
+        };
+        self.offset += pointer.len;
+        let (key, value) = decode_blob(bytes, self.cipher.as_ref(), pointer.offset)?;
+        Ok(Some((key, value, pointer)))
+    }
+}
+
+// Split a blob record read at `position` into its key and value, checking its checksum and
+// decrypting it with `cipher`, if any.
+fn decode_blob(
+    mut bytes: Vec<u8>,
+    cipher: Option<&FileCipher>,
+    position: u64,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    if bytes.len() < BLOB_RECORD_HEADER_LEN {
+        return Err(Error::from(ErrorKind::TornRecord));
+    }
+    let key_len = le_u32(&bytes[4..8]) as usize;
+    let value_len = le_u32(&bytes[8..12]) as usize;
+    if bytes.len() != BLOB_RECORD_HEADER_LEN + key_len + value_len {
+        return Err(Error::from(ErrorKind::TornRecord));
+    }
+    if le_u32(&bytes[..4]) != checksum(&bytes[4..]) {
+        return Err(Error::from(ErrorKind::CorruptedRecord));
+    }
+    let mut body = bytes.split_off(BLOB_RECORD_HEADER_LEN);
+    if let Some(cipher) = cipher {
+        if value_len < TAG_LEN {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        cipher.open(position, &bytes[4..], &mut body)?;
+    }
+    let value = body.split_off(key_len);
+    Ok((body, value))
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(bytes);
+    hasher.finalize()
+}
+
+#[inline]
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Counters of the value cache of a `KvStore`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// Gets answered from the cache.
+    pub hits: u64,
+    /// Gets that read their value from the disk.
+    pub misses: u64,
+    /// Bytes of the keys and values held by the cache.
+    pub size: u64,
+}
+
+/// Cache of the values read by `KvStore::get`, bounded in bytes, evicting the least recently
+/// used values first.
+///
+/// A value is cached with the version of its key and only returned while the index holds the
+/// same version. Sets and removes give the key a new version, so a value read before a write
+/// and cached after it is never returned. The compaction moves records without changing their
+/// version nor their value, so the cached values stay valid.
+pub struct ValueCache {
+    capacity: u64,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ValueCache {
+    pub fn new(capacity: u64) -> Self {
+        ValueCache {
+            capacity,
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Value of `key` cached at `version`, if any, counting a hit or a miss.
+    pub fn get(&self, key: &[u8], version: u64) -> Option<Vec<u8>> {
+        let mut lru = self.lru.lock().unwrap();
+        let value = match lru.entries.get(key) {
+            Some(cached) if cached.version == version => {
+                let value = cached.value.clone();
+                lru.touch(key);
+                Some(value)
+            }
+            Some(_stale) => {
+                lru.remove(key);
+                None
+            }
+            None => None,
+        };
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    /// Cache the value of `key` read at `version`, evicting the least recently used values
+    /// to make room for it. A value larger than the whole cache is not cached.
+    pub fn insert(&self, key: Vec<u8>, version: u64, value: Vec<u8>) {
+        let size = (key.len() + value.len()) as u64;
+        if size > self.capacity {
+            return;
+        }
+        let mut lru = self.lru.lock().unwrap();
+        lru.remove(&key);
+        while lru.size + size > self.capacity {
+            let oldest = match lru.recency.keys().next() {
+                Some(&tick) => lru.recency[&tick].clone(),
+                None => break,
+            };
+            lru.remove(&oldest);
+        }
+        lru.tick += 1;
+        let tick = lru.tick;
+        lru.recency.insert(tick, key.clone());
+        lru.size += size;
+        lru.entries.insert(
+            key,
+            Cached {
+                version,
+                value,
+                tick,
+            },
+        );
+    }
+
+    /// Drop the value of `key`, once it is written or removed.
+    pub fn invalidate(&self, key: &[u8]) {
+        self.lru.lock().unwrap().remove(key);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            size: self.lru.lock().unwrap().size,
+        }
+    }
+}
+
+struct Cached {
+    version: u64,
+    value: Vec<u8>,
+    // Tick of the last use of the value, its key in `Lru::recency`
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<Vec<u8>, Cached>,
+    // Keys ordered by their last use, the least recently used first
+    recency: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+    size: u64,
+}
+
+impl Lru {
+    // Mark the value of `key` as the most recently used.
+    fn touch(&mut self, key: &[u8]) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(cached) = self.entries.get_mut(key) {
+            let key = self
+                .recency
+                .remove(&cached.tick)
+                .unwrap_or_else(|| key.to_vec());
+            cached.tick = tick;
+            self.recency.insert(tick, key);
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(cached) = self.entries.remove(key) {
+            self.recency.remove(&cached.tick);
+            self.size -= (key.len() + cached.value.len()) as u64;
+        }
+    }
+}
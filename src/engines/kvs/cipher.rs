@@ -0,0 +1,163 @@
+use crate::error::{Error, ErrorKind, Result};
+
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{Tag, XChaCha20Poly1305, XNonce};
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+
+/// Size of an encryption key in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// Size of the random salt written in the header of every encrypted file.
+pub const SALT_LEN: usize = 16;
+
+/// Bytes added to every encrypted payload by its authentication tag.
+pub const TAG_LEN: usize = 16;
+
+/// Size of the cipher part of a file header: the key id, the salt and the tag checking the key.
+pub const CIPHER_HEADER_LEN: usize = 4 + SALT_LEN + TAG_LEN;
+
+// Position of the empty payload whose tag checks the key of a file, past any real payload.
+const KEY_CHECK_POSITION: u64 = u64::MAX;
+
+/// Key used to encrypt the files of a `KvStore`, identified by the id written in the header of
+/// every file it encrypts.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: u32,
+    key: [u8; KEY_LEN],
+}
+
+impl EncryptionKey {
+    pub fn new(id: u32, key: [u8; KEY_LEN]) -> Self {
+        EncryptionKey { id, key }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+// The key itself is never printed.
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// Keys known to a store. New files are encrypted with the key of the highest id, the other
+/// keys are only used to read the files written before it was added.
+#[derive(Clone, Debug, Default)]
+pub struct Keyring {
+    keys: BTreeMap<u32, EncryptionKey>,
+}
+
+impl Keyring {
+    pub fn add(&mut self, key: EncryptionKey) {
+        self.keys.insert(key.id, key);
+    }
+
+    /// Id of the key encrypting the new files, `None` when the store is not encrypted.
+    pub fn newest_id(&self) -> Option<u32> {
+        self.keys.keys().next_back().cloned()
+    }
+
+    /// Cipher of a new file, with a fresh salt, `None` when the store is not encrypted.
+    pub fn new_cipher(&self) -> Result<Option<FileCipher>> {
+        let key = match self.keys.values().next_back() {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let mut salt = [0; SALT_LEN];
+        getrandom::getrandom(&mut salt).map_err(|_err| Error::from(ErrorKind::UnknownError))?;
+        Ok(Some(FileCipher::new(key, salt)))
+    }
+
+    /// Cipher of an existing file, from the cipher part of its header.
+    ///
+    /// A key missing from the keyring, or not matching the one the file was written with,
+    /// fails with `UnknownEncryptionKey` before any payload is read, so a wrong key is never
+    /// mistaken for a damaged file.
+    pub fn cipher(&self, header: &[u8]) -> Result<FileCipher> {
+        let key_id = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let unknown = || Error::from(ErrorKind::UnknownEncryptionKey(key_id));
+        let key = self.keys.get(&key_id).ok_or_else(unknown)?;
+        let salt = header[4..4 + SALT_LEN].try_into().unwrap();
+        let cipher = FileCipher::new(key, salt);
+        let mut check = header[4 + SALT_LEN..].to_vec();
+        cipher
+            .open(KEY_CHECK_POSITION, &header[..4 + SALT_LEN], &mut check)
+            .map_err(|_err| unknown())?;
+        Ok(cipher)
+    }
+}
+
+/// Authenticated cipher of the payloads of one file.
+///
+/// Payloads are encrypted with XChaCha20-Poly1305. The 24 bytes nonce of a payload is the
+/// random salt of its file followed by the position of the payload inside the file, which
+/// never repeats as long as a file is only appended to by the process that created it. The
+/// salt makes the nonces of different files distinct.
+#[derive(Clone)]
+pub struct FileCipher {
+    key_id: u32,
+    salt: [u8; SALT_LEN],
+    aead: XChaCha20Poly1305,
+}
+
+impl FileCipher {
+    fn new(key: &EncryptionKey, salt: [u8; SALT_LEN]) -> Self {
+        FileCipher {
+            key_id: key.id,
+            salt,
+            aead: XChaCha20Poly1305::new(&key.key.into()),
+        }
+    }
+
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+
+    /// Cipher part of the header of the file.
+    pub fn header(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(CIPHER_HEADER_LEN);
+        header.extend_from_slice(&self.key_id.to_le_bytes());
+        header.extend_from_slice(&self.salt);
+        let mut check = Vec::with_capacity(TAG_LEN);
+        self.seal(KEY_CHECK_POSITION, &header, &mut check);
+        header.extend_from_slice(&check);
+        header
+    }
+
+    /// Encrypt `payload` written at `position` in place and append its tag, which also
+    /// authenticates `aad`.
+    pub fn seal(&self, position: u64, aad: &[u8], payload: &mut Vec<u8>) {
+        let tag = self
+            .aead
+            .encrypt_in_place_detached(&self.nonce(position), aad, payload)
+            .expect("payload too large to encrypt");
+        payload.extend_from_slice(&tag);
+    }
+
+    /// Check the tag at the end of `payload` read at `position`, then decrypt it in place
+    /// without its tag. A payload that does not match fails with `CorruptedRecord`.
+    pub fn open(&self, position: u64, aad: &[u8], payload: &mut Vec<u8>) -> Result<()> {
+        if payload.len() < TAG_LEN {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        let tag = payload.split_off(payload.len() - TAG_LEN);
+        self.aead
+            .decrypt_in_place_detached(&self.nonce(position), aad, payload, Tag::from_slice(&tag))
+            .map_err(|_err| Error::from(ErrorKind::CorruptedRecord))
+    }
+
+    fn nonce(&self, position: u64) -> XNonce {
+        let mut nonce = XNonce::default();
+        nonce[..SALT_LEN].copy_from_slice(&self.salt);
+        nonce[SALT_LEN..].copy_from_slice(&position.to_le_bytes());
+        nonce
+    }
+}
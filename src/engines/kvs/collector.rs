@@ -0,0 +1,171 @@
+use super::blob::{blob_path, encode_blob, BlobPointer, BlobReader};
+use super::record::Record;
+use super::segment::SegmentWriter;
+use super::{Inner, LogEntry};
+use crate::engines::expiry::now_millis;
+use crate::error::{Error, ErrorKind, Result};
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Handle of the thread that collects the blob files of a `KvStore`.
+///
+/// The collector runs apart from the compaction: it moves the live values of the blob files
+/// taken by dead values to new blob files, writes records pointing to their new position
+/// and deletes the old files. Dropping it stops the thread after waiting for the collection
+/// in progress.
+pub struct BlobCollector {
+    sender: Mutex<Option<Sender<()>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BlobCollector {
+    pub fn spawn(inner: Arc<Inner>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            while receiver.recv().is_ok() {
+                while receiver.try_recv().is_ok() {}
+                if let Err(err) = collect(&inner) {
+                    error!(inner.options.logger, "Blob collection failed: {}", err);
+                }
+            }
+        });
+
+        BlobCollector {
+            sender: Mutex::new(Some(sender)),
+            handle: Some(handle),
+        }
+    }
+
+    /// Ask the thread to collect the blob files reaching the garbage ratio.
+    pub fn trigger(&self) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            let _ = sender.send(());
+        }
+    }
+}
+
+impl Drop for BlobCollector {
+    fn drop(&mut self) {
+        self.sender.lock().unwrap().take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Collect every blob file reaching the garbage ratio or encrypted with an older key, except
+// the one taking writes. The live values of all of them are moved together to new files.
+fn collect(inner: &Inner) -> Result<()> {
+    let active = inner
+        .blob_writer
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|writer| writer.id);
+    let newest_key = inner.options.keyring.newest_id();
+    let blobs = inner.blobs.read().unwrap();
+    let garbage: Vec<u64> = inner
+        .blob_stats
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(&id, stats)| {
+            let stale = blobs
+                .get(&id)
+                .is_some_and(|file| file.key_id() != newest_key);
+            Some(id) != active && (stale || stats.is_garbage(inner.options.blob_garbage_ratio))
+        })
+        .map(|(&id, _stats)| id)
+        .collect();
+    drop(blobs);
+
+    let mut output = None;
+    for id in garbage {
+        collect_file(inner, id, &mut output)?;
+    }
+    if let Some(mut writer) = output {
+        inner.seal(&mut writer)?;
+    }
+    Ok(())
+}
+
+// Move the live values of the blob file `id` to `output` and delete the file.
+//
+// A value is live while the index points to it. Each moved key is written again with the
+// version it was read at, so a key written in the meantime keeps its new value and the
+// moved copy is simply dead. The file is only deleted once the new records are written,
+// and synced unless syncing is disabled, so a crash never leaves a record pointing to a
+// deleted file.
+fn collect_file(inner: &Inner, id: u64, output: &mut Option<SegmentWriter>) -> Result<()> {
+    let file =
+        File::open(blob_path(&inner.path, id)).map_err(|_err| Error::from(ErrorKind::FileError))?;
+    let mut reader = BlobReader::new(
+        id,
+        BufReader::with_capacity(inner.options.read_buffer_size, file),
+        &inner.options.keyring,
+    )?;
+
+    let now = now_millis();
+    let mut entries = Vec::new();
+    let mut moved = Vec::new();
+    loop {
+        let (key, value, pointer) = match reader.next_record() {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            // Nothing after a torn append was ever pointed by the index.
+            Err(ref err) if err.kind() == ErrorKind::TornRecord => break,
+            Err(err) => return Err(err),
+        };
+        let current = match inner.index.read().unwrap().get(&key) {
+            Some(&entry) if entry.blob == Some(pointer) && !entry.is_expired(now) => entry,
+            _ => continue,
+        };
+
+        let writer = match output {
+            Some(writer) => writer,
+            None => output.insert(inner.create_blob()?),
+        };
+        let bytes = encode_blob(&key, &value, writer.cipher.as_ref(), writer.offset);
+        let new_pointer = BlobPointer {
+            file: writer.id,
+            offset: writer.append(&bytes)?,
+            len: bytes.len() as u64,
+        };
+        if let Some(stats) = inner.blob_stats.lock().unwrap().get_mut(&writer.id) {
+            stats.size += new_pointer.len;
+        }
+        let mut entry = LogEntry::new(vec![Record::set_blob(
+            key.clone(),
+            new_pointer,
+            current.expires_at,
+        )]);
+        entry.reads.insert(key, Some(current.version));
+        entries.push(entry);
+        moved.push(new_pointer);
+
+        if writer.offset >= inner.options.segment_max_size {
+            inner.seal(writer)?;
+            *output = None;
+        }
+    }
+
+    if let Some(writer) = output.as_mut() {
+        inner.seal(writer)?;
+    }
+    let written = inner.write(entries, false)?;
+    inner.seal(&mut inner.writer.lock().unwrap())?;
+    // The copy of a value written again in the meantime is dead right away.
+    for (&pointer, written) in moved.iter().zip(written) {
+        if !written {
+            inner.release_blob(Some(pointer));
+        }
+    }
+
+    inner.blobs.write().unwrap().remove(&id);
+    inner.blob_stats.lock().unwrap().remove(&id);
+    fs::remove_file(blob_path(&inner.path, id)).map_err(|_err| Error::from(ErrorKind::FileError))
+}
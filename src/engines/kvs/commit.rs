@@ -0,0 +1,97 @@
+use super::{Inner, LogEntry};
+use crate::error::{Error, ErrorKind, Result};
+
+use std::mem;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+
+/// Queue of the entries waiting to be synced to the log, used by `SyncPolicy::Always`.
+///
+/// The first writer to find the queue idle becomes the leader: it writes every queued entry
+/// and syncs them with a single fsync, while the writers arriving in the meantime queue their
+/// entries for the next batch. Once done the leader acknowledges the whole batch and hands
+/// its role to the oldest queued writer, if any.
+pub struct CommitQueue {
+    state: Mutex<QueueState>,
+}
+
+struct QueueState {
+    pending: Vec<Pending>,
+    leading: bool,
+}
+
+struct Pending {
+    entry: LogEntry,
+    done: Sender<Commit>,
+}
+
+enum Commit {
+    Done(std::result::Result<(), ErrorKind>),
+    Lead,
+}
+
+impl CommitQueue {
+    pub fn new() -> Self {
+        CommitQueue {
+            state: Mutex::new(QueueState {
+                pending: Vec::new(),
+                leading: false,
+            }),
+        }
+    }
+
+    /// Write `entry` to the log of `inner` and sync it, together with the entries of the
+    /// concurrent writers.
+    pub fn commit(&self, inner: &Inner, entry: LogEntry) -> Result<()> {
+        let (done, receiver) = mpsc::channel();
+        let lead = {
+            let mut state = self.state.lock().unwrap();
+            state.pending.push(Pending { entry, done });
+            !mem::replace(&mut state.leading, true)
+        };
+        if !lead {
+            match receiver.recv().unwrap() {
+                Commit::Done(result) => return result.map_err(Error::from),
+                Commit::Lead => {}
+            }
+        }
+
+        let batch = mem::take(&mut self.state.lock().unwrap().pending);
+        let (entries, waiters): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|pending| (pending.entry, pending.done))
+            .unzip();
+        match inner.write(entries, true) {
+            Ok(written) => {
+                for (waiter, written) in waiters.into_iter().zip(written) {
+                    let result = if written {
+                        Ok(())
+                    } else {
+                        Err(ErrorKind::TransactionConflict)
+                    };
+                    let _ = waiter.send(Commit::Done(result));
+                }
+            }
+            Err(err) => {
+                for waiter in waiters {
+                    let _ = waiter.send(Commit::Done(Err(err.kind())));
+                }
+            }
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            match state.pending.first() {
+                Some(next) => {
+                    let _ = next.done.send(Commit::Lead);
+                }
+                None => state.leading = false,
+            }
+        }
+
+        match receiver.recv().unwrap() {
+            Commit::Done(result) => result.map_err(Error::from),
+            Commit::Lead => unreachable!("the leader is never asked to lead again"),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use super::cipher::FileCipher;
+use super::hint::{remove_hint, write_hint, HintEntry};
+use super::record::{max_resealed_size, reseal_record};
+use super::segment::{
+    open_segment, segment_path, segment_size, temporary_path, SegmentWriter, COMPACTION_EXTENSION,
+};
+use super::{read_pointer, IndexEntry, Inner, LogPointer};
+use crate::engines::expiry::now_millis;
+use crate::error::{Error, ErrorKind, Result};
+
+use std::borrow::Cow;
+use std::fs;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Handle of the thread that compacts the log of a `KvStore`.
+///
+/// Dropping it, which happens when the last handle of the store is dropped, stops the thread
+/// after waiting for the compaction in progress.
+pub struct Compactor {
+    sender: Mutex<Option<Sender<()>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Compactor {
+    pub fn spawn(inner: Arc<Inner>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            while receiver.recv().is_ok() {
+                // Writes keep triggering the compaction while it runs, only one more run is needed.
+                while receiver.try_recv().is_ok() {}
+                if let Err(err) = compact(&inner) {
+                    error!(inner.options.logger, "Log compaction failed: {}", err);
+                }
+            }
+        });
+
+        Compactor {
+            sender: Mutex::new(Some(sender)),
+            handle: Some(handle),
+        }
+    }
+
+    /// Ask the thread to compact the log.
+    pub fn trigger(&self) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            let _ = sender.send(());
+        }
+    }
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        self.sender.lock().unwrap().take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Merge every sealed segment in new segments that only contain live records.
+//
+// The active segment is sealed first and writes continue in a new segment whose id is
+// greater than the ids reserved for the merged output, so replaying the segments in order
+// always gives priority to the most recent records. Should the merged output need more ids
+// than reserved, the last reserved segment grows past the maximum size instead. The writer is only locked to start that
+// new segment, and the merged pointers are swapped in the index at the end in one step,
+// skipping the keys written or removed in the meantime.
+//
+// The values stored in blob files are not copied, only the records pointing to them. The
+// merged segments are encrypted with the newest key, so the compaction also rewrites the
+// records encrypted with an older key.
+fn compact(inner: &Inner) -> Result<()> {
+    let (sealed, sealed_size, first_merge_id, active_id) = {
+        let mut writer = inner.writer.lock().unwrap();
+        if let Some(blob_writer) = inner.blob_writer.lock().unwrap().as_mut() {
+            inner.seal(blob_writer)?;
+        }
+        inner.seal(&mut writer)?;
+
+        let sealed: Vec<u64> = inner.readers.read().unwrap().keys().cloned().collect();
+        let mut sealed_size = 0;
+        for &id in &sealed {
+            sealed_size += segment_size(&inner.path, id)?;
+        }
+
+        // The merged output never holds more than the sealed data, plus the tags added when the
+        // records are encrypted with a new key, so these ids are enough.
+        let merged_size = match inner.options.keyring.newest_id() {
+            Some(_) => max_resealed_size(sealed_size),
+            None => sealed_size,
+        };
+        let first_merge_id = writer.id + 1;
+        let active_id = first_merge_id + merged_size / inner.options.segment_max_size + 1;
+        inner.map_sealed(writer.id)?;
+        *writer = SegmentWriter::open(&inner.path, active_id, inner.options.keyring.new_cipher()?)?;
+        inner.readers.write().unwrap().insert(
+            active_id,
+            open_segment(&inner.path, active_id, &inner.options.keyring)?,
+        );
+        inner.uncompacted.store(0, Ordering::SeqCst);
+        inner.dead_bytes.store(0, Ordering::SeqCst);
+
+        (sealed, sealed_size, first_merge_id, active_id)
+    };
+
+    // Expired keys are not copied, they are dropped from the index along with the new pointers.
+    let now = now_millis();
+    let (expired, live): (Vec<_>, Vec<_>) = inner
+        .index
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(_key, entry)| entry.pointer.segment < first_merge_id)
+        .map(|(key, &entry)| (key.to_owned(), entry))
+        .partition(|(_key, entry)| entry.is_expired(now));
+
+    let mut moved = Vec::with_capacity(live.len());
+    let mut merged_size = 0;
+    let mut merged = MergedSegment::open(inner, first_merge_id)?;
+    for (key, entry) in live {
+        let pointer = entry.pointer;
+        let file = inner.readers.read().unwrap().get(&pointer.segment).cloned();
+        let file = file.ok_or_else(|| Error::from(ErrorKind::FileError))?;
+        let mut bytes = read_pointer(&file, &pointer)?;
+        // Records are copied as they are, unless their segment is encrypted differently.
+        let cipher = merged.writer.cipher.as_ref();
+        if file.cipher.is_some() || cipher.is_some() {
+            let from = (file.cipher.as_ref(), pointer.offset);
+            bytes = Cow::Owned(reseal_record(&bytes, from, (cipher, merged.writer.offset))?);
+        }
+        let new_pointer = merged.append(&key, &entry, &bytes)?;
+        moved.push((key, pointer, new_pointer));
+        if merged.writer.offset >= inner.options.segment_max_size
+            && merged.writer.id + 1 < active_id
+        {
+            let id = merged.writer.id;
+            merged_size += merged.finish(inner)?;
+            merged = MergedSegment::open(inner, id + 1)?;
+        }
+    }
+    merged_size += merged.finish(inner)?;
+
+    {
+        let mut index = inner.index.write().unwrap();
+        for (key, pointer, new_pointer) in moved {
+            if let Some(current) = index.get_mut(&key) {
+                if current.pointer == pointer {
+                    current.pointer = new_pointer;
+                }
+            }
+        }
+        for (key, entry) in expired {
+            if index.get(&key) == Some(&entry) {
+                index.remove(&key);
+                inner.release_blob(entry.blob);
+            }
+        }
+    }
+
+    // Oldest segments go first: a crash in the middle never leaves a removal record
+    // deleted while an older value of the same key is still on disk.
+    for id in sealed {
+        inner.readers.write().unwrap().remove(&id);
+        remove_hint(&inner.path, id)?;
+        fs::remove_file(segment_path(&inner.path, id))
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+    }
+    inner.total_bytes.fetch_add(merged_size, Ordering::Relaxed);
+    inner.total_bytes.fetch_sub(sealed_size, Ordering::Relaxed);
+
+    Ok(())
+}
+
+// Segment written by the compaction under a temporary name, so a crash can never leave a
+// partially merged segment in the log.
+struct MergedSegment {
+    writer: SegmentWriter,
+    hints: Vec<HintEntry>,
+}
+
+impl MergedSegment {
+    // Start the merged segment `id`, encrypted with the newest key, if any.
+    fn open(inner: &Inner, id: u64) -> Result<Self> {
+        let path = temporary_path(&inner.path, id, COMPACTION_EXTENSION);
+        let cipher = inner.options.keyring.new_cipher()?;
+        Ok(MergedSegment {
+            writer: SegmentWriter::open_path(&path, id, cipher)?,
+            hints: Vec::new(),
+        })
+    }
+
+    fn append(&mut self, key: &[u8], entry: &IndexEntry, bytes: &[u8]) -> Result<LogPointer> {
+        let offset = self.writer.append(bytes)?;
+        let len = bytes.len() as u64;
+        self.hints.push(HintEntry {
+            key: key.to_owned(),
+            offset,
+            len,
+            expires_at: entry.expires_at,
+            blob: entry.blob,
+        });
+        Ok(LogPointer {
+            segment: self.writer.id,
+            offset,
+            len,
+        })
+    }
+
+    // Move the segment to its final path, write its hint file and make it readable, returning
+    // its size. Without the hint file the segment is simply replayed, so a crash between both
+    // steps is harmless.
+    fn finish(mut self, inner: &Inner) -> Result<u64> {
+        inner.seal(&mut self.writer)?;
+        let id = self.writer.id;
+        fs::rename(
+            temporary_path(&inner.path, id, COMPACTION_EXTENSION),
+            segment_path(&inner.path, id),
+        )
+        .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        let cipher: Option<&FileCipher> = self.writer.cipher.as_ref();
+        write_hint(&inner.path, id, self.writer.offset, &self.hints, cipher)?;
+        inner
+            .readers
+            .write()
+            .unwrap()
+            .insert(id, open_segment(&inner.path, id, &inner.options.keyring)?);
+        inner.map_sealed(id)?;
+        Ok(self.writer.offset)
+    }
+}
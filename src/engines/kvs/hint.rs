@@ -0,0 +1,176 @@
+use super::blob::BlobPointer;
+use super::cipher::FileCipher;
+use super::segment::{segment_path, COMPACTION_EXTENSION};
+use crate::error::{Error, ErrorKind, Result};
+
+use crc32fast::Hasher;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extension of the hint file written next to every merged segment.
+pub const HINT_EXTENSION: &str = "hint";
+
+const HINT_MAGIC: &[u8; 3] = b"KVH";
+// Version 2 added the expiry time of each key, version 3 the blob holding its value.
+const HINT_VERSION: u8 = 3;
+
+// magic (3) + version (1) + segment size (8)
+const HINT_HEADER_LEN: usize = 12;
+
+// key length (4) + offset (8) + record length (8) + expiry time (8) + blob file (8)
+// + blob offset (8) + blob length (8)
+const HINT_ENTRY_HEADER_LEN: usize = 52;
+
+/// Location of the live record of a key inside a merged segment.
+pub struct HintEntry {
+    pub key: Vec<u8>,
+    pub offset: u64,
+    pub len: u64,
+    pub expires_at: Option<u64>,
+    pub blob: Option<BlobPointer>,
+}
+
+/// Path of the hint file of the segment `id`.
+pub fn hint_path(dir: &Path, id: u64) -> PathBuf {
+    segment_path(dir, id).with_extension(HINT_EXTENSION)
+}
+
+/// Write the hint file of the segment `id`, whose final size is `segment_size`.
+///
+/// The file is written under a temporary name and renamed once complete. It is laid out as
+/// a header with the size of the segment, one entry per record and a crc32 of everything
+/// before it. The entries of the hint of an encrypted segment are encrypted with the cipher
+/// of the segment at position 0, which no record of the segment uses.
+pub fn write_hint(
+    dir: &Path,
+    id: u64,
+    segment_size: u64,
+    entries: &[HintEntry],
+    cipher: Option<&FileCipher>,
+) -> Result<()> {
+    let temporary = dir.join(format!(
+        "{}.{}.{}",
+        id, HINT_EXTENSION, COMPACTION_EXTENSION
+    ));
+    let mut header = Vec::with_capacity(HINT_HEADER_LEN);
+    header.extend_from_slice(HINT_MAGIC);
+    header.push(HINT_VERSION);
+    header.extend_from_slice(&segment_size.to_le_bytes());
+
+    let mut content = Vec::new();
+    for entry in entries {
+        content.extend_from_slice(&(entry.key.len() as u32).to_le_bytes());
+        content.extend_from_slice(&entry.offset.to_le_bytes());
+        content.extend_from_slice(&entry.len.to_le_bytes());
+        // No key expires at the unix epoch, so 0 stands for a key without expiry.
+        content.extend_from_slice(&entry.expires_at.unwrap_or(0).to_le_bytes());
+        // Blob files are numbered from 1, so 0 stands for a value stored in the record.
+        let blob = entry.blob.unwrap_or(BlobPointer {
+            file: 0,
+            offset: 0,
+            len: 0,
+        });
+        content.extend_from_slice(&blob.file.to_le_bytes());
+        content.extend_from_slice(&blob.offset.to_le_bytes());
+        content.extend_from_slice(&blob.len.to_le_bytes());
+        content.extend_from_slice(&entry.key);
+    }
+    if let Some(cipher) = cipher {
+        cipher.seal(0, &header, &mut content);
+    }
+
+    let mut bytes = header;
+    bytes.append(&mut content);
+    let mut hasher = Hasher::new();
+    hasher.update(&bytes);
+    bytes.extend_from_slice(&hasher.finalize().to_le_bytes());
+    fs::write(&temporary, bytes).map_err(|_err| Error::from(ErrorKind::FileError))?;
+
+    fs::rename(temporary, hint_path(dir, id)).map_err(|_err| Error::from(ErrorKind::FileError))
+}
+
+/// Read the hint file of the segment `id`.
+///
+/// `Ok(None)` is returned when there is no hint file or when it does not match the segment,
+/// in which case the segment itself has to be replayed. `cipher` is the cipher of the segment.
+pub fn read_hint(
+    dir: &Path,
+    id: u64,
+    segment_size: u64,
+    cipher: Option<&FileCipher>,
+) -> Result<Option<Vec<HintEntry>>> {
+    let path = hint_path(dir, id);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let bytes = fs::read(path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+    if bytes.len() < HINT_HEADER_LEN + 4 {
+        return Ok(None);
+    }
+
+    let (content, crc) = bytes.split_at(bytes.len() - 4);
+    let mut hasher = Hasher::new();
+    hasher.update(content);
+    if hasher.finalize() != le_u32(crc)
+        || &content[..3] != HINT_MAGIC
+        || content[3] != HINT_VERSION
+        || le_u64(&content[4..HINT_HEADER_LEN]) != segment_size
+    {
+        return Ok(None);
+    }
+    let (header, content) = content.split_at(HINT_HEADER_LEN);
+    let mut content = content.to_vec();
+    if let Some(cipher) = cipher {
+        if cipher.open(0, header, &mut content).is_err() {
+            return Ok(None);
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut position = 0;
+    while position < content.len() {
+        if content.len() - position < HINT_ENTRY_HEADER_LEN {
+            return Ok(None);
+        }
+        let header = &content[position..position + HINT_ENTRY_HEADER_LEN];
+        let key_len = le_u32(&header[..4]) as usize;
+        let key_start = position + HINT_ENTRY_HEADER_LEN;
+        if content.len() - key_start < key_len {
+            return Ok(None);
+        }
+        entries.push(HintEntry {
+            key: content[key_start..key_start + key_len].to_vec(),
+            offset: le_u64(&header[4..12]),
+            len: le_u64(&header[12..20]),
+            expires_at: Some(le_u64(&header[20..28])).filter(|&expires_at| expires_at != 0),
+            blob: Some(BlobPointer {
+                file: le_u64(&header[28..36]),
+                offset: le_u64(&header[36..44]),
+                len: le_u64(&header[44..52]),
+            })
+            .filter(|blob| blob.file != 0),
+        });
+        position = key_start + key_len;
+    }
+    Ok(Some(entries))
+}
+
+/// Remove the hint file of the segment `id`, if any.
+pub fn remove_hint(dir: &Path, id: u64) -> Result<()> {
+    let path = hint_path(dir, id);
+    if path.is_file() {
+        fs::remove_file(path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+    }
+    Ok(())
+}
+
+#[inline]
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[inline]
+fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
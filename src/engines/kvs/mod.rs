@@ -0,0 +1,1319 @@
+use crate::error::{Error, ErrorKind, Result};
+
+use super::expiry::{expiry_time, now_millis, time_left, Sweeper};
+use super::scan::{is_empty_range, prefix_bounds};
+use super::syncer::Syncer;
+use super::{add_delta, BatchOp, KvsEngine, SyncPolicy, Transaction, WriteBatch};
+
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+mod blob;
+mod cache;
+mod cipher;
+mod collector;
+mod commit;
+mod compaction;
+mod hint;
+mod options;
+mod record;
+mod segment;
+mod snapshot;
+mod transaction;
+
+pub use self::cache::CacheStats;
+pub use self::cipher::EncryptionKey;
+pub use self::options::{CompactionTrigger, KvStoreOptions, RecoveryMode};
+pub use self::snapshot::KvStoreSnapshot;
+
+use self::blob::{
+    blob_ids, blob_size, create_blob, encode_blob, open_blob, read_blob, BlobPointer, BlobStats,
+};
+use self::cache::ValueCache;
+use self::cipher::FileCipher;
+use self::collector::BlobCollector;
+use self::commit::CommitQueue;
+use self::compaction::Compactor;
+use self::hint::read_hint;
+use self::record::{
+    check_entry, encode_entry, read_record, read_segment_header, Record, RecordKind,
+};
+pub(super) use self::segment::read_exact_at;
+use self::segment::{
+    is_legacy, open_reader, open_segment, remove_temporary_segments, segment_ids, segment_path,
+    segment_size, temporary_path, truncate_segment, LogFile, SegmentWriter, LEGACY_LOG_NAME,
+    UPGRADE_EXTENSION,
+};
+use self::transaction::KvTransaction;
+
+/// Every time this offset threshold is reached in the log file the KvStore will do a log compaction,
+/// unless another `CompactionTrigger` is configured.
+pub const KVS_UNCOMPACTED_THRESHOLD: u16 = 4_000;
+
+/// Once the active segment grows over this size a new segment is started, unless another size
+/// is configured.
+pub const KVS_SEGMENT_MAX_SIZE: u64 = 1024 * 1024;
+
+type Index = BTreeMap<Vec<u8>, IndexEntry>;
+type Readers = BTreeMap<u64, Arc<LogFile>>;
+// Key found in the index, with its entry and the file holding its value.
+type Located = (Vec<u8>, Arc<LogFile>, IndexEntry);
+// Keys read by a transaction with their version, `None` for a key that did not exist.
+type Reads = BTreeMap<Vec<u8>, Option<u64>>;
+
+/// Position of a record inside the log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LogPointer {
+    segment: u64,
+    offset: u64,
+    len: u64,
+}
+
+/// Record of a key in the index, with the version of the key and its expiry time.
+///
+/// The version changes every time the key is written, which lets a transaction check that the
+/// keys it read were not modified before it commits. Versions are only kept in memory: the
+/// keys loaded when the store is opened all start at version 0.
+///
+/// The value of a key written to a blob file is read from `blob`, the record of the key only
+/// holds that position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct IndexEntry {
+    pointer: LogPointer,
+    version: u64,
+    expires_at: Option<u64>,
+    blob: Option<BlobPointer>,
+}
+
+impl IndexEntry {
+    // An expired key stays in the index until it is swept, but is treated as missing.
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+// Records of one operation, written as a single entry of the log. The entry is only written
+// if the keys of `reads` still have the version they were read at.
+struct LogEntry {
+    records: Vec<Record>,
+    reads: Reads,
+}
+
+impl LogEntry {
+    fn new(records: Vec<Record>) -> Self {
+        LogEntry {
+            records,
+            reads: BTreeMap::new(),
+        }
+    }
+}
+
+/// Key-Value store structure.
+///
+/// The log is split in numbered segment files (`1.log`, `2.log`, ...) inside the store
+/// directory. Only the segment with the highest id (the active one) takes writes, the
+/// other ones are sealed and are only rewritten by the compaction, which runs in a
+/// background thread.
+#[derive(Clone)]
+pub struct KvStore {
+    inner: Arc<Inner>,
+    compactor: Arc<Compactor>,
+    collector: Arc<BlobCollector>,
+    // Only held to stop the sync and sweep threads along with the last handle of the store.
+    _syncer: Option<Arc<Syncer>>,
+    _sweeper: Arc<Sweeper>,
+}
+
+// State of the store shared between its handles and its background threads.
+struct Inner {
+    /// A BTreeMap from the std lib is used to store the key and the log pointer of each
+    /// element, which keeps the keys ordered for scans.
+    index: RwLock<Index>,
+
+    readers: RwLock<Readers>,
+    writer: Mutex<SegmentWriter>,
+    commits: CommitQueue,
+
+    // Blob files holding the large values, with the one taking writes, if any. The blob
+    // writer is only locked while the log writer is.
+    blobs: RwLock<Readers>,
+    blob_writer: Mutex<Option<SegmentWriter>>,
+    blob_stats: Mutex<BTreeMap<u64, BlobStats>>,
+    next_blob_id: AtomicU64,
+    // Whether a blob file reached the garbage ratio since the collector was last woken up
+    blob_garbage: AtomicBool,
+
+    // Number of write operations since last compactation
+    uncompacted: AtomicU64,
+    // Bytes of the log taken by overwritten or removed records, and the size of the whole log
+    dead_bytes: AtomicU64,
+    total_bytes: AtomicU64,
+    // Version given to the last key written
+    last_version: AtomicU64,
+    // Whether a key of the index may expire, which lets the sweeper skip the index otherwise
+    expiring: AtomicBool,
+    // Values read by `get`, when a cache size is configured
+    cache: Option<ValueCache>,
+
+    path: PathBuf,
+    options: KvStoreOptions,
+}
+
+impl Inner {
+    // Find the segment holding the record of `key`.
+    //
+    // Both locks are only held for reading and are released before the record is read, so
+    // concurrent gets never wait on each other. The compaction swaps the index before
+    // removing any segment, so the pointer and the segment are always consistent.
+    fn lookup(&self, key: &[u8]) -> Result<Option<(Arc<LogFile>, IndexEntry)>> {
+        let readers = self.readers.read().unwrap();
+        let blobs = self.blobs.read().unwrap();
+        let entry = match self.index.read().unwrap().get(key) {
+            Some(&entry) if !entry.is_expired(now_millis()) => entry,
+            _ => return Ok(None),
+        };
+        let file = value_file(&readers, &blobs, &entry)?;
+        Ok(Some((file, entry)))
+    }
+
+    // Find the records of the first `limit` keys inside `bounds`, in the same way as `lookup`.
+    fn range(
+        &self,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<Located>> {
+        let readers = self.readers.read().unwrap();
+        let blobs = self.blobs.read().unwrap();
+        let index = self.index.read().unwrap();
+        locate(&index, &readers, &blobs, bounds, limit, now_millis())
+    }
+
+    // Copy the index and the segment handles it needs, under the same locks as `lookup`.
+    fn snapshot(&self) -> KvStoreSnapshot {
+        let readers = self.readers.read().unwrap();
+        let blobs = self.blobs.read().unwrap();
+        let index = self.index.read().unwrap();
+        KvStoreSnapshot::new(index.clone(), readers.clone(), blobs.clone(), now_millis())
+    }
+
+    /// Assert if a key exists in the Key Value Storage.
+    fn exists(&self, key: &[u8]) -> bool {
+        match self.index.read().unwrap().get(key) {
+            Some(entry) => !entry.is_expired(now_millis()),
+            None => false,
+        }
+    }
+
+    // Drop the expired keys from the index. Replaying their records gives back keys that are
+    // already expired, so the records are simply left dead until the next compaction.
+    fn sweep(&self) {
+        if !self.expiring.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let now = now_millis();
+        let mut expiring = false;
+        let expired: Vec<Vec<u8>> = self
+            .index
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_key, entry)| {
+                expiring |= entry.expires_at.is_some();
+                entry.is_expired(now)
+            })
+            .map(|(key, _entry)| key.clone())
+            .collect();
+        if expiring {
+            self.expiring.store(true, Ordering::Relaxed);
+        }
+        if expired.is_empty() {
+            return;
+        }
+
+        let mut dead = 0;
+        let mut index = self.index.write().unwrap();
+        for key in expired {
+            // The key may have been written again since the index was read.
+            if let Entry::Occupied(entry) = index.entry(key) {
+                if entry.get().is_expired(now) {
+                    let removed = entry.remove();
+                    self.release_blob(removed.blob);
+                    dead += removed.pointer.len;
+                }
+            }
+        }
+        self.dead_bytes.fetch_add(dead, Ordering::Relaxed);
+    }
+
+    // Write an entry to the log, following the sync policy of the store. Its records are all
+    // visible or none of them is, and it fails with `TransactionConflict` when its reads are
+    // outdated.
+    fn log(&self, mut entry: LogEntry) -> Result<()> {
+        check_entry(&entry.records)?;
+        for record in &mut entry.records {
+            self.compress(record)?;
+        }
+        let written = match self.options.sync_policy {
+            SyncPolicy::Always => return self.commits.commit(self, entry),
+            SyncPolicy::EveryMillis(_) | SyncPolicy::Never => self.write(vec![entry], false)?,
+        };
+        if written[0] {
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::TransactionConflict))
+        }
+    }
+
+    // Compress the value of a set record according to the compression policy, before the
+    // writer is locked. The values going to blob files are left as they are.
+    fn compress(&self, record: &mut Record) -> Result<()> {
+        let len = record.value.len();
+        let blob = match self.options.blob_threshold {
+            Some(threshold) => len as u64 >= threshold,
+            None => false,
+        };
+        if record.kind == RecordKind::Set && !blob && self.options.compression.compresses(len) {
+            record.compress()?;
+        }
+        Ok(())
+    }
+
+    // Check the reads of each entry against the index, and against the keys written by the
+    // entries before it, which are not in the index yet.
+    fn validate(&self, entries: &[LogEntry]) -> Vec<bool> {
+        if entries.iter().all(|entry| entry.reads.is_empty()) {
+            return vec![true; entries.len()];
+        }
+        let index = self.index.read().unwrap();
+        let now = now_millis();
+        let mut written = BTreeSet::new();
+        entries
+            .iter()
+            .map(|entry| {
+                let valid = entry.reads.iter().all(|(key, &version)| {
+                    let current = index
+                        .get(key)
+                        .filter(|entry| !entry.is_expired(now))
+                        .map(|entry| entry.version);
+                    !written.contains(key) && current == version
+                });
+                if valid {
+                    written.extend(entry.records.iter().map(|record| &record.key));
+                }
+                valid
+            })
+            .collect()
+    }
+
+    // Append entries to the active segment and update the index while the writer is locked,
+    // so concurrent writers of the same key can not leave a stale pointer behind. The index
+    // is only updated once the entries are flushed, or synced when `sync` is set.
+    //
+    // The entries whose reads are outdated are skipped, the returned flags tell which entries
+    // were written.
+    fn write(&self, mut entries: Vec<LogEntry>, sync: bool) -> Result<Vec<bool>> {
+        let mut wr = self.writer.lock().unwrap();
+        let mut blob_wr = self.blob_writer.lock().unwrap();
+        let valid = self.validate(&entries);
+        let mut pointers = Vec::with_capacity(entries.len());
+        let mut written = 0;
+        for (entry, _valid) in entries
+            .iter_mut()
+            .zip(&valid)
+            .filter(|(_entry, &valid)| valid)
+        {
+            if entry.records.is_empty() {
+                continue;
+            }
+            for record in &mut entry.records {
+                self.store_blob(&mut blob_wr, record)?;
+            }
+            let (bytes, positions) = encode_entry(&entry.records, wr.cipher.as_ref(), wr.offset)?;
+            let offset = wr.append(&bytes)?;
+            written += bytes.len() as u64;
+            for (position, len) in positions {
+                pointers.push(LogPointer {
+                    segment: wr.id,
+                    offset: offset + position,
+                    len,
+                });
+            }
+
+            if wr.offset >= self.options.segment_max_size {
+                if let Some(blob_wr) = blob_wr.as_mut() {
+                    self.seal(blob_wr)?;
+                }
+                self.seal(&mut wr)?;
+                self.map_sealed(wr.id)?;
+                let id = wr.id + 1;
+                *wr = SegmentWriter::open(&self.path, id, self.options.keyring.new_cipher()?)?;
+                self.readers
+                    .write()
+                    .unwrap()
+                    .insert(id, open_segment(&self.path, id, &self.options.keyring)?);
+            }
+        }
+        // The records must never reach the disk before the values they point to.
+        if let Some(blob_wr) = blob_wr.as_mut() {
+            if sync {
+                blob_wr.sync()?;
+            } else {
+                blob_wr.flush()?;
+            }
+        }
+        if sync {
+            wr.sync()?;
+        } else {
+            wr.flush()?;
+        }
+
+        // The framing of the batches is dead as soon as it is written.
+        let mut dead = written;
+        let mut index = self.index.write().unwrap();
+        let records = entries
+            .into_iter()
+            .zip(&valid)
+            .filter(|(_entry, &valid)| valid)
+            .flat_map(|(entry, _valid)| entry.records);
+        for (record, pointer) in records.zip(pointers) {
+            if let Some(cache) = &self.cache {
+                cache.invalidate(&record.key);
+            }
+            let blob = record.blob;
+            let replaced = match record.kind {
+                RecordKind::Set => {
+                    dead -= pointer.len;
+                    if record.expires_at.is_some() {
+                        self.expiring.store(true, Ordering::Relaxed);
+                    }
+                    let entry = IndexEntry {
+                        pointer,
+                        version: self.last_version.fetch_add(1, Ordering::Relaxed) + 1,
+                        expires_at: record.expires_at,
+                        blob: record.blob,
+                    };
+                    index.insert(record.key, entry)
+                }
+                RecordKind::Remove | RecordKind::Batch => index.remove(&record.key),
+            };
+            if let Some(replaced) = replaced {
+                // A new expiry time keeps the value where it is.
+                if replaced.blob != blob {
+                    self.release_blob(replaced.blob);
+                }
+                dead += replaced.pointer.len;
+            }
+        }
+        self.dead_bytes.fetch_add(dead, Ordering::Relaxed);
+        self.total_bytes.fetch_add(written, Ordering::Relaxed);
+        Ok(valid)
+    }
+
+    // Move the value of a set record of at least `blob_threshold` bytes to the active blob
+    // file, starting a new one when there is none, and leave its position in the record.
+    fn store_blob(&self, writer: &mut Option<SegmentWriter>, record: &mut Record) -> Result<()> {
+        match self.options.blob_threshold {
+            Some(threshold)
+                if record.kind == RecordKind::Set
+                    && record.blob.is_none()
+                    && record.value.len() as u64 >= threshold => {}
+            _ => return Ok(()),
+        }
+        let blob_wr = match writer {
+            Some(blob_wr) => blob_wr,
+            None => writer.insert(self.create_blob()?),
+        };
+        let bytes = encode_blob(
+            &record.key,
+            &record.value,
+            blob_wr.cipher.as_ref(),
+            blob_wr.offset,
+        );
+        let offset = blob_wr.append(&bytes)?;
+        let len = bytes.len() as u64;
+        record.blob = Some(BlobPointer {
+            file: blob_wr.id,
+            offset,
+            len,
+        });
+        record.value = Vec::new();
+        self.blob_stats
+            .lock()
+            .unwrap()
+            .entry(blob_wr.id)
+            .or_default()
+            .size += len;
+
+        if blob_wr.offset >= self.options.segment_max_size {
+            self.seal(blob_wr)?;
+            *writer = None;
+        }
+        Ok(())
+    }
+
+    // Create a new blob file and make it readable.
+    fn create_blob(&self) -> Result<SegmentWriter> {
+        let id = self.next_blob_id.fetch_add(1, Ordering::SeqCst);
+        let writer = create_blob(&self.path, id, self.options.keyring.new_cipher()?)?;
+        self.blobs
+            .write()
+            .unwrap()
+            .insert(id, open_blob(&self.path, id, &self.options.keyring)?);
+        self.blob_stats
+            .lock()
+            .unwrap()
+            .insert(id, BlobStats::default());
+        Ok(writer)
+    }
+
+    // Count a value of a blob file as dead, once no entry of the index points to it.
+    fn release_blob(&self, blob: Option<BlobPointer>) {
+        let blob = match blob {
+            Some(blob) => blob,
+            None => return,
+        };
+        // The file is gone when the collector already dropped the value.
+        if let Some(stats) = self.blob_stats.lock().unwrap().get_mut(&blob.file) {
+            stats.dead += blob.len;
+            if stats.is_garbage(self.options.blob_garbage_ratio) {
+                self.blob_garbage.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Sync the active segment, after the active blob file its records may point to.
+    fn sync(&self) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(blob_writer) = self.blob_writer.lock().unwrap().as_mut() {
+            blob_writer.sync()?;
+        }
+        writer.sync()
+    }
+
+    // Flush a segment that stops taking writes. Unless syncing is disabled it is also synced,
+    // as the background sync only covers the active segment.
+    fn seal(&self, writer: &mut SegmentWriter) -> Result<()> {
+        match self.options.sync_policy {
+            SyncPolicy::Always | SyncPolicy::EveryMillis(_) => writer.sync(),
+            SyncPolicy::Never => writer.flush(),
+        }
+    }
+
+    // Swap the reader of the segment `id` for a mapped one when mapped reads are enabled, once
+    // no record is appended to the segment anymore. The gets already holding the unmapped
+    // file finish reading it.
+    fn map_sealed(&self, id: u64) -> Result<()> {
+        if !self.options.mmap_reads {
+            return Ok(());
+        }
+        let mut readers = self.readers.write().unwrap();
+        if let Some(file) = readers.get(&id).cloned() {
+            readers.insert(id, file.mapped()?);
+        }
+        Ok(())
+    }
+}
+
+impl KvStore {
+    // Count writes and wake up the compaction thread once the configured trigger is reached.
+    fn written(&self, operations: u64) {
+        let inner = &self.inner;
+        let uncompacted = inner.uncompacted.fetch_add(operations, Ordering::Relaxed);
+        let compact = match inner.options.compaction_trigger {
+            CompactionTrigger::Operations(operations) => uncompacted > operations,
+            CompactionTrigger::DeadBytesRatio(ratio) => {
+                let total = inner.total_bytes.load(Ordering::Relaxed);
+                let dead = inner.dead_bytes.load(Ordering::Relaxed);
+                total > inner.options.segment_max_size && dead as f64 >= ratio * total as f64
+            }
+        };
+        if compact {
+            self.compactor.trigger();
+        }
+        if inner.blob_garbage.swap(false, Ordering::Relaxed) {
+            self.collector.trigger();
+        }
+    }
+
+    /// Replace the value of `key` with the result of `f`, `None` removing the key, and return
+    /// the new value, like `sled::Tree::update_and_fetch`.
+    ///
+    /// `f` gets the current value, or `None` when the key does not exist. When the key is
+    /// written by someone else in the meantime, `f` runs again with the new value, so it can
+    /// run several times. The expiry of the key is kept, and nothing is written when `f`
+    /// returns the current value.
+    ///
+    /// ```
+    /// use kvs::KvStore;
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// let append = |value: Option<&[u8]>| {
+    ///     let mut value = value.map(<[u8]>::to_vec).unwrap_or_default();
+    ///     value.push(b'!');
+    ///     Some(value)
+    /// };
+    /// store.update_and_fetch(b"shout".to_vec(), append)?;
+    /// assert_eq!(store.update_and_fetch(b"shout".to_vec(), append)?, Some(b"!!".to_vec()));
+    ///# Ok::<(), Error>(())
+    /// ```
+    pub fn update_and_fetch<F>(&self, key: Vec<u8>, mut f: F) -> Result<Option<Vec<u8>>>
+    where
+        F: FnMut(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        loop {
+            let (current, read) = match self.inner.lookup(&key)? {
+                Some((file, entry)) => (Some(read_value(&file, &entry)?), Some(entry)),
+                None => (None, None),
+            };
+            let new = f(current.as_deref());
+            if new == current {
+                return Ok(new);
+            }
+            let record = match &new {
+                Some(value) => Record::set_expiring(
+                    key.clone(),
+                    value.clone(),
+                    read.and_then(|entry| entry.expires_at),
+                ),
+                None => Record::remove(key.clone()),
+            };
+            let mut entry = LogEntry::new(vec![record]);
+            entry
+                .reads
+                .insert(key.clone(), read.map(|entry| entry.version));
+            match self.inner.log(entry) {
+                Ok(()) => {
+                    self.written(1);
+                    return Ok(new);
+                }
+                Err(ref err) if err.kind() == ErrorKind::TransactionConflict => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // Rewrite the record of `key` with another expiry time, retrying when the key is written
+    // in the meantime. Returns whether the expiry changed, which it can not for a missing key,
+    // nor when the expiry of a key without one is removed. A value stored in a blob file is
+    // not copied, the new record points to it.
+    fn set_expiry(&self, key: Vec<u8>, expires_at: Option<u64>) -> Result<bool> {
+        loop {
+            let (file, current) = match self.inner.lookup(&key)? {
+                Some((_file, current)) if current.expires_at.is_none() && expires_at.is_none() => {
+                    return Ok(false)
+                }
+                Some(found) => found,
+                None => return Ok(false),
+            };
+            let record = match current.blob {
+                Some(blob) => Record::set_blob(key.clone(), blob, expires_at),
+                None => {
+                    let value = read_value(&file, &current)?;
+                    Record::set_expiring(key.clone(), value, expires_at)
+                }
+            };
+            let mut entry = LogEntry::new(vec![record]);
+            entry.reads.insert(key.clone(), Some(current.version));
+            match self.inner.log(entry) {
+                Ok(()) => {
+                    self.written(1);
+                    return Ok(true);
+                }
+                Err(ref err) if err.kind() == ErrorKind::TransactionConflict => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // Read the values of the first `limit` keys inside `bounds`, once the index is unlocked.
+    fn read_range(
+        &self,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner
+            .range(bounds, limit)?
+            .into_iter()
+            .map(|(key, file, entry)| Ok((key, read_value(&file, &entry)?)))
+            .collect()
+    }
+
+    /// Hits and misses of the value cache, all zero when no cache size is configured, see
+    /// `KvStoreOptions::cache_size`.
+    pub fn cache_stats(&self) -> CacheStats {
+        match &self.inner.cache {
+            Some(cache) => cache.stats(),
+            None => CacheStats::default(),
+        }
+    }
+
+    /// Options to open a KvStore with a non default configuration.
+    pub fn builder() -> KvStoreOptions {
+        KvStoreOptions::new()
+    }
+
+    ///
+    /// Create a KvStore in a given path, with the default options.
+    ///
+    /// A damaged tail left by a crash in the middle of a write is truncated, see
+    /// `KvStore::builder` to choose another behaviour.
+    /// ```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let mut store = KvStore::open(dir.path())?;
+    ///# Ok::<(), Error>(())
+    /// ```
+    pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::builder().open(path)
+    }
+
+    /// Open the KvStore in a given path, encrypting its files with `key`. See
+    /// `KvStoreOptions::encryption_key` to rotate the key.
+    pub fn open_encrypted(path: impl Into<PathBuf>, key: EncryptionKey) -> Result<KvStore> {
+        KvStore::builder().encryption_key(key).open(path)
+    }
+
+    fn open_with_options(path: impl Into<PathBuf>, options: KvStoreOptions) -> Result<KvStore> {
+        let path: PathBuf = path.into();
+
+        options.compression.check()?;
+        if options.create_dir {
+            fs::create_dir_all(&path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+        }
+        remove_temporary_segments(&path)?;
+        migrate_legacy_log(&path)?;
+        let ids = segment_ids(&path)?;
+        for &id in &ids {
+            if is_legacy(&path, id)? {
+                let recover =
+                    options.recovery == RecoveryMode::TruncateTail && Some(&id) == ids.last();
+                upgrade_legacy_segment(&path, id, recover, &options)?;
+            }
+        }
+
+        let ReplayedLog {
+            index,
+            mut readers,
+            dead_bytes,
+        } = replay(&path, &options)?;
+        let expiring = index.values().any(|entry| entry.expires_at.is_some());
+        let mut total_bytes = 0;
+        for &id in readers.keys() {
+            total_bytes += segment_size(&path, id)?;
+        }
+
+        let mut blobs = BTreeMap::new();
+        let mut blob_stats = BTreeMap::new();
+        for id in blob_ids(&path)? {
+            let file = open_blob(&path, id, &options.keyring)?;
+            let size = blob_size(&path, id, &file)?;
+            blobs.insert(id, file);
+            blob_stats.insert(id, BlobStats { size, dead: size });
+        }
+        for blob in index.values().filter_map(|entry| entry.blob) {
+            if let Some(stats) = blob_stats.get_mut(&blob.file) {
+                stats.dead = stats.dead.saturating_sub(blob.len);
+            }
+        }
+        // The blob files encrypted with an older key are rewritten like the garbage ones.
+        let newest_key = options.keyring.newest_id();
+        let blob_garbage = blob_stats
+            .values()
+            .any(|stats| stats.is_garbage(options.blob_garbage_ratio))
+            || blobs.values().any(|file| file.key_id() != newest_key);
+        // Writes never go to an existing blob file, whose tail may be torn.
+        let next_blob_id = blobs.keys().next_back().map_or(1, |&id| id + 1);
+
+        // An encrypted store never appends to a segment it did not create, see `FileCipher`.
+        let active_id = match readers.keys().next_back() {
+            Some(&id)
+                if newest_key.is_none() && segment_size(&path, id)? < options.segment_max_size =>
+            {
+                id
+            }
+            Some(&id) => id + 1,
+            None => 1,
+        };
+        let writer = SegmentWriter::open(&path, active_id, options.keyring.new_cipher()?)?;
+        if let Entry::Vacant(entry) = readers.entry(active_id) {
+            entry.insert(open_segment(&path, active_id, &options.keyring)?);
+        }
+        if options.mmap_reads {
+            for (_id, file) in readers.range_mut(..active_id) {
+                *file = file.mapped()?;
+            }
+        }
+
+        let inner = Arc::new(Inner {
+            index: RwLock::new(index),
+            readers: RwLock::new(readers),
+            writer: Mutex::new(writer),
+            commits: CommitQueue::new(),
+            blobs: RwLock::new(blobs),
+            blob_writer: Mutex::new(None),
+            blob_stats: Mutex::new(blob_stats),
+            next_blob_id: AtomicU64::new(next_blob_id),
+            blob_garbage: AtomicBool::new(false),
+            uncompacted: AtomicU64::new(0),
+            dead_bytes: AtomicU64::new(dead_bytes),
+            total_bytes: AtomicU64::new(total_bytes),
+            last_version: AtomicU64::new(0),
+            expiring: AtomicBool::new(expiring),
+            cache: options.cache_size.map(ValueCache::new),
+            path,
+            options,
+        });
+        let compactor = Arc::new(Compactor::spawn(inner.clone()));
+        let collector = Arc::new(BlobCollector::spawn(inner.clone()));
+        if blob_garbage {
+            collector.trigger();
+        }
+        let syncer = match inner.options.sync_policy {
+            SyncPolicy::EveryMillis(millis) => {
+                let inner = inner.clone();
+                Some(Arc::new(Syncer::spawn(
+                    Duration::from_millis(millis),
+                    move || {
+                        if let Err(err) = inner.sync() {
+                            error!(inner.options.logger, "Log sync failed: {}", err);
+                        }
+                    },
+                )))
+            }
+            SyncPolicy::Always | SyncPolicy::Never => None,
+        };
+        let sweeper = {
+            let inner = inner.clone();
+            Sweeper::spawn(inner.options.sweep_interval, move || inner.sweep())
+        };
+
+        Ok(KvStore {
+            inner,
+            compactor,
+            collector,
+            _syncer: syncer,
+            _sweeper: Arc::new(sweeper),
+        })
+    }
+}
+
+// Index rebuilt from the segments when the store is opened.
+struct ReplayedLog {
+    index: Index,
+    readers: Readers,
+    dead_bytes: u64,
+}
+
+impl ReplayedLog {
+    fn insert(
+        &mut self,
+        key: Vec<u8>,
+        pointer: LogPointer,
+        expires_at: Option<u64>,
+        blob: Option<BlobPointer>,
+    ) {
+        let entry = IndexEntry {
+            pointer,
+            version: 0,
+            expires_at,
+            blob,
+        };
+        if let Some(replaced) = self.index.insert(key, entry) {
+            self.dead_bytes += replaced.pointer.len;
+        }
+    }
+
+    fn apply(&mut self, record: Record, pointer: LogPointer) {
+        match record.kind {
+            RecordKind::Set => self.insert(record.key, pointer, record.expires_at, record.blob),
+            RecordKind::Remove | RecordKind::Batch => self.remove(&record.key, pointer.len),
+        }
+    }
+
+    fn remove(&mut self, key: &[u8], len: u64) {
+        self.dead_bytes += len;
+        if let Some(removed) = self.index.remove(key) {
+            self.dead_bytes += removed.pointer.len;
+        }
+    }
+}
+
+// Replay the segments from the oldest to the newest to build the index. Merged segments are
+// loaded from their hint file instead, which avoids reading every value of the store.
+fn replay(dir: &Path, options: &KvStoreOptions) -> Result<ReplayedLog> {
+    let mut log = ReplayedLog {
+        index: BTreeMap::new(),
+        readers: BTreeMap::new(),
+        dead_bytes: 0,
+    };
+    let ids = segment_ids(dir)?;
+    for &id in &ids {
+        let mut reader = open_reader(dir, id, options.read_buffer_size)?;
+        let (header_len, cipher) = read_segment_header(&mut reader, &options.keyring)?;
+        if let Some(hints) = read_hint(dir, id, segment_size(dir, id)?, cipher.as_ref())? {
+            for hint in hints {
+                let pointer = LogPointer {
+                    segment: id,
+                    offset: hint.offset,
+                    len: hint.len,
+                };
+                log.insert(hint.key, pointer, hint.expires_at, hint.blob);
+            }
+            let file = reader.into_inner();
+            log.readers.insert(id, Arc::new(LogFile::new(file, cipher)));
+            continue;
+        }
+
+        let mut offset = header_len;
+        let replayed = replay_segment(&mut reader, id, cipher.as_ref(), &mut offset, &mut log);
+        if let Err(err) = replayed {
+            let damaged = matches!(
+                err.kind(),
+                ErrorKind::TornRecord | ErrorKind::CorruptedRecord
+            );
+            if !damaged || options.recovery == RecoveryMode::Strict || Some(&id) != ids.last() {
+                return Err(err);
+            }
+            let size = segment_size(dir, id)?;
+            warn!(
+                options.logger,
+                "Dropping {} bytes at the end of segment {} after offset {}: {}",
+                size - offset,
+                id,
+                offset,
+                err
+            );
+            truncate_segment(dir, id, offset)?;
+        }
+        let file = reader.into_inner();
+        log.readers.insert(id, Arc::new(LogFile::new(file, cipher)));
+    }
+    Ok(log)
+}
+
+// Replay a single segment whose header was read, leaving in `offset` the end of the last valid
+// record.
+fn replay_segment(
+    reader: &mut BufReader<File>,
+    id: u64,
+    cipher: Option<&FileCipher>,
+    offset: &mut u64,
+    log: &mut ReplayedLog,
+) -> Result<()> {
+    while let Some((record, len)) = read_record(reader, cipher, *offset)? {
+        if record.kind == RecordKind::Batch {
+            // Only the records of the batch are indexed, its framing is dead.
+            log.dead_bytes += len;
+            for (record, position, record_len) in record.split_batch(cipher, *offset)? {
+                log.dead_bytes -= record_len;
+                let pointer = LogPointer {
+                    segment: id,
+                    offset: *offset + position,
+                    len: record_len,
+                };
+                log.apply(record, pointer);
+            }
+        } else {
+            let pointer = LogPointer {
+                segment: id,
+                offset: *offset,
+                len,
+            };
+            log.apply(record, pointer);
+        }
+        *offset += len;
+    }
+    Ok(())
+}
+
+// Logs written before segmentation live in a single `kvs.log`, which becomes the first segment.
+fn migrate_legacy_log(dir: &Path) -> Result<()> {
+    let legacy = dir.join(LEGACY_LOG_NAME);
+    if legacy.is_file() && segment_ids(dir)?.is_empty() {
+        fs::rename(legacy, segment_path(dir, 1))
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+    }
+    Ok(())
+}
+
+// Command of the original log format, which only held strings.
+#[derive(Deserialize)]
+enum LegacyCommand {
+    Set(String, String),
+    Rm(String),
+}
+
+// Rewrite a segment of serde_json commands with the binary record format.
+//
+// The new segment is written next to the old one and renamed over it, so a crash during the
+// upgrade leaves the original segment untouched. With `recover` the lines following the first
+// one that can not be parsed are dropped instead of failing.
+fn upgrade_legacy_segment(
+    dir: &Path,
+    id: u64,
+    recover: bool,
+    options: &KvStoreOptions,
+) -> Result<()> {
+    let upgrade_path = temporary_path(dir, id, UPGRADE_EXTENSION);
+
+    let mut writer = SegmentWriter::open_path(&upgrade_path, id, options.keyring.new_cipher()?)?;
+    let mut offset = 0;
+    for line in open_reader(dir, id, options.read_buffer_size)?.lines() {
+        let line = line.map_err(|_err| Error::from(ErrorKind::FileError))?;
+        let command = match serde_json::from_str(&line[..]) {
+            Ok(command) => command,
+            Err(_err) if recover => {
+                let size = segment_size(dir, id)?;
+                warn!(
+                    options.logger,
+                    "Dropping {} bytes at the end of legacy segment {} after offset {}",
+                    size - offset,
+                    id,
+                    offset
+                );
+                break;
+            }
+            Err(_err) => return Err(Error::from(ErrorKind::ParsingError)),
+        };
+        let record = match command {
+            LegacyCommand::Set(key, value) => Record::set(key.into_bytes(), value.into_bytes()),
+            LegacyCommand::Rm(key) => Record::remove(key.into_bytes()),
+        };
+        writer.append(&record.encode(writer.cipher.as_ref(), writer.offset)?)?;
+        offset += line.len() as u64 + 1;
+    }
+    writer.flush()?;
+
+    fs::rename(upgrade_path, segment_path(dir, id))
+        .map_err(|_err| Error::from(ErrorKind::FileError))
+}
+
+// Read the raw bytes of the record pointed by `pointer`.
+fn read_pointer<'a>(file: &'a LogFile, pointer: &LogPointer) -> Result<Cow<'a, [u8]>> {
+    file.read_at(pointer.offset, pointer.len)
+}
+
+// Find the file holding the value of `entry`, a blob file or the segment of its record.
+fn value_file(readers: &Readers, blobs: &Readers, entry: &IndexEntry) -> Result<Arc<LogFile>> {
+    let file = match entry.blob {
+        Some(blob) => blobs.get(&blob.file),
+        None => readers.get(&entry.pointer.segment),
+    };
+    file.cloned()
+        .ok_or_else(|| Error::from(ErrorKind::FileError))
+}
+
+// Find the values of the first `limit` keys of `index` inside `bounds`, skipping the keys
+// expired at `now`.
+fn locate(
+    index: &Index,
+    readers: &Readers,
+    blobs: &Readers,
+    bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    limit: usize,
+    now: u64,
+) -> Result<Vec<Located>> {
+    if is_empty_range(&bounds) {
+        return Ok(Vec::new());
+    }
+    index
+        .range(bounds)
+        .filter(|(_key, entry)| !entry.is_expired(now))
+        .take(limit)
+        .map(|(key, entry)| Ok((key.clone(), value_file(readers, blobs, entry)?, *entry)))
+        .collect()
+}
+
+// Read the value of `entry` from `file`, found with `value_file`.
+fn read_value(file: &LogFile, entry: &IndexEntry) -> Result<Vec<u8>> {
+    if let Some(blob) = &entry.blob {
+        return read_blob(file, blob);
+    }
+    let bytes = read_pointer(file, &entry.pointer)?;
+    let record = Record::decode(&bytes, file.cipher.as_ref(), entry.pointer.offset)?;
+    match record.kind {
+        RecordKind::Set if record.blob.is_none() => record.into_value(),
+        RecordKind::Set | RecordKind::Remove | RecordKind::Batch => {
+            Err(Error::from(ErrorKind::InvalidData))
+        }
+    }
+}
+
+impl KvsEngine for KvStore {
+    type Snapshot = KvStoreSnapshot;
+
+    /// Get value of a given key in the KV store.
+    ///
+    ///```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// let missing = store.get(b"missing_key".to_vec())?;
+    /// assert_eq!(missing, None);
+    ///# Ok::<(), Error>(())
+    ///```
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let (file, entry) = match self.inner.lookup(&key)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let cache = match &self.inner.cache {
+            Some(cache) => cache,
+            None => return read_value(&file, &entry).map(Some),
+        };
+        if let Some(value) = cache.get(&key, entry.version) {
+            return Ok(Some(value));
+        }
+        let value = read_value(&file, &entry)?;
+        cache.insert(key, entry.version, value.clone());
+        Ok(Some(value))
+    }
+
+    /// Get the first `limit` keys in `[start, end)` with their values, ordered by key.
+    ///
+    ///```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// store.set(b"user:1".to_vec(), b"alice".to_vec())?;
+    /// store.set(b"user:2".to_vec(), b"bob".to_vec())?;
+    /// let users = store.scan(b"user:".to_vec(), b"user;".to_vec(), 1)?;
+    /// assert_eq!(users, vec![(b"user:1".to_vec(), b"alice".to_vec())]);
+    ///# Ok::<(), Error>(())
+    ///```
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range((Bound::Included(start), Bound::Excluded(end)), limit)
+    }
+
+    /// Get the first `limit` keys starting with `prefix` that come after `after`.
+    ///
+    /// The index is only locked while the keys are looked up, see `scan_prefix` to iterate
+    /// over all the keys of a prefix.
+    ///```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// store.set(b"user:1:name".to_vec(), b"alice".to_vec())?;
+    /// store.set(b"user:1:mail".to_vec(), b"alice@example.com".to_vec())?;
+    /// let keys: Vec<_> = store
+    ///     .scan_prefix(b"user:1:".to_vec())
+    ///     .map(|pair| pair.map(|(key, _value)| key))
+    ///     .collect::<Result<_, Error>>()?;
+    /// assert_eq!(keys, vec![b"user:1:mail".to_vec(), b"user:1:name".to_vec()]);
+    ///# Ok::<(), Error>(())
+    ///```
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range(prefix_bounds(&prefix, after), limit)
+    }
+
+    ///
+    /// Set value of a key in in the KV store
+    /// ```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let mut store = KvStore::open(dir.path())?;
+    /// store.set(b"key1".to_vec(), b"value 1\n".to_vec())?;
+    /// let value = store.get(b"key1".to_vec())?.unwrap();
+    /// assert_eq!(value, b"value 1\n");
+    ///# Ok::<(), Error>(())
+    /// ```
+    fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.inner
+            .log(LogEntry::new(vec![Record::set(key, value)]))?;
+        self.written(1);
+        Ok(())
+    }
+
+    /// Set value of a key expiring after `ttl`. The expiry time is stored in the log, so it
+    /// survives a restart.
+    /// ```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// use std::time::Duration;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// store.set_with_ttl(b"session".to_vec(), b"token".to_vec(), Duration::from_secs(30))?;
+    /// assert!(store.ttl(b"session".to_vec())?.unwrap() <= Duration::from_secs(30));
+    ///# Ok::<(), Error>(())
+    /// ```
+    fn set_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let record = Record::set_expiring(key, value, Some(expiry_time(ttl)));
+        self.inner.log(LogEntry::new(vec![record]))?;
+        self.written(1);
+        Ok(())
+    }
+
+    /// Make a key expire after `ttl` by writing its value again with the new expiry time.
+    fn expire(&self, key: Vec<u8>, ttl: Duration) -> Result<bool> {
+        self.set_expiry(key, Some(expiry_time(ttl)))
+    }
+
+    fn ttl(&self, key: Vec<u8>) -> Result<Option<Duration>> {
+        match self.inner.lookup(&key)? {
+            Some((_file, entry)) => Ok(entry.expires_at.map(time_left)),
+            None => Err(Error::from(ErrorKind::KeyNotFound)),
+        }
+    }
+
+    /// Remove the expiry of a key by writing its value again without expiry time.
+    fn persist(&self, key: Vec<u8>) -> Result<bool> {
+        self.set_expiry(key, None)
+    }
+
+    /// Take a read-only view of the store, which is not affected by the following writes.
+    ///
+    /// ```
+    /// use kvs::engines::KvsSnapshot;
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    /// let snapshot = store.snapshot()?;
+    /// store.set(b"key1".to_vec(), b"value2".to_vec())?;
+    /// assert_eq!(snapshot.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    ///# Ok::<(), Error>(())
+    /// ```
+    fn snapshot(&self) -> Result<KvStoreSnapshot> {
+        Ok(self.inner.snapshot())
+    }
+
+    /// Add `delta` to the integer stored in a key with `KvStore::update_and_fetch`.
+    ///
+    /// ```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// store.set(b"visits".to_vec(), b"41".to_vec())?;
+    /// assert_eq!(store.increment(b"visits".to_vec(), 1)?, 42);
+    ///# Ok::<(), Error>(())
+    /// ```
+    fn increment(&self, key: Vec<u8>, delta: i64) -> Result<i64> {
+        let mut result = Ok(0);
+        self.update_and_fetch(key, |value| {
+            result = add_delta(value, delta);
+            match result {
+                Ok(sum) => Some(sum.to_string().into_bytes()),
+                // Returning the current value leaves the key untouched.
+                Err(_) => value.map(<[u8]>::to_vec),
+            }
+        })?;
+        result
+    }
+
+    /// Apply the writes of `batch` as a single entry of the log. A batch torn by a crash is
+    /// dropped as a whole when the store is opened again.
+    ///
+    /// ```
+    /// use kvs::engines::WriteBatch;
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// let mut batch = WriteBatch::new();
+    /// batch.set(b"key1".to_vec(), b"value1".to_vec());
+    /// batch.set(b"key2".to_vec(), b"value2".to_vec());
+    /// store.write_batch(batch)?;
+    /// assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+    ///# Ok::<(), Error>(())
+    /// ```
+    fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let records: Vec<_> = batch
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Set(key, value) => Record::set(key, value),
+                BatchOp::Remove(key) => Record::remove(key),
+            })
+            .collect();
+        let operations = records.len() as u64;
+        self.inner.log(LogEntry::new(records))?;
+        self.written(operations);
+        Ok(())
+    }
+
+    /// Run `f` as an optimistic transaction.
+    ///
+    /// The version of every key read by `f` is checked when its writes are appended to the
+    /// log, with the writer locked. If one of them was written in the meantime, `f` runs again.
+    ///
+    /// ```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = KvStore::open(dir.path())?;
+    /// store.set(b"from".to_vec(), b"10".to_vec())?;
+    /// store.transaction(|tx| {
+    ///     let from = tx.get(b"from".to_vec())?.unwrap_or_default();
+    ///     tx.set(b"to".to_vec(), from)?;
+    ///     tx.remove(b"from".to_vec())
+    /// })?;
+    /// assert_eq!(store.get(b"to".to_vec())?, Some(b"10".to_vec()));
+    ///# Ok::<(), Error>(())
+    /// ```
+    fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&mut dyn Transaction) -> Result<T>,
+    {
+        loop {
+            let mut tx = KvTransaction::new(&self.inner);
+            let value = f(&mut tx)?;
+            let (entry, operations) = tx.into_entry();
+            match self.inner.log(entry) {
+                Ok(()) => {
+                    self.written(operations);
+                    return Ok(value);
+                }
+                Err(ref err) if err.kind() == ErrorKind::TransactionConflict => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Remove key-value from the KV store
+    /// ```
+    /// use kvs::{KvStore, KvsEngine};
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let mut store = KvStore::open(dir.path())?;
+    /// store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    /// let value = store.get(b"key1".to_vec())?.unwrap();
+    /// assert_eq!(value, b"value1");
+    /// store.remove(b"key1".to_vec());
+    /// let missing = store.get(b"missing_key".to_vec())?;
+    /// assert_eq!(missing, None);
+    ///# Ok::<(), Error>(())
+    /// ```
+    fn remove(&self, key: Vec<u8>) -> Result<()> {
+        if self.inner.exists(&key) {
+            self.inner.log(LogEntry::new(vec![Record::remove(key)]))?;
+            self.written(1);
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::KeyNotFound))
+        }
+    }
+}
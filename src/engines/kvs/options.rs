@@ -0,0 +1,207 @@
+use super::cipher::{EncryptionKey, Keyring};
+use super::{KvStore, KVS_SEGMENT_MAX_SIZE, KVS_UNCOMPACTED_THRESHOLD};
+use crate::engines::expiry::SWEEP_INTERVAL;
+use crate::engines::{CompressionPolicy, SyncPolicy};
+use crate::error::Result;
+
+use slog::{Discard, Logger};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Behaviour of `KvStore` when the newest segment ends with a damaged record, which is what
+/// a process killed in the middle of a write leaves behind.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecoveryMode {
+    /// Truncate the segment back to its last valid record, logging what was dropped.
+    TruncateTail,
+    /// Refuse to open the store, returning `TornRecord` or `CorruptedRecord`.
+    Strict,
+}
+
+/// Condition that wakes up the compaction thread.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionTrigger {
+    /// Compact after this many sets and removes.
+    Operations(u64),
+    /// Compact once this fraction of the log is taken by overwritten or removed records,
+    /// as long as the log is bigger than one segment.
+    DeadBytesRatio(f64),
+}
+
+/// Options used to open a `KvStore`.
+///
+/// ```
+/// use kvs::engines::{CompactionTrigger, KvStore, SyncPolicy};
+/// use kvs::error::Error;
+/// # let dir = tempfile::TempDir::new().unwrap();
+/// let store = KvStore::builder()
+///     .compaction_trigger(CompactionTrigger::DeadBytesRatio(0.5))
+///     .segment_max_size(64 * 1024 * 1024)
+///     .sync_policy(SyncPolicy::Always)
+///     .open(dir.path())?;
+///# Ok::<(), Error>(())
+/// ```
+#[derive(Clone)]
+pub struct KvStoreOptions {
+    pub(super) compaction_trigger: CompactionTrigger,
+    pub(super) segment_max_size: u64,
+    pub(super) sync_policy: SyncPolicy,
+    pub(super) read_buffer_size: usize,
+    pub(super) create_dir: bool,
+    pub(super) recovery: RecoveryMode,
+    pub(super) sweep_interval: Duration,
+    pub(super) blob_threshold: Option<u64>,
+    pub(super) blob_garbage_ratio: f64,
+    pub(super) compression: CompressionPolicy,
+    pub(super) keyring: Keyring,
+    pub(super) cache_size: Option<u64>,
+    pub(super) mmap_reads: bool,
+    pub(super) logger: Logger,
+}
+
+impl Default for KvStoreOptions {
+    fn default() -> Self {
+        KvStoreOptions {
+            compaction_trigger: CompactionTrigger::Operations(u64::from(KVS_UNCOMPACTED_THRESHOLD)),
+            segment_max_size: KVS_SEGMENT_MAX_SIZE,
+            sync_policy: SyncPolicy::Never,
+            read_buffer_size: 8 * 1024,
+            create_dir: false,
+            recovery: RecoveryMode::TruncateTail,
+            sweep_interval: SWEEP_INTERVAL,
+            blob_threshold: None,
+            blob_garbage_ratio: 0.5,
+            compression: CompressionPolicy::Off,
+            keyring: Keyring::default(),
+            cache_size: None,
+            mmap_reads: false,
+            logger: Logger::root(Discard, o!()),
+        }
+    }
+}
+
+impl KvStoreOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets when the log is compacted. Defaults to every `KVS_UNCOMPACTED_THRESHOLD` writes.
+    pub fn compaction_trigger(mut self, trigger: CompactionTrigger) -> Self {
+        self.compaction_trigger = trigger;
+        self
+    }
+
+    /// Sets the size after which a new segment is started. Defaults to `KVS_SEGMENT_MAX_SIZE`.
+    pub fn segment_max_size(mut self, size: u64) -> Self {
+        self.segment_max_size = size.max(1);
+        self
+    }
+
+    /// Sets when writes reach the disk. Defaults to `SyncPolicy::Never`.
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Sets the size of the buffer used to read the log when the store is opened.
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer_size = size.max(1);
+        self
+    }
+
+    /// Whether the store directory is created when it does not exist. Defaults to `false`.
+    pub fn create_dir(mut self, create: bool) -> Self {
+        self.create_dir = create;
+        self
+    }
+
+    /// Sets how a damaged log tail is handled. Defaults to `RecoveryMode::TruncateTail`.
+    pub fn recovery(mut self, recovery: RecoveryMode) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Sets how often the expired keys are dropped from the index. Defaults to one second.
+    ///
+    /// Expired keys are never returned, the sweep only frees the memory they hold, and the
+    /// next compaction reclaims their records.
+    pub fn sweep_interval(mut self, interval: Duration) -> Self {
+        self.sweep_interval = interval;
+        self
+    }
+
+    /// Stores the values of at least `size` bytes in blob files instead of the log, which then
+    /// only holds their position. Defaults to storing every value in the log.
+    ///
+    /// The compaction copies the position instead of the value, which keeps it cheap when
+    /// large values sit next to frequently written small ones. Blob files are collected on
+    /// their own, see `blob_garbage_ratio`.
+    pub fn blob_threshold(mut self, size: u64) -> Self {
+        self.blob_threshold = Some(size);
+        self
+    }
+
+    /// Sets the fraction of a blob file taken by overwritten or removed values after which
+    /// its live values are moved to a new file and the file is deleted. Defaults to 0.5.
+    pub fn blob_garbage_ratio(mut self, ratio: f64) -> Self {
+        self.blob_garbage_ratio = ratio;
+        self
+    }
+
+    /// Sets which values are compressed before they are written to the log. Defaults to
+    /// `CompressionPolicy::Off`.
+    ///
+    /// Each record tells how its value is encoded, so the policy can change between two
+    /// openings of the store: the values already written are read as they are. Values stored
+    /// in blob files are never compressed.
+    pub fn compression(mut self, policy: CompressionPolicy) -> Self {
+        self.compression = policy;
+        self
+    }
+
+    /// Adds a key encrypting the files of the store. Defaults to storing them in plain text.
+    ///
+    /// The keys and values written to the log, blob and hint files are encrypted with the key
+    /// of the highest id, written in the header of every file so the other keys can still read
+    /// the files written before. Rotating the key is a matter of adding a new one: the
+    /// compaction and the blob collector rewrite the older files with it, after which the
+    /// older keys are no longer needed. Opening an encrypted store without the key of one of
+    /// its files fails with `UnknownEncryptionKey`.
+    pub fn encryption_key(mut self, key: EncryptionKey) -> Self {
+        self.keyring.add(key);
+        self
+    }
+
+    /// Caches the values read by `get` in memory, up to `size` bytes of keys and values.
+    /// Defaults to reading every value from the disk.
+    ///
+    /// The least recently read values are evicted first. Writing or removing a key drops its
+    /// cached value, see `KvStore::cache_stats` for the hit and miss counters.
+    pub fn cache_size(mut self, size: u64) -> Self {
+        self.cache_size = Some(size);
+        self
+    }
+
+    /// Whether the segments that no longer take writes are mapped in memory. Defaults to
+    /// `false`, reading every record with a positional read of the file.
+    ///
+    /// A get from a mapped segment copies the record out of the mapping, without any system
+    /// call. The active segment and the blob files are always read from the file. The
+    /// segments merged by the compaction are mapped once they are complete, and the old
+    /// mappings are dropped once no get or snapshot uses them anymore.
+    pub fn mmap_reads(mut self, enabled: bool) -> Self {
+        self.mmap_reads = enabled;
+        self
+    }
+
+    /// Sets the logger used for recovery and compaction messages.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Open the `KvStore` stored in `path` with these options.
+    pub fn open(self, path: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::open_with_options(path, self)
+    }
+}
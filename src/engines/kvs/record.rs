@@ -0,0 +1,556 @@
+use super::blob::BlobPointer;
+use super::cipher::{FileCipher, Keyring, CIPHER_HEADER_LEN, TAG_LEN};
+use crate::engines::expiry::now_millis;
+use crate::error::{Error, ErrorKind, Result};
+
+use crc32fast::Hasher;
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Cursor, Read};
+
+/// Bytes at the start of every segment written with the binary format.
+pub const SEGMENT_MAGIC: &[u8; 3] = b"KVS";
+
+/// Version of the record format written by this crate.
+///
+/// Version `0` is the original format, one serde_json `Command` per line without any header.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Version of the segments whose records are encrypted, see `segment_header`.
+pub const ENCRYPTED_FORMAT_VERSION: u8 = 2;
+
+/// Size of the segment header: the magic bytes followed by the format version.
+pub const SEGMENT_HEADER_LEN: u64 = 4;
+
+// crc32 (4) + timestamp (8) + kind (1) + key length (4) + value length (4)
+const RECORD_HEADER_LEN: usize = 21;
+
+/// Header written at the start of every binary segment.
+///
+/// The header of an encrypted segment uses `ENCRYPTED_FORMAT_VERSION` and goes on with the id
+/// of the key encrypting the segment as a `u32`, the salt of the segment and a tag checking the
+/// key, see `FileCipher`.
+pub fn segment_header(cipher: Option<&FileCipher>) -> Vec<u8> {
+    let mut header = SEGMENT_MAGIC.to_vec();
+    match cipher {
+        Some(cipher) => {
+            header.push(ENCRYPTED_FORMAT_VERSION);
+            header.extend_from_slice(&cipher.header());
+        }
+        None => header.push(FORMAT_VERSION),
+    }
+    header
+}
+
+/// Operation stored in a record.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordKind {
+    Set = 1,
+    Remove = 2,
+    /// Several records written together, see `encode_entry`.
+    Batch = 3,
+}
+
+// Kind byte of a set record whose key expires, see `Record`.
+const EXPIRING_SET: u8 = 4;
+
+// Kind byte of a set record whose value is stored in a blob file, see `Record`.
+const BLOB_SET: u8 = 5;
+
+// expiry time (8) + blob file (8) + offset (8) + length (8)
+const BLOB_SET_VALUE_LEN: usize = 32;
+
+// Kind byte of a set record whose value is compressed, see `Record`.
+const COMPRESSED_SET: u8 = 6;
+
+// codec (1) + expiry time (8)
+const COMPRESSED_SET_HEADER_LEN: usize = 9;
+
+/// Largest key or value a record can hold. Their lengths are encoded as `u32`, and a value can
+/// be preceded by a compression header and followed by an authentication tag.
+pub const MAX_FIELD_LEN: usize = u32::MAX as usize - COMPRESSED_SET_HEADER_LEN - TAG_LEN;
+
+/// Encoding of the value of a set record.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    Raw = 0,
+    Zstd = 1,
+}
+
+/// Entry of the log.
+///
+/// Records are encoded as:
+///
+/// ```text
+/// crc32      u32   checksum of every following byte of the record
+/// timestamp  u64   milliseconds since the unix epoch
+/// kind       u8    1 = set, 2 = remove, 3 = batch, 4 = set of an expiring key,
+///                  5 = set of a value stored in a blob file, 6 = set of a compressed value
+/// key_len    u32
+/// value_len  u32
+/// key        [u8; key_len]
+/// value      [u8; value_len]
+/// ```
+///
+/// Integers are little endian. The value of a batch record holds the encoded records of the
+/// batch, and its key is empty. The value of an expiring set starts with the expiry time of
+/// the key as a `u64` in milliseconds since the unix epoch, followed by the value itself;
+/// it decodes to a `RecordKind::Set` with `expires_at` set. The value of a blob set holds
+/// the expiry time of the key, 0 for none, followed by the file, offset and length of the
+/// blob record as `u64`s; it decodes to a `RecordKind::Set` with `blob` set and an empty
+/// value. The value of a compressed set starts with the codec byte, 1 for zstd, and the expiry
+/// time of the key, 0 for none, followed by the compressed value; it decodes to a
+/// `RecordKind::Set` with `codec` set and the value left compressed, see `into_value`.
+///
+/// In an encrypted segment the key and value of every record but a batch are encrypted
+/// together, using the position of the record in the segment as nonce and its header as
+/// additional data, and followed by the authentication tag, which `value_len` includes. The
+/// records inside a batch are encrypted on their own, at their position in the segment.
+#[derive(Debug)]
+pub struct Record {
+    pub timestamp: u64,
+    pub kind: RecordKind,
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub expires_at: Option<u64>,
+    pub blob: Option<BlobPointer>,
+    pub codec: Codec,
+}
+
+impl Record {
+    pub fn set(key: Vec<u8>, value: Vec<u8>) -> Self {
+        Record::set_expiring(key, value, None)
+    }
+
+    /// Set record of a key expiring at `expires_at`, in milliseconds since the unix epoch.
+    pub fn set_expiring(key: Vec<u8>, value: Vec<u8>, expires_at: Option<u64>) -> Self {
+        Record {
+            timestamp: now_millis(),
+            kind: RecordKind::Set,
+            key,
+            value,
+            expires_at,
+            blob: None,
+            codec: Codec::Raw,
+        }
+    }
+
+    /// Set record of a key whose value was written to a blob file at `blob`.
+    pub fn set_blob(key: Vec<u8>, blob: BlobPointer, expires_at: Option<u64>) -> Self {
+        Record {
+            blob: Some(blob),
+            ..Record::set_expiring(key, Vec::new(), expires_at)
+        }
+    }
+
+    pub fn remove(key: Vec<u8>) -> Self {
+        Record {
+            timestamp: now_millis(),
+            kind: RecordKind::Remove,
+            key,
+            value: Vec::new(),
+            expires_at: None,
+            blob: None,
+            codec: Codec::Raw,
+        }
+    }
+
+    /// Compress the value of a set record with zstd, unless it does not get any smaller.
+    #[cfg(feature = "compression")]
+    pub fn compress(&mut self) -> Result<()> {
+        if self.kind != RecordKind::Set || self.blob.is_some() || self.codec != Codec::Raw {
+            return Ok(());
+        }
+        let compressed = zstd::encode_all(&self.value[..], zstd::DEFAULT_COMPRESSION_LEVEL)
+            .map_err(|_err| Error::from(ErrorKind::InvalidData))?;
+        if compressed.len() + COMPRESSED_SET_HEADER_LEN < self.value.len() {
+            self.value = compressed;
+            self.codec = Codec::Zstd;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "compression"))]
+    pub fn compress(&mut self) -> Result<()> {
+        Err(Error::from(ErrorKind::UnsupportedCodec))
+    }
+
+    /// Take the value of the record, decompressing it when needed.
+    pub fn into_value(self) -> Result<Vec<u8>> {
+        match self.codec {
+            Codec::Raw => Ok(self.value),
+            Codec::Zstd => decompress(&self.value),
+        }
+    }
+
+    /// Encode the record written at `position` of a segment, encrypting it with the cipher of
+    /// the segment, if any. Fails with `DataTooLarge` when a length does not fit in the record
+    /// header.
+    pub fn encode(&self, cipher: Option<&FileCipher>, position: u64) -> Result<Vec<u8>> {
+        let bytes = self.encode_plain()?;
+        match cipher {
+            Some(cipher) if self.kind != RecordKind::Batch => seal_record(bytes, cipher, position),
+            _ => Ok(bytes),
+        }
+    }
+
+    fn encode_plain(&self) -> Result<Vec<u8>> {
+        if let (RecordKind::Set, Some(blob)) = (self.kind, self.blob) {
+            let mut value = Vec::with_capacity(BLOB_SET_VALUE_LEN);
+            value.extend_from_slice(&self.expires_at.unwrap_or(0).to_le_bytes());
+            value.extend_from_slice(&blob.file.to_le_bytes());
+            value.extend_from_slice(&blob.offset.to_le_bytes());
+            value.extend_from_slice(&blob.len.to_le_bytes());
+            return self.encode_with(BLOB_SET, None, &value);
+        }
+        if let (RecordKind::Set, Codec::Zstd) = (self.kind, self.codec) {
+            let mut value = Vec::with_capacity(COMPRESSED_SET_HEADER_LEN + self.value.len());
+            value.push(self.codec as u8);
+            value.extend_from_slice(&self.expires_at.unwrap_or(0).to_le_bytes());
+            value.extend_from_slice(&self.value);
+            return self.encode_with(COMPRESSED_SET, None, &value);
+        }
+        let expires_at = self.expires_at.filter(|_| self.kind == RecordKind::Set);
+        let kind = match expires_at {
+            Some(_) => EXPIRING_SET,
+            None => self.kind as u8,
+        };
+        self.encode_with(kind, expires_at, &self.value)
+    }
+
+    fn encode_with(&self, kind: u8, expires_at: Option<u64>, value: &[u8]) -> Result<Vec<u8>> {
+        let expiry_len = if expires_at.is_some() { 8 } else { 0 };
+        let value_len = expiry_len + value.len();
+        let mut bytes = Vec::with_capacity(RECORD_HEADER_LEN + self.key.len() + value_len);
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(&encode_len(self.key.len())?);
+        bytes.extend_from_slice(&encode_len(value_len)?);
+        bytes.extend_from_slice(&self.key);
+        if let Some(expires_at) = expires_at {
+            bytes.extend_from_slice(&expires_at.to_le_bytes());
+        }
+        bytes.extend_from_slice(value);
+        let crc = checksum(&bytes[4..]);
+        bytes[..4].copy_from_slice(&crc.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Decode a record from exactly the bytes written by `encode` with the same cipher and
+    /// position.
+    pub fn decode(bytes: &[u8], cipher: Option<&FileCipher>, position: u64) -> Result<Self> {
+        let bytes = open_record(bytes, cipher, position)?;
+        let (key_len, value_len) = body_lens(&bytes[..RECORD_HEADER_LEN]);
+
+        let key_end = RECORD_HEADER_LEN + key_len;
+        let mut blob = None;
+        let mut codec = Codec::Raw;
+        let (kind, expires_at, value_start) = match bytes[12] {
+            1 => (RecordKind::Set, None, key_end),
+            2 => (RecordKind::Remove, None, key_end),
+            3 => (RecordKind::Batch, None, key_end),
+            EXPIRING_SET if value_len >= 8 => {
+                let expires_at = le_u64(&bytes[key_end..key_end + 8]);
+                (RecordKind::Set, Some(expires_at), key_end + 8)
+            }
+            BLOB_SET if value_len == BLOB_SET_VALUE_LEN => {
+                let value = &bytes[key_end..];
+                blob = Some(BlobPointer {
+                    file: le_u64(&value[8..16]),
+                    offset: le_u64(&value[16..24]),
+                    len: le_u64(&value[24..32]),
+                });
+                let expires_at = Some(le_u64(&value[..8])).filter(|&expires_at| expires_at != 0);
+                (RecordKind::Set, expires_at, bytes.len())
+            }
+            COMPRESSED_SET if value_len >= COMPRESSED_SET_HEADER_LEN => {
+                codec = match bytes[key_end] {
+                    1 => Codec::Zstd,
+                    _ => return Err(Error::from(ErrorKind::UnsupportedCodec)),
+                };
+                let expires_at = le_u64(&bytes[key_end + 1..key_end + COMPRESSED_SET_HEADER_LEN]);
+                let expires_at = Some(expires_at).filter(|&expires_at| expires_at != 0);
+                (
+                    RecordKind::Set,
+                    expires_at,
+                    key_end + COMPRESSED_SET_HEADER_LEN,
+                )
+            }
+            _ => return Err(Error::from(ErrorKind::CorruptedRecord)),
+        };
+        Ok(Record {
+            timestamp: le_u64(&bytes[4..12]),
+            kind,
+            key: bytes[RECORD_HEADER_LEN..key_end].to_vec(),
+            value: bytes[value_start..].to_vec(),
+            expires_at,
+            blob,
+            codec,
+        })
+    }
+
+    /// Split a batch record written at `batch_position` into its records, with the position of
+    /// each of them inside the batch record and their length.
+    pub fn split_batch(
+        self,
+        cipher: Option<&FileCipher>,
+        batch_position: u64,
+    ) -> Result<Vec<(Record, u64, u64)>> {
+        let mut records = Vec::new();
+        let mut position = RECORD_HEADER_LEN as u64;
+        let mut reader = Cursor::new(self.value);
+        // The checksum of the batch matched, so a damaged record inside it is not a torn write.
+        while let Some((record, len)) = read_record(&mut reader, cipher, batch_position + position)
+            .map_err(|_err| Error::from(ErrorKind::CorruptedRecord))?
+        {
+            if record.kind == RecordKind::Batch {
+                return Err(Error::from(ErrorKind::CorruptedRecord));
+            }
+            records.push((record, position, len));
+            position += len;
+        }
+        Ok(records)
+    }
+}
+
+// Bytes of the records of one write, with the position and length of each record in them.
+type EncodedEntry = (Vec<u8>, Vec<(u64, u64)>);
+
+/// Encode the records of one write: a single record as is, and several records inside a batch
+/// record, so that they are replayed all together or not at all. The position of each record
+/// inside the returned bytes is returned along with its length.
+///
+/// The bytes are written at `position` of a segment encrypted with `cipher`, if any.
+pub fn encode_entry(
+    records: &[Record],
+    cipher: Option<&FileCipher>,
+    position: u64,
+) -> Result<EncodedEntry> {
+    if let [record] = records {
+        let bytes = record.encode(cipher, position)?;
+        let len = bytes.len() as u64;
+        return Ok((bytes, vec![(0, len)]));
+    }
+
+    let mut value = Vec::new();
+    let mut positions = Vec::with_capacity(records.len());
+    for record in records {
+        let record_position = (RECORD_HEADER_LEN + value.len()) as u64;
+        let bytes = record.encode(cipher, position + record_position)?;
+        positions.push((record_position, bytes.len() as u64));
+        value.extend_from_slice(&bytes);
+    }
+    let batch = Record {
+        timestamp: now_millis(),
+        kind: RecordKind::Batch,
+        key: Vec::new(),
+        value,
+        expires_at: None,
+        blob: None,
+        codec: Codec::Raw,
+    };
+    Ok((batch.encode(cipher, position)?, positions))
+}
+
+/// Check that the records of one write fit in the record format before anything is written,
+/// see `MAX_FIELD_LEN`. Several records must also fit together in the value of their batch
+/// record.
+pub fn check_entry(records: &[Record]) -> Result<()> {
+    let mut batch_len = 0;
+    for record in records {
+        if record.key.len() > MAX_FIELD_LEN || record.value.len() > MAX_FIELD_LEN {
+            return Err(Error::from(ErrorKind::DataTooLarge));
+        }
+        let max_overhead = RECORD_HEADER_LEN + COMPRESSED_SET_HEADER_LEN + TAG_LEN;
+        batch_len += (max_overhead + record.key.len() + record.value.len()) as u64;
+    }
+    if records.len() > 1 && batch_len > u64::from(u32::MAX) {
+        return Err(Error::from(ErrorKind::DataTooLarge));
+    }
+    Ok(())
+}
+
+/// Read the next record of a segment, found at `position`, returning it together with its
+/// encoded length.
+///
+/// `Ok(None)` is returned when the reader ends exactly at a record boundary.
+pub fn read_record<R: Read>(
+    reader: &mut R,
+    cipher: Option<&FileCipher>,
+    position: u64,
+) -> Result<Option<(Record, u64)>> {
+    let mut bytes = vec![0; RECORD_HEADER_LEN];
+    match read_full(reader, &mut bytes)? {
+        0 => return Ok(None),
+        RECORD_HEADER_LEN => {}
+        _ => return Err(Error::from(ErrorKind::TornRecord)),
+    }
+
+    // The lengths of a torn header can be garbage, so the body is not allocated up front.
+    let (key_len, value_len) = body_lens(&bytes);
+    let body_len = (key_len + value_len) as u64;
+    let read = reader
+        .by_ref()
+        .take(body_len)
+        .read_to_end(&mut bytes)
+        .map_err(|_err| Error::from(ErrorKind::FileError))?;
+    if read as u64 != body_len {
+        return Err(Error::from(ErrorKind::TornRecord));
+    }
+
+    let len = bytes.len() as u64;
+    Record::decode(&bytes, cipher, position).map(|record| Some((record, len)))
+}
+
+/// Read the header of a segment and return its length with the cipher of the segment, found
+/// in `keyring`, when its records are encrypted.
+pub fn read_segment_header<R: Read>(
+    reader: &mut R,
+    keyring: &Keyring,
+) -> Result<(u64, Option<FileCipher>)> {
+    let mut header = [0; SEGMENT_HEADER_LEN as usize];
+    if read_full(reader, &mut header)? != header.len() {
+        return Err(Error::from(ErrorKind::TornRecord));
+    }
+    if &header[..3] != SEGMENT_MAGIC {
+        return Err(Error::from(ErrorKind::CorruptedRecord));
+    }
+    match header[3] {
+        FORMAT_VERSION => Ok((SEGMENT_HEADER_LEN, None)),
+        ENCRYPTED_FORMAT_VERSION => {
+            let mut cipher = [0; CIPHER_HEADER_LEN];
+            if read_full(reader, &mut cipher)? != cipher.len() {
+                return Err(Error::from(ErrorKind::TornRecord));
+            }
+            let header_len = SEGMENT_HEADER_LEN + CIPHER_HEADER_LEN as u64;
+            Ok((header_len, Some(keyring.cipher(&cipher)?)))
+        }
+        version => Err(Error::from(ErrorKind::InvalidFormatVersion(version))),
+    }
+}
+
+/// Upper bound of the size of `size` bytes of records once resealed by `reseal_record`: each
+/// record gains at most a tag, and holds at least a record header.
+pub fn max_resealed_size(size: u64) -> u64 {
+    size + size / RECORD_HEADER_LEN as u64 * TAG_LEN as u64
+}
+
+/// Encode again the record `bytes` read at `from` of a segment encrypted with `from_cipher`, if
+/// any, to be written at `to` of a segment encrypted with `to_cipher`, if any.
+pub fn reseal_record(
+    bytes: &[u8],
+    (from_cipher, from): (Option<&FileCipher>, u64),
+    (to_cipher, to): (Option<&FileCipher>, u64),
+) -> Result<Vec<u8>> {
+    let bytes = open_record(bytes, from_cipher, from)?.into_owned();
+    match to_cipher {
+        Some(cipher) if bytes[12] != RecordKind::Batch as u8 => seal_record(bytes, cipher, to),
+        _ => Ok(bytes),
+    }
+}
+
+// Encrypt the key and value of the plain record `bytes`, written at `position`.
+fn seal_record(mut bytes: Vec<u8>, cipher: &FileCipher, position: u64) -> Result<Vec<u8>> {
+    let mut body = bytes.split_off(RECORD_HEADER_LEN);
+    let value_len = le_u32(&bytes[17..21]) as usize + TAG_LEN;
+    bytes[17..21].copy_from_slice(&encode_len(value_len)?);
+    cipher.seal(position, &bytes[4..], &mut body);
+    bytes.extend_from_slice(&body);
+    let crc = checksum(&bytes[4..]);
+    bytes[..4].copy_from_slice(&crc.to_le_bytes());
+    Ok(bytes)
+}
+
+// Check the length and checksum of the record `bytes`, read at `position`, and decrypt it when
+// it is encrypted, returning the bytes of the same record in a plain segment.
+fn open_record<'a>(
+    bytes: &'a [u8],
+    cipher: Option<&FileCipher>,
+    position: u64,
+) -> Result<Cow<'a, [u8]>> {
+    if bytes.len() < RECORD_HEADER_LEN {
+        return Err(Error::from(ErrorKind::TornRecord));
+    }
+    let (key_len, value_len) = body_lens(&bytes[..RECORD_HEADER_LEN]);
+    if bytes.len() as u64 != (RECORD_HEADER_LEN + key_len) as u64 + value_len as u64 {
+        return Err(Error::from(ErrorKind::TornRecord));
+    }
+    if le_u32(&bytes[..4]) != checksum(&bytes[4..]) {
+        return Err(Error::from(ErrorKind::CorruptedRecord));
+    }
+    let cipher = match cipher {
+        Some(cipher) if bytes[12] != RecordKind::Batch as u8 => cipher,
+        _ => return Ok(Cow::Borrowed(bytes)),
+    };
+    if value_len < TAG_LEN {
+        return Err(Error::from(ErrorKind::CorruptedRecord));
+    }
+
+    let mut body = bytes[RECORD_HEADER_LEN..].to_vec();
+    cipher.open(position, &bytes[4..RECORD_HEADER_LEN], &mut body)?;
+    let mut plain = Vec::with_capacity(RECORD_HEADER_LEN + body.len());
+    plain.extend_from_slice(&bytes[..17]);
+    plain.extend_from_slice(&((value_len - TAG_LEN) as u32).to_le_bytes());
+    plain.extend_from_slice(&body);
+    let crc = checksum(&plain[4..]);
+    plain[..4].copy_from_slice(&crc.to_le_bytes());
+    Ok(Cow::Owned(plain))
+}
+
+#[cfg(feature = "compression")]
+fn decompress(value: &[u8]) -> Result<Vec<u8>> {
+    zstd::decode_all(value).map_err(|_err| Error::from(ErrorKind::CorruptedRecord))
+}
+
+// Records compressed by a build with the `compression` feature can still be replayed, only
+// their values can not be read.
+#[cfg(not(feature = "compression"))]
+fn decompress(_value: &[u8]) -> Result<Vec<u8>> {
+    Err(Error::from(ErrorKind::UnsupportedCodec))
+}
+
+// Length of a key or value as written in a record header.
+fn encode_len(len: usize) -> Result<[u8; 4]> {
+    match u32::try_from(len) {
+        Ok(len) => Ok(len.to_le_bytes()),
+        Err(_err) => Err(Error::from(ErrorKind::DataTooLarge)),
+    }
+}
+
+fn body_lens(header: &[u8]) -> (usize, usize) {
+    (
+        le_u32(&header[13..17]) as usize,
+        le_u32(&header[17..21]) as usize,
+    )
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(bytes);
+    hasher.finalize()
+}
+
+#[inline]
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[inline]
+fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+/// Like `read_exact` but returns how many bytes were read when the reader ends early.
+pub fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_err) => return Err(Error::from(ErrorKind::FileError)),
+        }
+    }
+    Ok(read)
+}
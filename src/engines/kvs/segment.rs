@@ -0,0 +1,286 @@
+use super::cipher::{FileCipher, Keyring};
+use super::record::{read_segment_header, segment_header, SEGMENT_MAGIC};
+use crate::error::{Error, ErrorKind, Result};
+
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Extension used by every segment file of the log.
+pub const SEGMENT_EXTENSION: &str = "log";
+
+/// Name of the single log file used before the log was split in segments.
+pub const LEGACY_LOG_NAME: &str = "kvs.log";
+
+/// Extension of a segment being written by the compaction.
+pub const COMPACTION_EXTENSION: &str = "compact";
+
+/// Extension of a legacy segment being rewritten with the binary format.
+pub const UPGRADE_EXTENSION: &str = "upgrade";
+
+/// Path of the segment `id` inside `dir`.
+pub fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.{}", id, SEGMENT_EXTENSION))
+}
+
+/// Sorted list of the ids of the segments stored in `dir`.
+pub fn segment_ids(dir: &Path) -> Result<Vec<u64>> {
+    let mut ids: Vec<u64> = fs::read_dir(dir)
+        .map_err(|_err| Error::from(ErrorKind::FileError))?
+        .flat_map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension() == Some(SEGMENT_EXTENSION.as_ref()))
+        .flat_map(|path| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::parse::<u64>)
+        })
+        .flatten()
+        .collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Path where the segment `id` is written before being renamed to its final path.
+pub fn temporary_path(dir: &Path, id: u64, extension: &str) -> PathBuf {
+    segment_path(dir, id).with_extension(extension)
+}
+
+/// Remove the temporary segments left behind by a compaction or an upgrade that did not finish.
+pub fn remove_temporary_segments(dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(|_err| Error::from(ErrorKind::FileError))? {
+        let path = entry
+            .map_err(|_err| Error::from(ErrorKind::FileError))?
+            .path();
+        let extension = path.extension();
+        if extension == Some(COMPACTION_EXTENSION.as_ref())
+            || extension == Some(UPGRADE_EXTENSION.as_ref())
+        {
+            fs::remove_file(path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+        }
+    }
+    Ok(())
+}
+
+/// Cut the segment `id` at `len` bytes.
+pub fn truncate_segment(dir: &Path, id: u64, len: u64) -> Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(segment_path(dir, id))
+        .and_then(|file| file.set_len(len))
+        .map_err(|_err| Error::from(ErrorKind::FileError))
+}
+
+/// Size in bytes of the segment `id`.
+pub fn segment_size(dir: &Path, id: u64) -> Result<u64> {
+    fs::metadata(segment_path(dir, id))
+        .map(|metadata| metadata.len())
+        .map_err(|_err| Error::from(ErrorKind::FileError))
+}
+
+/// Open a buffered reader over the segment `id`.
+pub fn open_reader(dir: &Path, id: u64, capacity: usize) -> Result<BufReader<File>> {
+    let file =
+        File::open(segment_path(dir, id)).map_err(|_err| Error::from(ErrorKind::FileError))?;
+    Ok(BufReader::with_capacity(capacity, file))
+}
+
+/// Open the segment `id` for positional reads, finding the cipher of its records in `keyring`.
+pub fn open_segment(dir: &Path, id: u64, keyring: &Keyring) -> Result<Arc<LogFile>> {
+    let mut file =
+        File::open(segment_path(dir, id)).map_err(|_err| Error::from(ErrorKind::FileError))?;
+    let (_header_len, cipher) = read_segment_header(&mut file, keyring)?;
+    Ok(Arc::new(LogFile::new(file, cipher)))
+}
+
+/// Segment or blob file opened for positional reads, with the cipher of its records when they
+/// are encrypted.
+///
+/// A file that is no longer written can be mapped in memory, see `mapped`.
+pub struct LogFile {
+    pub file: File,
+    pub cipher: Option<FileCipher>,
+    map: Option<Mmap>,
+}
+
+impl LogFile {
+    pub fn new(file: File, cipher: Option<FileCipher>) -> Self {
+        LogFile {
+            file,
+            cipher,
+            map: None,
+        }
+    }
+
+    /// The same file mapped in memory, so reads are copies out of the mapping instead of
+    /// system calls.
+    ///
+    /// The file must not be written anymore: the store only maps the segments sealed for good,
+    /// which are never modified again, only deleted once the compaction merged them. The
+    /// handles still reading a deleted segment keep its mapping alive.
+    pub fn mapped(&self) -> Result<Arc<LogFile>> {
+        let file = self
+            .file
+            .try_clone()
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        let map = unsafe { Mmap::map(&file) }.map_err(|_err| Error::from(ErrorKind::FileError))?;
+        Ok(Arc::new(LogFile {
+            file,
+            cipher: self.cipher.clone(),
+            map: Some(map),
+        }))
+    }
+
+    /// Id of the key encrypting the records of the file, if any.
+    pub fn key_id(&self) -> Option<u32> {
+        self.cipher.as_ref().map(FileCipher::key_id)
+    }
+
+    /// Read the `len` bytes written at `offset`, borrowed from the mapping of a mapped file.
+    pub fn read_at(&self, offset: u64, len: u64) -> Result<Cow<'_, [u8]>> {
+        if let Some(map) = &self.map {
+            // Bytes past the end of the mapping fail like a short read.
+            return offset
+                .checked_add(len)
+                .and_then(|end| map.get(offset as usize..end as usize))
+                .map(Cow::Borrowed)
+                .ok_or_else(|| Error::from(ErrorKind::FileError));
+        }
+        let mut bytes = vec![0; len as usize];
+        read_exact_at(&self.file, &mut bytes, offset)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        Ok(Cow::Owned(bytes))
+    }
+}
+
+/// Read exactly `buf.len()` bytes at `offset` without using the file cursor, so a single
+/// handle can be shared by every reading thread.
+#[cfg(unix)]
+pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+/// Read exactly `buf.len()` bytes at `offset` without using the file cursor, so a single
+/// handle can be shared by every reading thread.
+#[cfg(windows)]
+pub fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Whether the segment `id` was written with the original one JSON command per line format.
+pub fn is_legacy(dir: &Path, id: u64) -> Result<bool> {
+    let file =
+        File::open(segment_path(dir, id)).map_err(|_err| Error::from(ErrorKind::FileError))?;
+    let mut magic = Vec::with_capacity(SEGMENT_MAGIC.len());
+    file.take(SEGMENT_MAGIC.len() as u64)
+        .read_to_end(&mut magic)
+        .map_err(|_err| Error::from(ErrorKind::FileError))?;
+    Ok(!magic.is_empty() && magic[..] != SEGMENT_MAGIC[..magic.len()])
+}
+
+/// Writer that appends records at the end of a segment.
+pub struct SegmentWriter {
+    pub id: u64,
+    pub offset: u64,
+    /// Cipher of the records of the segment, when they are encrypted.
+    pub cipher: Option<FileCipher>,
+    writer: BufWriter<File>,
+    // Whether bytes were appended since the last sync.
+    unsynced: bool,
+}
+
+impl SegmentWriter {
+    /// Open the segment `id` for appending, creating it with a header if it does not exist.
+    ///
+    /// A segment encrypted with `cipher` must not exist yet: appending to the segment of
+    /// another process could reuse the nonces of the records it wrote last, see `FileCipher`.
+    pub fn open(dir: &Path, id: u64, cipher: Option<FileCipher>) -> Result<Self> {
+        Self::open_path(&segment_path(dir, id), id, cipher)
+    }
+
+    /// Same as `open` for a segment stored somewhere else than its final path.
+    pub fn open_path(path: &Path, id: u64, cipher: Option<FileCipher>) -> Result<Self> {
+        let header = segment_header(cipher.as_ref());
+        Self::open_with_header(path, id, &header, cipher)
+    }
+
+    /// Same as `open_path` for a file starting with another header than the segments.
+    pub fn open_with_header(
+        path: &Path,
+        id: u64,
+        header: &[u8],
+        cipher: Option<FileCipher>,
+    ) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        let offset = file
+            .metadata()
+            .map_err(|_err| Error::from(ErrorKind::FileError))?
+            .len();
+        if offset != 0 && cipher.is_some() {
+            return Err(Error::from(ErrorKind::FileError));
+        }
+        let mut writer = SegmentWriter {
+            id,
+            offset,
+            cipher,
+            writer: BufWriter::new(file),
+            unsynced: false,
+        };
+        if offset == 0 {
+            writer.append(header)?;
+            writer.flush()?;
+        }
+        Ok(writer)
+    }
+
+    /// Append `bytes` to the segment and return the offset where they were written.
+    pub fn append(&mut self, bytes: &[u8]) -> Result<u64> {
+        let offset = self.offset;
+        self.writer
+            .write_all(bytes)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        self.offset += bytes.len() as u64;
+        self.unsynced = true;
+        Ok(offset)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .map_err(|_err| Error::from(ErrorKind::FileError))
+    }
+
+    /// Flush the segment and wait until its content reaches the disk. Nothing is done when
+    /// nothing was appended since the last sync.
+    pub fn sync(&mut self) -> Result<()> {
+        if !self.unsynced {
+            return Ok(());
+        }
+        self.flush()?;
+        self.writer
+            .get_ref()
+            .sync_data()
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        self.unsynced = false;
+        Ok(())
+    }
+}
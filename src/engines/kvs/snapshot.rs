@@ -0,0 +1,75 @@
+use super::{locate, read_value, value_file, Index, Readers};
+use crate::engines::scan::prefix_bounds;
+use crate::engines::KvsSnapshot;
+use crate::error::Result;
+
+use std::ops::Bound;
+
+/// Read-only view of a `KvStore` at the time `KvsEngine::snapshot` was called.
+///
+/// The snapshot holds a copy of the index and the handles of the segments and blob files it
+/// points to. A segment merged by the compaction or a blob file rewritten by the collector
+/// while the snapshot is alive is removed from the store directory, but stays readable
+/// through these handles until the snapshot is dropped.
+///
+/// Keys are expired as of the time the snapshot was taken, a key expiring afterwards stays
+/// readable through the snapshot.
+pub struct KvStoreSnapshot {
+    index: Index,
+    readers: Readers,
+    blobs: Readers,
+    taken_at: u64,
+}
+
+impl KvStoreSnapshot {
+    pub(super) fn new(index: Index, readers: Readers, blobs: Readers, taken_at: u64) -> Self {
+        KvStoreSnapshot {
+            index,
+            readers,
+            blobs,
+            taken_at,
+        }
+    }
+
+    fn read_range(
+        &self,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        locate(
+            &self.index,
+            &self.readers,
+            &self.blobs,
+            bounds,
+            limit,
+            self.taken_at,
+        )?
+        .into_iter()
+        .map(|(key, file, entry)| Ok((key, read_value(&file, &entry)?)))
+        .collect()
+    }
+}
+
+impl KvsSnapshot for KvStoreSnapshot {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let entry = match self.index.get(&key) {
+            Some(entry) if !entry.is_expired(self.taken_at) => entry,
+            _ => return Ok(None),
+        };
+        let file = value_file(&self.readers, &self.blobs, entry)?;
+        read_value(&file, entry).map(Some)
+    }
+
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range((Bound::Included(start), Bound::Excluded(end)), limit)
+    }
+
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range(prefix_bounds(&prefix, after), limit)
+    }
+}
@@ -0,0 +1,68 @@
+use super::record::Record;
+use super::{read_value, Inner, LogEntry, Reads};
+use crate::engines::Transaction;
+use crate::error::Result;
+
+use std::collections::BTreeMap;
+
+/// Transaction of a `KvStore`, see `KvsEngine::transaction`.
+///
+/// Reads go to the store and remember the version of each key read, writes are buffered until
+/// the transaction is written as a single `LogEntry` carrying those versions.
+pub(super) struct KvTransaction<'a> {
+    inner: &'a Inner,
+    reads: Reads,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> KvTransaction<'a> {
+    pub fn new(inner: &'a Inner) -> Self {
+        KvTransaction {
+            inner,
+            reads: BTreeMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Entry committing the transaction, with the number of writes it holds.
+    pub fn into_entry(self) -> (LogEntry, u64) {
+        let records: Vec<_> = self
+            .writes
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => Record::set(key, value),
+                None => Record::remove(key),
+            })
+            .collect();
+        let operations = records.len() as u64;
+        let entry = LogEntry {
+            records,
+            reads: self.reads,
+        };
+        (entry, operations)
+    }
+}
+
+impl<'a> Transaction for KvTransaction<'a> {
+    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.get(&key) {
+            return Ok(value.clone());
+        }
+        let (value, version) = match self.inner.lookup(&key)? {
+            Some((file, entry)) => (Some(read_value(&file, &entry)?), Some(entry.version)),
+            None => (None, None),
+        };
+        self.reads.entry(key).or_insert(version);
+        Ok(value)
+    }
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.writes.insert(key, Some(value));
+        Ok(())
+    }
+
+    fn remove(&mut self, key: Vec<u8>) -> Result<()> {
+        self.writes.insert(key, None);
+        Ok(())
+    }
+}
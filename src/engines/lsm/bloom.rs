@@ -0,0 +1,79 @@
+use super::entry::le_u32;
+use crate::error::{Error, ErrorKind, Result};
+
+/// Bloom filter of the keys of a table, which lets a get skip the tables that can not hold
+/// its key without reading them.
+///
+/// The filter is encoded as the number of hash functions as a `u32` followed by the bits.
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Build the filter of the keys whose hashes are `hashes`, see `key_hash`, with about
+    /// `bits_per_key` bits for each of them.
+    pub fn build(hashes: &[u64], bits_per_key: usize) -> Self {
+        // ln(2) times the bits per key minimizes the false positive rate.
+        let functions = ((bits_per_key as f64 * 0.69) as u32).clamp(1, 30);
+        let len = (hashes.len() * bits_per_key).max(64).div_ceil(8);
+        let mut filter = BloomFilter {
+            bits: vec![0; len],
+            hashes: functions,
+        };
+        for &hash in hashes {
+            for bit in filter.positions(hash) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    /// Whether the key whose hash is `hash` may be in the table. A `false` is always right.
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.positions(hash)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.bits.len());
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        bytes.extend_from_slice(&self.bits);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() <= 4 {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        Ok(BloomFilter {
+            hashes: le_u32(&bytes[..4]),
+            bits: bytes[4..].to_vec(),
+        })
+    }
+
+    // Bits of the key whose hash is `hash`, derived from both halves of the hash by double
+    // hashing.
+    fn positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let len = (self.bits.len() * 8) as u64;
+        let (low, high) = (hash & 0xffff_ffff, hash >> 32);
+        (0..u64::from(self.hashes))
+            .map(move |i| (low.wrapping_add(i.wrapping_mul(high)) % len) as usize)
+    }
+}
+
+/// Hash of `key` used by the bloom filters: a 64 bit FNV-1a, mixed so that both halves
+/// depend on every byte of the key.
+///
+/// The filters are stored in the tables, so this function must never change.
+pub fn key_hash(key: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in key {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash
+}
@@ -0,0 +1,334 @@
+use super::entry::{is_expired, Entry};
+use super::merge::{MergeIter, Source};
+use super::options::{CompactionStyle, LsmStoreOptions};
+use super::table::{table_path, Table, TableBuilder};
+use super::wal::wal_path;
+use super::{run_source, Inner, Levels, Run, LSM_LEVELS};
+use crate::engines::expiry::now_millis;
+use crate::error::{Error, ErrorKind, Result};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Delay before retrying a failed flush, doubled after every failure up to `MAX_FLUSH_RETRY`.
+// The writes do not trigger the thread again while a memtable is frozen, so the flush has to
+// be retried without them.
+const MIN_FLUSH_RETRY: Duration = Duration::from_millis(100);
+const MAX_FLUSH_RETRY: Duration = Duration::from_secs(5);
+
+/// Handle of the thread that flushes the frozen memtables of an `LsmStore` and compacts its
+/// tables.
+///
+/// Dropping it, which happens when the last handle of the store is dropped, stops the thread
+/// after waiting for the flush or compaction in progress. A memtable which was not flushed
+/// yet is replayed from its write-ahead log when the store is opened again. A failed flush is
+/// retried after a growing delay, while the active memtable takes the writes.
+pub struct Compactor {
+    sender: Mutex<Option<Sender<()>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Compactor {
+    pub fn spawn(inner: Arc<Inner>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            // Last key compacted out of each level, so the leveled compaction goes round them.
+            let mut cursors = vec![Vec::new(); LSM_LEVELS];
+            let mut retry = None;
+            loop {
+                let woken = match retry {
+                    Some(delay) => match receiver.recv_timeout(delay) {
+                        Ok(()) | Err(RecvTimeoutError::Timeout) => true,
+                        Err(RecvTimeoutError::Disconnected) => false,
+                    },
+                    None => receiver.recv().is_ok(),
+                };
+                if !woken {
+                    break;
+                }
+                while receiver.try_recv().is_ok() {}
+                if let Err(err) = flush(&inner) {
+                    error!(inner.options.logger, "Memtable flush failed: {}", err);
+                    retry = Some(retry.map_or(MIN_FLUSH_RETRY, |delay: Duration| {
+                        (delay * 2).min(MAX_FLUSH_RETRY)
+                    }));
+                    continue;
+                }
+                retry = None;
+                loop {
+                    match compact(&inner, &mut cursors) {
+                        Ok(true) => {}
+                        Ok(false) => break,
+                        Err(err) => {
+                            error!(inner.options.logger, "Table compaction failed: {}", err);
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Compactor {
+            sender: Mutex::new(Some(sender)),
+            handle: Some(handle),
+        }
+    }
+
+    /// Ask the thread to flush the frozen memtable and compact the tables.
+    pub fn trigger(&self) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            let _ = sender.send(());
+        }
+    }
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        self.sender.lock().unwrap().take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Write the entries of a memtable, in key order, to the new table `id`.
+pub fn write_table<'a>(
+    dir: &Path,
+    id: u64,
+    entries: impl Iterator<Item = (&'a Vec<u8>, &'a Entry)>,
+    options: &LsmStoreOptions,
+) -> Result<Table> {
+    let mut builder =
+        TableBuilder::create(dir, id, options.block_size, options.bloom_bits_per_key)?;
+    for (key, entry) in entries {
+        builder.add(key, entry)?;
+    }
+    builder.finish()
+}
+
+// Write the frozen memtable to a table of level 0 and remove its write-ahead log.
+//
+// The levels are only modified by this thread, so they can be read, rewritten and swapped
+// without holding the state lock in the meantime.
+fn flush(inner: &Inner) -> Result<()> {
+    let (wal_id, memtable, levels) = {
+        let state = inner.state.read().unwrap();
+        match &state.frozen {
+            Some((id, memtable)) => (*id, memtable.clone(), state.levels.clone()),
+            None => return Ok(()),
+        }
+    };
+
+    let mut levels = (*levels).clone();
+    if !memtable.is_empty() {
+        let id = inner.next_id.fetch_add(1, Ordering::SeqCst);
+        let table = write_table(&inner.path, id, memtable.iter(), &inner.options)?;
+        levels[0].insert(0, vec![Arc::new(table)]);
+    }
+    // Every later log has a greater id, the ids in between belong to tables.
+    inner.log_id.store(wal_id + 1, Ordering::SeqCst);
+    inner.write_manifest(&levels)?;
+    {
+        let mut state = inner.state.write().unwrap();
+        state.levels = Arc::new(levels);
+        state.frozen = None;
+    }
+    fs::remove_file(wal_path(&inner.path, wal_id)).map_err(|_err| Error::from(ErrorKind::FileError))
+}
+
+// Tables merged by one compaction.
+struct Compaction {
+    // Runs merged together, from the newest to the oldest
+    inputs: Vec<Run>,
+    output_level: usize,
+    // Whether no older data can hold the keys of the inputs, which lets the merge drop the
+    // tombstones and the expired values instead of keeping them to hide older values.
+    bottom: bool,
+}
+
+// Run the next compaction needed by the levels, if any. Returns whether one was run.
+fn compact(inner: &Inner, cursors: &mut [Vec<u8>]) -> Result<bool> {
+    let levels = inner.state.read().unwrap().levels.clone();
+    let compaction = match inner.options.compaction_style {
+        CompactionStyle::Leveled {
+            level0_tables,
+            size_ratio,
+        } => pick_leveled(&levels, cursors, level0_tables, size_ratio, &inner.options),
+        CompactionStyle::Tiered { tier_width } => pick_tiered(&levels, tier_width),
+    };
+    let compaction = match compaction {
+        Some(compaction) => compaction,
+        None => return Ok(false),
+    };
+
+    let sources = compaction
+        .inputs
+        .iter()
+        .map(|run| run_source(run, Bound::Unbounded))
+        .collect();
+    let outputs = merge(inner, sources, compaction.bottom)?;
+
+    let merged: BTreeSet<u64> = compaction
+        .inputs
+        .iter()
+        .flatten()
+        .map(|table| table.id)
+        .collect();
+    let mut levels = (*levels).clone();
+    for level in levels.iter_mut() {
+        for run in level.iter_mut() {
+            run.retain(|table| !merged.contains(&table.id));
+        }
+        level.retain(|run| !run.is_empty());
+    }
+    let output = &mut levels[compaction.output_level];
+    match inner.options.compaction_style {
+        // The untouched tables of the level do not overlap the merged keys.
+        CompactionStyle::Leveled { .. } if compaction.output_level > 0 => {
+            let mut run = output.pop().unwrap_or_default();
+            run.extend(outputs);
+            run.sort_by(|a, b| a.first_key().cmp(b.first_key()));
+            output.push(run);
+        }
+        CompactionStyle::Leveled { .. } | CompactionStyle::Tiered { .. } => {
+            if !outputs.is_empty() {
+                output.insert(0, outputs);
+            }
+        }
+    }
+
+    inner.write_manifest(&levels)?;
+    inner.state.write().unwrap().levels = Arc::new(levels);
+    // Readers still searching the merged tables keep them open until they are done.
+    for id in merged {
+        fs::remove_file(table_path(&inner.path, id))
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+    }
+    Ok(true)
+}
+
+// With leveled compaction, merge level 0 into level 1 once it holds `level0_tables` tables,
+// or else one table of the first level over its size into the next level.
+fn pick_leveled(
+    levels: &Levels,
+    cursors: &mut [Vec<u8>],
+    level0_tables: usize,
+    size_ratio: u64,
+    options: &LsmStoreOptions,
+) -> Option<Compaction> {
+    if levels[0].len() >= level0_tables {
+        let tables: Vec<&Arc<Table>> = levels[0].iter().flatten().collect();
+        let start = tables.iter().map(|table| table.first_key()).min().unwrap();
+        let end = tables.iter().map(|table| table.last_key()).max().unwrap();
+        let mut inputs = levels[0].clone();
+        inputs.push(overlapping(&levels[1], start, end));
+        return Some(Compaction {
+            inputs,
+            output_level: 1,
+            bottom: levels[2..].iter().all(Vec::is_empty),
+        });
+    }
+
+    let mut capacity = options.table_size * level0_tables as u64;
+    for level in 1..LSM_LEVELS - 1 {
+        let run = match levels[level].first() {
+            Some(run) => run,
+            None => {
+                capacity = capacity.saturating_mul(size_ratio);
+                continue;
+            }
+        };
+        let size: u64 = run.iter().map(|table| table.size).sum();
+        if size <= capacity {
+            capacity = capacity.saturating_mul(size_ratio);
+            continue;
+        }
+        let table = run
+            .iter()
+            .find(|table| table.first_key() > &cursors[level][..])
+            .unwrap_or(&run[0]);
+        cursors[level] = table.last_key().to_vec();
+        let next = overlapping(&levels[level + 1], table.first_key(), table.last_key());
+        return Some(Compaction {
+            inputs: vec![vec![table.clone()], next],
+            output_level: level + 1,
+            bottom: levels[level + 2..].iter().all(Vec::is_empty),
+        });
+    }
+    None
+}
+
+// With tiered compaction, merge every run of the first level holding `tier_width` runs into
+// a single run of the next level. The runs of the last level are merged together.
+fn pick_tiered(levels: &Levels, tier_width: usize) -> Option<Compaction> {
+    let level = (0..LSM_LEVELS).find(|&level| levels[level].len() >= tier_width)?;
+    let output_level = (level + 1).min(LSM_LEVELS - 1);
+    let older_runs = if output_level == level {
+        0
+    } else {
+        levels[output_level].len()
+    };
+    Some(Compaction {
+        inputs: levels[level].clone(),
+        output_level,
+        bottom: older_runs == 0 && levels[output_level + 1..].iter().all(Vec::is_empty),
+    })
+}
+
+// Tables of the leveled run of `level` holding keys inside `[start, end]`.
+fn overlapping(level: &[Run], start: &[u8], end: &[u8]) -> Run {
+    level
+        .iter()
+        .flatten()
+        .filter(|table| table.overlaps(start, end))
+        .cloned()
+        .collect()
+}
+
+// Merge `sources` into new tables of about `table_size` bytes each.
+//
+// Expired values become tombstones, which still hide the older values of their key, unless
+// the merge is at the bottom of the tree where tombstones are dropped.
+fn merge(inner: &Inner, sources: Vec<Source>, bottom: bool) -> Result<Run> {
+    let now = now_millis();
+    let mut tables = Vec::new();
+    let mut builder: Option<TableBuilder> = None;
+    for item in MergeIter::new(sources) {
+        let (key, entry) = item?;
+        let entry = match entry {
+            Entry::Value { expires_at, .. } if is_expired(expires_at, now) => Entry::Tombstone,
+            entry => entry,
+        };
+        if bottom && entry == Entry::Tombstone {
+            continue;
+        }
+        let table = match &mut builder {
+            Some(builder) => builder,
+            None => {
+                let id = inner.next_id.fetch_add(1, Ordering::SeqCst);
+                let options = &inner.options;
+                builder.get_or_insert(TableBuilder::create(
+                    &inner.path,
+                    id,
+                    options.block_size,
+                    options.bloom_bits_per_key,
+                )?)
+            }
+        };
+        table.add(&key, &entry)?;
+        if table.size() >= inner.options.table_size {
+            tables.push(Arc::new(builder.take().unwrap().finish()?));
+        }
+    }
+    if let Some(builder) = builder {
+        tables.push(Arc::new(builder.finish()?));
+    }
+    Ok(tables)
+}
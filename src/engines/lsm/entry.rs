@@ -0,0 +1,142 @@
+use crate::error::{Error, ErrorKind, Result};
+
+use std::convert::{TryFrom, TryInto};
+
+// kind (1) + expiry time (8) + key length (4) + value length (4)
+const ENTRY_HEADER_LEN: usize = 17;
+
+const VALUE: u8 = 1;
+const TOMBSTONE: u8 = 2;
+
+/// Latest state of a key in a memtable or a table.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entry {
+    /// The key holds `value`, expiring at `expires_at` in milliseconds since the unix epoch.
+    Value {
+        value: Vec<u8>,
+        expires_at: Option<u64>,
+    },
+    /// The key was removed, which hides its older values until the compaction drops them.
+    Tombstone,
+}
+
+impl Entry {
+    pub fn value(value: Vec<u8>) -> Self {
+        Entry::Value {
+            value,
+            expires_at: None,
+        }
+    }
+
+    /// Value of the entry, `None` for a tombstone or a value expired at `now`.
+    pub fn live_value(&self, now: u64) -> Option<&[u8]> {
+        match self {
+            Entry::Value { value, expires_at } if !is_expired(*expires_at, now) => Some(value),
+            Entry::Value { .. } | Entry::Tombstone => None,
+        }
+    }
+
+    /// Number of bytes taken by the entry of `key` once encoded.
+    pub fn encoded_len(&self, key: &[u8]) -> usize {
+        let value_len = match self {
+            Entry::Value { value, .. } => value.len(),
+            Entry::Tombstone => 0,
+        };
+        ENTRY_HEADER_LEN + key.len() + value_len
+    }
+}
+
+/// Whether a key expiring at `expires_at` is expired at `now`.
+pub fn is_expired(expires_at: Option<u64>, now: u64) -> bool {
+    expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+/// Append the entry of `key` to `bytes`.
+///
+/// Entries are encoded as:
+///
+/// ```text
+/// kind       u8    1 = value, 2 = tombstone
+/// expires_at u64   milliseconds since the unix epoch, 0 for a key without expiry
+/// key_len    u32
+/// value_len  u32
+/// key        [u8; key_len]
+/// value      [u8; value_len]
+/// ```
+///
+/// Integers are little endian. The same encoding is used by the write-ahead log and by the
+/// data blocks of the tables. Fails with `DataTooLarge` when a length does not fit in a `u32`.
+pub fn encode_entry(bytes: &mut Vec<u8>, key: &[u8], entry: &Entry) -> Result<()> {
+    let (kind, value, expires_at): (_, &[u8], _) = match entry {
+        Entry::Value { value, expires_at } => (VALUE, value, *expires_at),
+        Entry::Tombstone => (TOMBSTONE, &[], None),
+    };
+    let (key_len, value_len) = (encode_len(key.len())?, encode_len(value.len())?);
+    bytes.push(kind);
+    bytes.extend_from_slice(&expires_at.unwrap_or(0).to_le_bytes());
+    bytes.extend_from_slice(&key_len);
+    bytes.extend_from_slice(&value_len);
+    bytes.extend_from_slice(key);
+    bytes.extend_from_slice(value);
+    Ok(())
+}
+
+/// Check that the entries of one write fit in the write-ahead log before anything is written:
+/// their encoding is logged after its length as a `u32`, which bounds every key and value too.
+pub fn check_entries(entries: &[(Vec<u8>, Entry)]) -> Result<()> {
+    let len: u64 = entries
+        .iter()
+        .map(|(key, entry)| entry.encoded_len(key) as u64)
+        .sum();
+    if len > u64::from(u32::MAX) {
+        return Err(Error::from(ErrorKind::DataTooLarge));
+    }
+    Ok(())
+}
+
+/// Length of a key, a value or a write as written in the header before it.
+pub fn encode_len(len: usize) -> Result<[u8; 4]> {
+    match u32::try_from(len) {
+        Ok(len) => Ok(len.to_le_bytes()),
+        Err(_err) => Err(Error::from(ErrorKind::DataTooLarge)),
+    }
+}
+
+/// Decode every entry of `bytes`, which must hold exactly a sequence of encoded entries.
+pub fn decode_entries(mut bytes: &[u8]) -> Result<Vec<(Vec<u8>, Entry)>> {
+    let mut entries = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < ENTRY_HEADER_LEN {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        let expires_at = le_u64(&bytes[1..9]);
+        let key_len = le_u32(&bytes[9..13]) as usize;
+        let value_len = le_u32(&bytes[13..17]) as usize;
+        let body = &bytes[ENTRY_HEADER_LEN..];
+        if body.len() < key_len + value_len {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        let key = body[..key_len].to_vec();
+        let entry = match bytes[0] {
+            VALUE => Entry::Value {
+                value: body[key_len..key_len + value_len].to_vec(),
+                expires_at: Some(expires_at).filter(|&expires_at| expires_at != 0),
+            },
+            TOMBSTONE => Entry::Tombstone,
+            _ => return Err(Error::from(ErrorKind::CorruptedRecord)),
+        };
+        entries.push((key, entry));
+        bytes = &body[key_len + value_len..];
+    }
+    Ok(entries)
+}
+
+#[inline]
+pub fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[inline]
+pub fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
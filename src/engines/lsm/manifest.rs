@@ -0,0 +1,115 @@
+use super::entry::{le_u32, le_u64};
+use crate::error::{Error, ErrorKind, Result};
+
+use crc32fast::Hasher;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+/// Name of the manifest file inside the store directory.
+pub const MANIFEST_NAME: &str = "MANIFEST";
+
+const MANIFEST_TEMPORARY_NAME: &str = "MANIFEST.tmp";
+const MANIFEST_MAGIC: &[u8; 3] = b"KVM";
+const MANIFEST_VERSION: u8 = 1;
+
+/// Tables making up the store, as the ids of the tables of each sorted run of each level.
+///
+/// The manifest is rewritten after every flush and every compaction, and the files that it
+/// does not mention are leftovers of an interrupted flush or compaction. It is laid out as:
+///
+/// ```text
+/// magic, version   "KVM", u8
+/// next_id          u64   id given to the next table or write-ahead log
+/// log_id           u64   oldest write-ahead log not flushed yet
+/// levels           u32   number of levels, then for each level:
+///   runs           u32   number of runs, newest first, then for each run:
+///     tables       u32   number of tables, then their ids as u64, in key order
+/// crc32            u32   checksum of every previous byte
+/// ```
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub next_id: u64,
+    pub log_id: u64,
+    pub levels: Vec<Vec<Vec<u64>>>,
+}
+
+impl Manifest {
+    /// Write the manifest under a temporary name, sync it and rename it over the current one.
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MANIFEST_MAGIC);
+        bytes.push(MANIFEST_VERSION);
+        bytes.extend_from_slice(&self.next_id.to_le_bytes());
+        bytes.extend_from_slice(&self.log_id.to_le_bytes());
+        bytes.extend_from_slice(&(self.levels.len() as u32).to_le_bytes());
+        for level in &self.levels {
+            bytes.extend_from_slice(&(level.len() as u32).to_le_bytes());
+            for run in level {
+                bytes.extend_from_slice(&(run.len() as u32).to_le_bytes());
+                for id in run {
+                    bytes.extend_from_slice(&id.to_le_bytes());
+                }
+            }
+        }
+        let mut hasher = Hasher::new();
+        hasher.update(&bytes);
+        bytes.extend_from_slice(&hasher.finalize().to_le_bytes());
+
+        let temporary = dir.join(MANIFEST_TEMPORARY_NAME);
+        File::create(&temporary)
+            .and_then(|mut file| file.write_all(&bytes).and_then(|_| file.sync_all()))
+            .and_then(|_| fs::rename(&temporary, dir.join(MANIFEST_NAME)))
+            .map_err(|_err| Error::from(ErrorKind::FileError))
+    }
+
+    /// Read the manifest of `dir`, `None` when the store has none yet.
+    pub fn read(dir: &Path) -> Result<Option<Manifest>> {
+        let path = dir.join(MANIFEST_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let bytes = fs::read(path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+        if bytes.len() < 24 + 4 {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        let (content, crc) = bytes.split_at(bytes.len() - 4);
+        let mut hasher = Hasher::new();
+        hasher.update(content);
+        if hasher.finalize() != le_u32(crc) || &content[..3] != MANIFEST_MAGIC {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        if content[3] != MANIFEST_VERSION {
+            return Err(Error::from(ErrorKind::InvalidFormatVersion(content[3])));
+        }
+
+        let mut rest = &content[4..];
+        let mut take = |len: usize| -> Result<&[u8]> {
+            if rest.len() < len {
+                return Err(Error::from(ErrorKind::CorruptedRecord));
+            }
+            let (taken, remaining) = rest.split_at(len);
+            rest = remaining;
+            Ok(taken)
+        };
+        let next_id = le_u64(take(8)?);
+        let log_id = le_u64(take(8)?);
+        let mut levels = Vec::new();
+        for _ in 0..le_u32(take(4)?) {
+            let mut level = Vec::new();
+            for _ in 0..le_u32(take(4)?) {
+                let mut run = Vec::new();
+                for _ in 0..le_u32(take(4)?) {
+                    run.push(le_u64(take(8)?));
+                }
+                level.push(run);
+            }
+            levels.push(level);
+        }
+        Ok(Some(Manifest {
+            next_id,
+            log_id,
+            levels,
+        }))
+    }
+}
@@ -0,0 +1,50 @@
+use super::entry::Entry;
+use crate::engines::scan::is_empty_range;
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/// Sorted in-memory table taking the writes until it is flushed to a table.
+#[derive(Clone, Default)]
+pub struct Memtable {
+    entries: BTreeMap<Vec<u8>, Entry>,
+    // Size of the entries once encoded, which is about the size of the flushed table
+    size: u64,
+}
+
+impl Memtable {
+    pub fn insert(&mut self, key: Vec<u8>, entry: Entry) {
+        self.size += entry.encoded_len(&key) as u64;
+        if let Some(replaced) = self.entries.get(&key) {
+            self.size -= replaced.encoded_len(&key) as u64;
+        }
+        self.entries.insert(key, entry);
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&Entry> {
+        self.entries.get(key)
+    }
+
+    /// Copy the entries inside `bounds`.
+    pub fn range(&self, bounds: &(Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Vec<(Vec<u8>, Entry)> {
+        if is_empty_range(bounds) {
+            return Vec::new();
+        }
+        self.entries
+            .range(bounds.clone())
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &Entry)> {
+        self.entries.iter()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
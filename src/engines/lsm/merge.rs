@@ -0,0 +1,71 @@
+use super::entry::Entry;
+use crate::error::Result;
+
+/// Source of entries in key order, used by `MergeIter`.
+pub type Source = Box<dyn Iterator<Item = Result<(Vec<u8>, Entry)>> + Send>;
+
+/// Iterator merging sources of entries into a single sequence in key order.
+///
+/// The sources are given from the newest to the oldest: when several of them hold the same
+/// key, only the entry of the newest one is returned.
+pub struct MergeIter {
+    sources: Vec<Source>,
+    heads: Vec<Option<(Vec<u8>, Entry)>>,
+    started: bool,
+}
+
+impl MergeIter {
+    pub fn new(sources: Vec<Source>) -> Self {
+        let heads = sources.iter().map(|_source| None).collect();
+        MergeIter {
+            sources,
+            heads,
+            started: false,
+        }
+    }
+
+    fn advance(&mut self, source: usize) -> Result<()> {
+        self.heads[source] = self.sources[source].next().transpose()?;
+        Ok(())
+    }
+}
+
+impl Iterator for MergeIter {
+    type Item = Result<(Vec<u8>, Entry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            for source in 0..self.sources.len() {
+                if let Err(err) = self.advance(source) {
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        // The first source holding the smallest key is the newest one.
+        let mut newest: Option<usize> = None;
+        for (source, head) in self.heads.iter().enumerate() {
+            if let Some((key, _entry)) = head {
+                match newest {
+                    Some(current) if self.heads[current].as_ref().unwrap().0 <= *key => {}
+                    _ => newest = Some(source),
+                }
+            }
+        }
+        let newest = newest?;
+        let (key, entry) = self.heads[newest].take().unwrap();
+        for source in newest..self.sources.len() {
+            let shadowed = match &self.heads[source] {
+                Some((other, _entry)) => *other == key,
+                None => source == newest,
+            };
+            if shadowed {
+                if let Err(err) = self.advance(source) {
+                    return Some(Err(err));
+                }
+            }
+        }
+        Some(Ok((key, entry)))
+    }
+}
@@ -0,0 +1,573 @@
+use crate::error::{Error, ErrorKind, Result};
+
+use super::expiry::{expiry_time, now_millis, time_left};
+use super::scan::{is_empty_range, prefix_bounds};
+use super::syncer::Syncer;
+use super::{add_delta, BatchOp, KvsEngine, SyncPolicy, Transaction, WriteBatch};
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::mem;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::Duration;
+
+mod bloom;
+mod compaction;
+mod entry;
+mod manifest;
+mod memtable;
+mod merge;
+mod options;
+mod snapshot;
+mod table;
+mod transaction;
+mod wal;
+
+pub use self::options::{CompactionStyle, LsmStoreOptions};
+pub use self::snapshot::LsmSnapshot;
+
+use self::compaction::{write_table, Compactor};
+use self::entry::{check_entries, Entry};
+use self::manifest::{Manifest, MANIFEST_NAME};
+use self::memtable::Memtable;
+use self::merge::{MergeIter, Source};
+use self::table::{table_path, Table, TableIter, TABLE_EXTENSION, TEMPORARY_EXTENSION};
+use self::transaction::LsmTransaction;
+use self::wal::{replay_wal, wal_path, Wal, WAL_EXTENSION};
+
+/// Number of levels of the tree. The last level is never merged into another one.
+pub const LSM_LEVELS: usize = 7;
+
+// Sorted run of tables whose keys do not overlap, ordered by key.
+type Run = Vec<Arc<Table>>;
+// Runs of each level, from the newest to the oldest.
+type Levels = Vec<Vec<Run>>;
+
+/// Key-Value store built as a log-structured merge tree.
+///
+/// Writes go to a write-ahead log and to a sorted memtable. Once the memtable is big enough
+/// it is flushed to an immutable table (`<id>.sst`) of level 0, and a background thread
+/// merges the tables into the deeper levels, see `CompactionStyle`. The `MANIFEST` file lists
+/// the tables of each level.
+///
+/// Unlike `KvStore`, the keys are not indexed in memory: only the memtable, the first key of
+/// each block of the tables and their bloom filters are, so the keys do not need to fit in
+/// memory. Writes are serialized, and transactions run one at a time, so they never conflict.
+#[derive(Clone)]
+pub struct LsmStore {
+    inner: Arc<Inner>,
+    compactor: Arc<Compactor>,
+    // Only held to stop the sync thread along with the last handle of the store, which syncs
+    // the last writes. Dropped after the compactor, so nothing is written after that sync.
+    _syncer: Option<Arc<Syncer>>,
+}
+
+// State of the store read by every operation.
+struct State {
+    memtable: Memtable,
+    // Memtable being flushed, with the id of its write-ahead log
+    frozen: Option<(u64, Arc<Memtable>)>,
+    // Only replaced as a whole by the flushes and the compactions, so readers can search the
+    // tables without holding the lock.
+    levels: Arc<Levels>,
+}
+
+// State of the store shared between its handles and its background threads.
+struct Inner {
+    state: RwLock<State>,
+    // Held by every write, so the order of the write-ahead log is the order of the memtable.
+    wal: Mutex<Wal>,
+    // Id given to the next table or write-ahead log
+    next_id: AtomicU64,
+    // Oldest write-ahead log which is not flushed yet
+    log_id: AtomicU64,
+    path: PathBuf,
+    options: LsmStoreOptions,
+}
+
+impl Inner {
+    // Find the latest entry of `key`. The tables are searched once the state is unlocked.
+    fn lookup(&self, key: &[u8]) -> Result<Option<Entry>> {
+        let levels = {
+            let state = self.state.read().unwrap();
+            let frozen = state.frozen.as_ref().map(|(_id, memtable)| &**memtable);
+            for memtable in Some(&state.memtable).into_iter().chain(frozen) {
+                if let Some(entry) = memtable.get(key) {
+                    return Ok(Some(entry.clone()));
+                }
+            }
+            state.levels.clone()
+        };
+        lookup_levels(&levels, key)
+    }
+
+    // Get the value of `key` with its expiry time, `None` when it is missing or expired.
+    fn live_entry(&self, key: &[u8]) -> Result<Option<(Vec<u8>, Option<u64>)>> {
+        match self.lookup(key)? {
+            Some(Entry::Value { value, expires_at })
+                if !entry::is_expired(expires_at, now_millis()) =>
+            {
+                Ok(Some((value, expires_at)))
+            }
+            Some(_) | None => Ok(None),
+        }
+    }
+
+    fn range(
+        &self,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let (memtables, levels) = {
+            let state = self.state.read().unwrap();
+            let mut memtables = vec![state.memtable.range(&bounds)];
+            if let Some((_id, frozen)) = &state.frozen {
+                memtables.push(frozen.range(&bounds));
+            }
+            (memtables, state.levels.clone())
+        };
+        read_range(memtables, &levels, bounds, limit, now_millis())
+    }
+
+    fn snapshot(&self) -> LsmSnapshot {
+        let state = self.state.read().unwrap();
+        let mut memtables = vec![Arc::new(state.memtable.clone())];
+        if let Some((_id, frozen)) = &state.frozen {
+            memtables.push(frozen.clone());
+        }
+        LsmSnapshot::new(memtables, state.levels.clone(), now_millis())
+    }
+
+    fn lock_wal(&self) -> MutexGuard<'_, Wal> {
+        self.wal.lock().unwrap()
+    }
+
+    // Append the entries of one write to the write-ahead log, then to the memtable. Returns
+    // whether the memtable was frozen and has to be flushed.
+    fn write(&self, wal: &mut Wal, entries: Vec<(Vec<u8>, Entry)>) -> Result<bool> {
+        if entries.is_empty() {
+            return Ok(false);
+        }
+        check_entries(&entries)?;
+        wal.append(&entries)?;
+        if self.options.sync_policy == SyncPolicy::Always {
+            wal.sync()?;
+        }
+
+        let mut state = self.state.write().unwrap();
+        for (key, entry) in entries {
+            state.memtable.insert(key, entry);
+        }
+        // While a memtable is being flushed the active one keeps growing.
+        if state.memtable.size() < self.options.memtable_size || state.frozen.is_some() {
+            return Ok(false);
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut sealed = mem::replace(wal, Wal::create(&self.path, id)?);
+        self.seal(&mut sealed)?;
+        let memtable = mem::take(&mut state.memtable);
+        state.frozen = Some((sealed.id, Arc::new(memtable)));
+        Ok(true)
+    }
+
+    // Sync a write-ahead log that stops taking writes, unless syncing is disabled.
+    fn seal(&self, wal: &mut Wal) -> Result<()> {
+        match self.options.sync_policy {
+            SyncPolicy::Always | SyncPolicy::EveryMillis(_) => wal.sync(),
+            SyncPolicy::Never => Ok(()),
+        }
+    }
+
+    // Write the manifest listing `levels`. The tables it lists must be synced already.
+    fn write_manifest(&self, levels: &Levels) -> Result<()> {
+        let manifest = Manifest {
+            next_id: self.next_id.load(Ordering::SeqCst),
+            log_id: self.log_id.load(Ordering::SeqCst),
+            levels: levels
+                .iter()
+                .map(|level| {
+                    level
+                        .iter()
+                        .map(|run| run.iter().map(|table| table.id).collect())
+                        .collect()
+                })
+                .collect(),
+        };
+        manifest.write(&self.path)
+    }
+}
+
+impl LsmStore {
+    /// Options to open an LsmStore with a non default configuration.
+    pub fn builder() -> LsmStoreOptions {
+        LsmStoreOptions::new()
+    }
+
+    /// Create an LsmStore in a given path, with the default options.
+    ///
+    /// ```
+    /// use kvs::engines::LsmStore;
+    /// use kvs::KvsEngine;
+    /// use kvs::error::Error;
+    /// # let dir = tempfile::TempDir::new().unwrap();
+    /// let store = LsmStore::open(dir.path())?;
+    /// store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    /// assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    ///# Ok::<(), Error>(())
+    /// ```
+    pub fn open(path: impl Into<PathBuf>) -> Result<LsmStore> {
+        LsmStore::builder().open(path)
+    }
+
+    fn open_with_options(path: impl Into<PathBuf>, options: LsmStoreOptions) -> Result<LsmStore> {
+        let path: PathBuf = path.into();
+        if options.create_dir {
+            fs::create_dir_all(&path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+        }
+
+        let manifest = Manifest::read(&path)?.unwrap_or_default();
+        let (wal_ids, last_id) = remove_leftovers(&path, &manifest)?;
+        let mut levels: Levels = manifest
+            .levels
+            .iter()
+            .map(|level| {
+                level
+                    .iter()
+                    .map(|run| {
+                        run.iter()
+                            .map(|&id| Table::open(&path, id).map(Arc::new))
+                            .collect::<Result<Run>>()
+                    })
+                    .collect::<Result<Vec<Run>>>()
+            })
+            .collect::<Result<Levels>>()?;
+        levels.resize_with(LSM_LEVELS.max(levels.len()), Vec::new);
+        let mut next_id = manifest.next_id.max(last_id + 1);
+
+        // The logs left by the previous run are flushed right away, so the memtable starts
+        // empty with a new log.
+        let mut memtable = Memtable::default();
+        for &id in &wal_ids {
+            replay_wal(&path, id, &mut memtable, &options.logger)?;
+        }
+        if !memtable.is_empty() {
+            let table = write_table(&path, next_id, memtable.iter(), &options)?;
+            next_id += 1;
+            levels[0].insert(0, vec![Arc::new(table)]);
+        }
+        let wal = Wal::create(&path, next_id)?;
+
+        let inner = Arc::new(Inner {
+            state: RwLock::new(State {
+                memtable: Memtable::default(),
+                frozen: None,
+                levels: Arc::new(levels),
+            }),
+            log_id: AtomicU64::new(wal.id),
+            next_id: AtomicU64::new(next_id + 1),
+            wal: Mutex::new(wal),
+            path,
+            options,
+        });
+        inner.write_manifest(&inner.state.read().unwrap().levels)?;
+        for id in wal_ids {
+            fs::remove_file(wal_path(&inner.path, id))
+                .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        }
+
+        let compactor = Arc::new(Compactor::spawn(inner.clone()));
+        // Level 0 may already hold enough tables to be compacted.
+        compactor.trigger();
+        let syncer = match inner.options.sync_policy {
+            SyncPolicy::EveryMillis(millis) => {
+                let inner = inner.clone();
+                Some(Arc::new(Syncer::spawn(
+                    Duration::from_millis(millis),
+                    move || {
+                        if let Err(err) = inner.lock_wal().sync() {
+                            error!(inner.options.logger, "Write-ahead log sync failed: {}", err);
+                        }
+                    },
+                )))
+            }
+            SyncPolicy::Always | SyncPolicy::Never => None,
+        };
+
+        Ok(LsmStore {
+            inner,
+            compactor,
+            _syncer: syncer,
+        })
+    }
+
+    // Write entries while the write-ahead log is locked, waking up the background thread when
+    // the memtable was frozen.
+    fn write(&self, wal: &mut Wal, entries: Vec<(Vec<u8>, Entry)>) -> Result<()> {
+        if self.inner.write(wal, entries)? {
+            self.compactor.trigger();
+        }
+        Ok(())
+    }
+
+    // Rewrite the value of `key` with another expiry time. Returns whether the expiry changed,
+    // which it can not for a missing key, nor when the expiry of a key without one is removed.
+    fn set_expiry(&self, key: Vec<u8>, expires_at: Option<u64>) -> Result<bool> {
+        let mut wal = self.inner.lock_wal();
+        match self.inner.live_entry(&key)? {
+            Some((_value, None)) if expires_at.is_none() => Ok(false),
+            Some((value, _expires_at)) => {
+                let entry = Entry::Value { value, expires_at };
+                self.write(&mut wal, vec![(key, entry)])?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+// Remove the files of `dir` that the manifest does not need: tables it does not list, logs
+// already flushed and temporary files. Returns the ids of the logs to replay, in order, with
+// the highest id found.
+fn remove_leftovers(dir: &Path, manifest: &Manifest) -> Result<(Vec<u64>, u64)> {
+    let tables: BTreeSet<u64> = manifest
+        .levels
+        .iter()
+        .flatten()
+        .flatten()
+        .cloned()
+        .collect();
+    let mut wal_ids = Vec::new();
+    let mut last_id = 0;
+    for entry in fs::read_dir(dir).map_err(|_err| Error::from(ErrorKind::FileError))? {
+        let path = entry
+            .map_err(|_err| Error::from(ErrorKind::FileError))?
+            .path();
+        if !path.is_file() {
+            continue;
+        }
+        let id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok());
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let remove = match (id, extension) {
+            (_, Some(TEMPORARY_EXTENSION)) => true,
+            (Some(id), Some(TABLE_EXTENSION)) => {
+                last_id = last_id.max(id);
+                !tables.contains(&id)
+            }
+            (Some(id), Some(WAL_EXTENSION)) => {
+                last_id = last_id.max(id);
+                if id >= manifest.log_id {
+                    wal_ids.push(id);
+                }
+                id < manifest.log_id
+            }
+            _ => false,
+        };
+        if remove {
+            fs::remove_file(path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+        }
+    }
+    for &id in &tables {
+        if !table_path(dir, id).is_file() {
+            return Err(Error::from(ErrorKind::FileError));
+        }
+    }
+    wal_ids.sort_unstable();
+    Ok((wal_ids, last_id))
+}
+
+/// Whether `dir` holds an `LsmStore`.
+pub fn is_lsm_store(dir: &Path) -> bool {
+    dir.join(MANIFEST_NAME).is_file()
+}
+
+// Find the latest entry of `key` in the tables, from the newest to the oldest.
+fn lookup_levels(levels: &Levels, key: &[u8]) -> Result<Option<Entry>> {
+    for run in levels.iter().flatten() {
+        // Only one table of a run can hold the key.
+        let position = run.partition_point(|table| table.last_key() < key);
+        if let Some(table) = run.get(position) {
+            if let Some(entry) = table.get(key)? {
+                return Ok(Some(entry));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// Merge the entries of the memtables, given from the newest to the oldest, with the tables
+// to get the first `limit` live keys inside `bounds`.
+fn read_range(
+    memtables: Vec<Vec<(Vec<u8>, Entry)>>,
+    levels: &Levels,
+    bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    limit: usize,
+    now: u64,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if is_empty_range(&bounds) {
+        return Ok(Vec::new());
+    }
+    let (start, end) = bounds;
+    let mut sources: Vec<Source> = memtables
+        .into_iter()
+        .map(|entries| Box::new(entries.into_iter().map(Ok)) as Source)
+        .collect();
+    for run in levels.iter().flatten() {
+        sources.push(run_source(run, start.clone()));
+    }
+
+    let mut pairs = Vec::new();
+    for item in MergeIter::new(sources) {
+        if pairs.len() >= limit {
+            break;
+        }
+        let (key, entry) = item?;
+        let before_end = match &end {
+            Bound::Included(end) => key <= *end,
+            Bound::Excluded(end) => key < *end,
+            Bound::Unbounded => true,
+        };
+        if !before_end {
+            break;
+        }
+        if let Some(value) = entry.live_value(now) {
+            let value = value.to_vec();
+            pairs.push((key, value));
+        }
+    }
+    Ok(pairs)
+}
+
+// Entries of the tables of `run` coming after `start`, reading the tables one by one.
+fn run_source(run: &[Arc<Table>], start: Bound<Vec<u8>>) -> Source {
+    let tables: Vec<Arc<Table>> = run
+        .iter()
+        .filter(|table| match &start {
+            Bound::Included(start) => table.last_key() >= &start[..],
+            Bound::Excluded(start) => table.last_key() > &start[..],
+            Bound::Unbounded => true,
+        })
+        .cloned()
+        .collect();
+    Box::new(
+        tables
+            .into_iter()
+            .flat_map(move |table| TableIter::new(table, start.clone())),
+    )
+}
+
+impl KvsEngine for LsmStore {
+    type Snapshot = LsmSnapshot;
+
+    /// Get the value of a key from the memtables, or else from the tables from the newest to
+    /// the oldest, skipping the tables whose bloom filter does not hold the key.
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .inner
+            .live_entry(&key)?
+            .map(|(value, _expires_at)| value))
+    }
+
+    fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let mut wal = self.inner.lock_wal();
+        self.write(&mut wal, vec![(key, Entry::value(value))])
+    }
+
+    fn set_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let entry = Entry::Value {
+            value,
+            expires_at: Some(expiry_time(ttl)),
+        };
+        let mut wal = self.inner.lock_wal();
+        self.write(&mut wal, vec![(key, entry)])
+    }
+
+    fn expire(&self, key: Vec<u8>, ttl: Duration) -> Result<bool> {
+        self.set_expiry(key, Some(expiry_time(ttl)))
+    }
+
+    fn ttl(&self, key: Vec<u8>) -> Result<Option<Duration>> {
+        match self.inner.live_entry(&key)? {
+            Some((_value, expires_at)) => Ok(expires_at.map(time_left)),
+            None => Err(Error::from(ErrorKind::KeyNotFound)),
+        }
+    }
+
+    fn persist(&self, key: Vec<u8>) -> Result<bool> {
+        self.set_expiry(key, None)
+    }
+
+    /// Add `delta` to the integer stored in a key while writes are locked, keeping the expiry
+    /// of the key.
+    fn increment(&self, key: Vec<u8>, delta: i64) -> Result<i64> {
+        let mut wal = self.inner.lock_wal();
+        let current = self.inner.live_entry(&key)?;
+        let sum = add_delta(current.as_ref().map(|(value, _)| &value[..]), delta)?;
+        let entry = Entry::Value {
+            value: sum.to_string().into_bytes(),
+            expires_at: current.and_then(|(_value, expires_at)| expires_at),
+        };
+        self.write(&mut wal, vec![(key, entry)])?;
+        Ok(sum)
+    }
+
+    /// Apply the writes of `batch` as a single write of the write-ahead log, which is replayed
+    /// as a whole or not at all.
+    fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        let entries = batch
+            .into_iter()
+            .map(|op| match op {
+                BatchOp::Set(key, value) => (key, Entry::value(value)),
+                BatchOp::Remove(key) => (key, Entry::Tombstone),
+            })
+            .collect();
+        let mut wal = self.inner.lock_wal();
+        self.write(&mut wal, entries)
+    }
+
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner
+            .range((Bound::Included(start), Bound::Excluded(end)), limit)
+    }
+
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.inner.range(prefix_bounds(&prefix, after), limit)
+    }
+
+    /// Run `f` with writes locked, which makes `f` run exactly once. Its writes are applied as
+    /// a single write of the write-ahead log.
+    fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&mut dyn Transaction) -> Result<T>,
+    {
+        let mut wal = self.inner.lock_wal();
+        let mut tx = LsmTransaction::new(&self.inner);
+        let value = f(&mut tx)?;
+        self.write(&mut wal, tx.into_entries())?;
+        Ok(value)
+    }
+
+    /// Take a read-only view of the store: a copy of the memtable and the tables as they are
+    /// now, which stay readable until the snapshot is dropped even if a compaction removes them.
+    fn snapshot(&self) -> Result<LsmSnapshot> {
+        Ok(self.inner.snapshot())
+    }
+
+    fn remove(&self, key: Vec<u8>) -> Result<()> {
+        let mut wal = self.inner.lock_wal();
+        match self.inner.live_entry(&key)? {
+            Some(_) => self.write(&mut wal, vec![(key, Entry::Tombstone)]),
+            None => Err(Error::from(ErrorKind::KeyNotFound)),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::LsmStore;
+use crate::engines::SyncPolicy;
+use crate::error::Result;
+
+use slog::{Discard, Logger};
+use std::path::PathBuf;
+
+/// How the tables of an `LsmStore` are merged together.
+///
+/// Level 0 holds the tables flushed from the memtables, which may overlap each other. Every
+/// compaction merges tables of a level into the next one, dropping the overwritten values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactionStyle {
+    /// Every level below 0 is a single sorted run, `size_ratio` times bigger than the
+    /// previous level. Once level 0 holds `level0_tables` tables they are merged into level 1,
+    /// and a level growing over its size has one of its tables merged into the next level.
+    ///
+    /// Each key is in at most one table per level, which keeps reads and space low at the
+    /// cost of rewriting the data more often.
+    Leveled {
+        level0_tables: usize,
+        size_ratio: u64,
+    },
+    /// Every level holds up to `tier_width` sorted runs, which are merged together into a
+    /// single run of the next level once the level is full.
+    ///
+    /// Data is rewritten less often than with `Leveled`, but a key may be in several runs of
+    /// a level, which costs reads and space.
+    Tiered { tier_width: usize },
+}
+
+/// Options used to open an `LsmStore`.
+///
+/// ```
+/// use kvs::engines::{CompactionStyle, LsmStore, SyncPolicy};
+/// use kvs::error::Error;
+/// # let dir = tempfile::TempDir::new().unwrap();
+/// let store = LsmStore::builder()
+///     .memtable_size(8 * 1024 * 1024)
+///     .compaction_style(CompactionStyle::Tiered { tier_width: 4 })
+///     .sync_policy(SyncPolicy::Always)
+///     .open(dir.path())?;
+///# Ok::<(), Error>(())
+/// ```
+#[derive(Clone)]
+pub struct LsmStoreOptions {
+    pub(super) memtable_size: u64,
+    pub(super) table_size: u64,
+    pub(super) block_size: usize,
+    pub(super) bloom_bits_per_key: usize,
+    pub(super) compaction_style: CompactionStyle,
+    pub(super) sync_policy: SyncPolicy,
+    pub(super) create_dir: bool,
+    pub(super) logger: Logger,
+}
+
+impl Default for LsmStoreOptions {
+    fn default() -> Self {
+        LsmStoreOptions {
+            memtable_size: 4 * 1024 * 1024,
+            table_size: 2 * 1024 * 1024,
+            block_size: 4 * 1024,
+            bloom_bits_per_key: 10,
+            compaction_style: CompactionStyle::Leveled {
+                level0_tables: 4,
+                size_ratio: 10,
+            },
+            sync_policy: SyncPolicy::Never,
+            create_dir: false,
+            logger: Logger::root(Discard, o!()),
+        }
+    }
+}
+
+impl LsmStoreOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the size after which the memtable is flushed to a table. Defaults to 4 MiB.
+    ///
+    /// The memtable and the write-ahead log replayed when the store is opened are bounded by
+    /// this size.
+    pub fn memtable_size(mut self, size: u64) -> Self {
+        self.memtable_size = size.max(1);
+        self
+    }
+
+    /// Sets the size after which the compaction starts a new table. Defaults to 2 MiB.
+    pub fn table_size(mut self, size: u64) -> Self {
+        self.table_size = size.max(1);
+        self
+    }
+
+    /// Sets the size of the blocks of the tables, which is what a get reads from a table.
+    /// Only the first key of each block is kept in memory. Defaults to 4 KiB.
+    pub fn block_size(mut self, size: usize) -> Self {
+        self.block_size = size.max(1);
+        self
+    }
+
+    /// Sets the number of bits per key of the bloom filters. Defaults to 10, which gives about
+    /// 1% of false positives.
+    pub fn bloom_bits_per_key(mut self, bits: usize) -> Self {
+        self.bloom_bits_per_key = bits.max(1);
+        self
+    }
+
+    /// Sets how tables are merged. Defaults to `CompactionStyle::Leveled` with 4 tables in
+    /// level 0 and a size ratio of 10.
+    pub fn compaction_style(mut self, style: CompactionStyle) -> Self {
+        self.compaction_style = match style {
+            CompactionStyle::Leveled {
+                level0_tables,
+                size_ratio,
+            } => CompactionStyle::Leveled {
+                level0_tables: level0_tables.max(1),
+                size_ratio: size_ratio.max(2),
+            },
+            CompactionStyle::Tiered { tier_width } => CompactionStyle::Tiered {
+                tier_width: tier_width.max(2),
+            },
+        };
+        self
+    }
+
+    /// Sets when writes reach the disk. Defaults to `SyncPolicy::Never`.
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Whether the store directory is created when it does not exist. Defaults to `false`.
+    pub fn create_dir(mut self, create: bool) -> Self {
+        self.create_dir = create;
+        self
+    }
+
+    /// Sets the logger used for recovery and compaction messages.
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Open the `LsmStore` stored in `path` with these options.
+    pub fn open(self, path: impl Into<PathBuf>) -> Result<LsmStore> {
+        LsmStore::open_with_options(path, self)
+    }
+}
@@ -0,0 +1,75 @@
+use super::memtable::Memtable;
+use super::{lookup_levels, read_range, Levels};
+use crate::engines::scan::prefix_bounds;
+use crate::engines::KvsSnapshot;
+use crate::error::Result;
+
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Read-only view of an `LsmStore` at the time `KvsEngine::snapshot` was called.
+///
+/// The snapshot holds a copy of the memtable and the tables of the store at that time. A
+/// table merged by the compaction while the snapshot is alive is removed from the store
+/// directory, but stays readable through the snapshot until it is dropped.
+///
+/// Keys are expired as of the time the snapshot was taken.
+pub struct LsmSnapshot {
+    // From the newest to the oldest
+    memtables: Vec<Arc<Memtable>>,
+    levels: Arc<Levels>,
+    taken_at: u64,
+}
+
+impl LsmSnapshot {
+    pub(super) fn new(memtables: Vec<Arc<Memtable>>, levels: Arc<Levels>, taken_at: u64) -> Self {
+        LsmSnapshot {
+            memtables,
+            levels,
+            taken_at,
+        }
+    }
+
+    fn read_range(
+        &self,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let memtables = self
+            .memtables
+            .iter()
+            .map(|memtable| memtable.range(&bounds))
+            .collect();
+        read_range(memtables, &self.levels, bounds, limit, self.taken_at)
+    }
+}
+
+impl KvsSnapshot for LsmSnapshot {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let entry = match self
+            .memtables
+            .iter()
+            .find_map(|memtable| memtable.get(&key))
+        {
+            Some(entry) => Some(entry.clone()),
+            None => lookup_levels(&self.levels, &key)?,
+        };
+        Ok(entry
+            .as_ref()
+            .and_then(|entry| entry.live_value(self.taken_at))
+            .map(<[u8]>::to_vec))
+    }
+
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range((Bound::Included(start), Bound::Excluded(end)), limit)
+    }
+
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range(prefix_bounds(&prefix, after), limit)
+    }
+}
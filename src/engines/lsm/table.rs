@@ -0,0 +1,376 @@
+use super::bloom::{key_hash, BloomFilter};
+use super::entry::{decode_entries, encode_entry, le_u32, le_u64, Entry};
+use crate::engines::kvs::read_exact_at;
+use crate::error::{Error, ErrorKind, Result};
+
+use crc32fast::Hasher;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Extension of the table files.
+pub const TABLE_EXTENSION: &str = "sst";
+
+/// Extension of a table being written, renamed once complete.
+pub const TEMPORARY_EXTENSION: &str = "tmp";
+
+const TABLE_MAGIC: &[u8; 3] = b"KVT";
+const TABLE_VERSION: u8 = 1;
+
+// index offset (8) + bloom offset (8) + entries (8) + crc32 (4) + magic (3) + version (1)
+const FOOTER_LEN: usize = 32;
+
+/// Path of the table `id` inside `dir`.
+pub fn table_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.{}", id, TABLE_EXTENSION))
+}
+
+// Location of a data block, with the first key it holds.
+struct BlockHandle {
+    first_key: Vec<u8>,
+    offset: u64,
+    len: u64,
+}
+
+/// Immutable sorted table of entries, stored in `<id>.sst`.
+///
+/// A table is laid out as:
+///
+/// ```text
+/// data blocks   entries in key order, see `encode_entry`, each block followed by its crc32
+/// index         last key, then the first key, offset and length of every block
+/// bloom filter  see `BloomFilter`
+/// footer        index offset, bloom offset, number of entries, crc32 of the index and
+///               the bloom filter, magic bytes and format version
+/// ```
+///
+/// Only the index, which holds one key per block, and the bloom filter are kept in memory.
+/// Blocks are read from the file when needed, so the keys of a table never need to fit in
+/// memory.
+pub struct Table {
+    pub id: u64,
+    pub size: u64,
+    file: File,
+    blocks: Vec<BlockHandle>,
+    last_key: Vec<u8>,
+    bloom: BloomFilter,
+}
+
+impl Table {
+    /// Open the table `id` and load its index and bloom filter.
+    pub fn open(dir: &Path, id: u64) -> Result<Self> {
+        let file =
+            File::open(table_path(dir, id)).map_err(|_err| Error::from(ErrorKind::FileError))?;
+        let size = file
+            .metadata()
+            .map_err(|_err| Error::from(ErrorKind::FileError))?
+            .len();
+        if size < (4 + FOOTER_LEN) as u64 {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        let mut footer = [0; FOOTER_LEN];
+        read_exact_at(&file, &mut footer, size - FOOTER_LEN as u64)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        if &footer[28..31] != TABLE_MAGIC {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        if footer[31] != TABLE_VERSION {
+            return Err(Error::from(ErrorKind::InvalidFormatVersion(footer[31])));
+        }
+        let index_offset = le_u64(&footer[..8]);
+        let bloom_offset = le_u64(&footer[8..16]);
+        let meta_end = size - FOOTER_LEN as u64;
+        if index_offset > bloom_offset || bloom_offset > meta_end {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+
+        let mut meta = vec![0; (meta_end - index_offset) as usize];
+        read_exact_at(&file, &mut meta, index_offset)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        if checksum(&meta) != le_u32(&footer[24..28]) {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        let (index, bloom) = meta.split_at((bloom_offset - index_offset) as usize);
+        let (last_key, blocks) = decode_index(index)?;
+
+        Ok(Table {
+            id,
+            size,
+            file,
+            blocks,
+            last_key,
+            bloom: BloomFilter::decode(bloom)?,
+        })
+    }
+
+    pub fn first_key(&self) -> &[u8] {
+        &self.blocks[0].first_key
+    }
+
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// Whether some keys of the table are inside `[start, end]`.
+    pub fn overlaps(&self, start: &[u8], end: &[u8]) -> bool {
+        self.first_key() <= end && self.last_key() >= start
+    }
+
+    /// Get the entry of `key`, `None` when the table does not hold it.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Entry>> {
+        if key < self.first_key() || key > self.last_key() || !self.bloom.may_contain(key_hash(key))
+        {
+            return Ok(None);
+        }
+        let block = self.block_of(key);
+        let entries = self.read_block(block)?;
+        Ok(entries
+            .binary_search_by(|(entry_key, _entry)| entry_key[..].cmp(key))
+            .ok()
+            .map(|position| entries[position].1.clone()))
+    }
+
+    // Index of the only block that can hold `key`.
+    fn block_of(&self, key: &[u8]) -> usize {
+        self.blocks
+            .partition_point(|block| &block.first_key[..] <= key)
+            .saturating_sub(1)
+    }
+
+    fn read_block(&self, block: usize) -> Result<Vec<(Vec<u8>, Entry)>> {
+        let handle = &self.blocks[block];
+        let mut bytes = vec![0; handle.len as usize];
+        read_exact_at(&self.file, &mut bytes, handle.offset)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        if bytes.len() < 4 {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        let (content, crc) = bytes.split_at(bytes.len() - 4);
+        if checksum(content) != le_u32(crc) {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        decode_entries(content)
+    }
+}
+
+/// Iterator over the entries of a table from a given key, reading one block at a time.
+pub struct TableIter {
+    table: Arc<Table>,
+    next_block: usize,
+    start: Bound<Vec<u8>>,
+    entries: VecDeque<(Vec<u8>, Entry)>,
+}
+
+impl TableIter {
+    /// Iterate over the entries of `table` coming after `start`.
+    pub fn new(table: Arc<Table>, start: Bound<Vec<u8>>) -> Self {
+        let next_block = match &start {
+            Bound::Included(key) | Bound::Excluded(key) => table.block_of(key),
+            Bound::Unbounded => 0,
+        };
+        TableIter {
+            table,
+            next_block,
+            start,
+            entries: VecDeque::new(),
+        }
+    }
+}
+
+impl Iterator for TableIter {
+    type Item = Result<(Vec<u8>, Entry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.entries.is_empty() {
+            if self.next_block >= self.table.blocks.len() {
+                return None;
+            }
+            let entries = match self.table.read_block(self.next_block) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    self.next_block = self.table.blocks.len();
+                    return Some(Err(err));
+                }
+            };
+            self.next_block += 1;
+            let start = &self.start;
+            self.entries = entries
+                .into_iter()
+                .filter(|(key, _entry)| match start {
+                    Bound::Included(start) => key >= start,
+                    Bound::Excluded(start) => key > start,
+                    Bound::Unbounded => true,
+                })
+                .collect();
+        }
+        self.entries.pop_front().map(Ok)
+    }
+}
+
+/// Writer of a new table, which has to be given its entries in key order.
+///
+/// The table is written under a temporary name and only renamed to its final path by
+/// `finish`, so a crash never leaves a partial table behind.
+pub struct TableBuilder {
+    id: u64,
+    dir: PathBuf,
+    writer: BufWriter<File>,
+    offset: u64,
+    block: Vec<u8>,
+    block_size: usize,
+    blocks: Vec<BlockHandle>,
+    last_key: Vec<u8>,
+    hashes: Vec<u64>,
+    bits_per_key: usize,
+}
+
+impl TableBuilder {
+    pub fn create(dir: &Path, id: u64, block_size: usize, bits_per_key: usize) -> Result<Self> {
+        let path = table_path(dir, id).with_extension(TEMPORARY_EXTENSION);
+        let file = File::create(path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+        let mut builder = TableBuilder {
+            id,
+            dir: dir.to_owned(),
+            writer: BufWriter::new(file),
+            offset: 0,
+            block: Vec::with_capacity(block_size),
+            block_size,
+            blocks: Vec::new(),
+            last_key: Vec::new(),
+            hashes: Vec::new(),
+            bits_per_key,
+        };
+        builder.write(&[
+            TABLE_MAGIC[0],
+            TABLE_MAGIC[1],
+            TABLE_MAGIC[2],
+            TABLE_VERSION,
+        ])?;
+        Ok(builder)
+    }
+
+    /// Append the entry of `key`, which must come after every key already added.
+    pub fn add(&mut self, key: &[u8], entry: &Entry) -> Result<()> {
+        if self.block.is_empty() {
+            self.blocks.push(BlockHandle {
+                first_key: key.to_vec(),
+                offset: self.offset,
+                len: 0,
+            });
+        }
+        encode_entry(&mut self.block, key, entry)?;
+        self.hashes.push(key_hash(key));
+        self.last_key = key.to_vec();
+        if self.block.len() >= self.block_size {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    /// Number of bytes written so far, which is about the size of the table.
+    pub fn size(&self) -> u64 {
+        self.offset + self.block.len() as u64
+    }
+
+    /// Write the index, the bloom filter and the footer, sync the table and move it to its
+    /// final path.
+    pub fn finish(mut self) -> Result<Table> {
+        self.write_block()?;
+
+        let mut meta = Vec::new();
+        meta.extend_from_slice(&(self.last_key.len() as u32).to_le_bytes());
+        meta.extend_from_slice(&self.last_key);
+        meta.extend_from_slice(&(self.blocks.len() as u32).to_le_bytes());
+        for block in &self.blocks {
+            meta.extend_from_slice(&(block.first_key.len() as u32).to_le_bytes());
+            meta.extend_from_slice(&block.first_key);
+            meta.extend_from_slice(&block.offset.to_le_bytes());
+            meta.extend_from_slice(&block.len.to_le_bytes());
+        }
+        let index_offset = self.offset;
+        let bloom_offset = index_offset + meta.len() as u64;
+        meta.extend_from_slice(&BloomFilter::build(&self.hashes, self.bits_per_key).encode());
+
+        let mut footer = Vec::with_capacity(FOOTER_LEN);
+        footer.extend_from_slice(&index_offset.to_le_bytes());
+        footer.extend_from_slice(&bloom_offset.to_le_bytes());
+        footer.extend_from_slice(&(self.hashes.len() as u64).to_le_bytes());
+        footer.extend_from_slice(&checksum(&meta).to_le_bytes());
+        footer.extend_from_slice(TABLE_MAGIC);
+        footer.push(TABLE_VERSION);
+        self.write(&meta)?;
+        self.write(&footer)?;
+
+        // The table replaces data which may only be on disk once the manifest points to it.
+        self.writer
+            .flush()
+            .and_then(|_| self.writer.get_ref().sync_all())
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        let path = table_path(&self.dir, self.id);
+        fs::rename(path.with_extension(TEMPORARY_EXTENSION), path)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        Table::open(&self.dir, self.id)
+    }
+
+    fn write_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let crc = checksum(&self.block);
+        self.block.extend_from_slice(&crc.to_le_bytes());
+        self.writer
+            .write_all(&self.block)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        let len = self.block.len() as u64;
+        self.offset += len;
+        self.blocks.last_mut().unwrap().len = len;
+        self.block.clear();
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer
+            .write_all(bytes)
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+// Decode the index of a table into its last key and the handles of its blocks.
+fn decode_index(mut bytes: &[u8]) -> Result<(Vec<u8>, Vec<BlockHandle>)> {
+    let mut take = |len: usize| -> Result<&[u8]> {
+        if bytes.len() < len {
+            return Err(Error::from(ErrorKind::CorruptedRecord));
+        }
+        let (taken, rest) = bytes.split_at(len);
+        bytes = rest;
+        Ok(taken)
+    };
+    let last_key_len = le_u32(take(4)?) as usize;
+    let last_key = take(last_key_len)?.to_vec();
+    let count = le_u32(take(4)?) as usize;
+    let mut blocks = Vec::new();
+    for _ in 0..count {
+        let key_len = le_u32(take(4)?) as usize;
+        let first_key = take(key_len)?.to_vec();
+        blocks.push(BlockHandle {
+            first_key,
+            offset: le_u64(take(8)?),
+            len: le_u64(take(8)?),
+        });
+    }
+    if blocks.is_empty() {
+        return Err(Error::from(ErrorKind::CorruptedRecord));
+    }
+    Ok((last_key, blocks))
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(bytes);
+    hasher.finalize()
+}
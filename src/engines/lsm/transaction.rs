@@ -0,0 +1,57 @@
+use super::entry::Entry;
+use super::Inner;
+use crate::engines::Transaction;
+use crate::error::Result;
+
+use std::collections::BTreeMap;
+
+/// Transaction of an `LsmStore`, see `KvsEngine::transaction`.
+///
+/// The transaction runs while writes are locked, so its reads can not be outdated. Writes
+/// are buffered until they are written together.
+pub(super) struct LsmTransaction<'a> {
+    inner: &'a Inner,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> LsmTransaction<'a> {
+    pub fn new(inner: &'a Inner) -> Self {
+        LsmTransaction {
+            inner,
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Entries committing the transaction.
+    pub fn into_entries(self) -> Vec<(Vec<u8>, Entry)> {
+        self.writes
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => (key, Entry::value(value)),
+                None => (key, Entry::Tombstone),
+            })
+            .collect()
+    }
+}
+
+impl<'a> Transaction for LsmTransaction<'a> {
+    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.get(&key) {
+            return Ok(value.clone());
+        }
+        Ok(self
+            .inner
+            .live_entry(&key)?
+            .map(|(value, _expires_at)| value))
+    }
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.writes.insert(key, Some(value));
+        Ok(())
+    }
+
+    fn remove(&mut self, key: Vec<u8>) -> Result<()> {
+        self.writes.insert(key, None);
+        Ok(())
+    }
+}
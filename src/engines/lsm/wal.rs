@@ -0,0 +1,139 @@
+use super::entry::{decode_entries, encode_entry, encode_len, le_u32, Entry};
+use super::memtable::Memtable;
+use crate::error::{Error, ErrorKind, Result};
+
+use crc32fast::Hasher;
+use slog::Logger;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Extension of the write-ahead log files.
+pub const WAL_EXTENSION: &str = "wal";
+
+// crc32 (4) + body length (4)
+const WAL_HEADER_LEN: usize = 8;
+
+/// Path of the write-ahead log `id` inside `dir`.
+pub fn wal_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.{}", id, WAL_EXTENSION))
+}
+
+/// Write-ahead log of a memtable, `<id>.wal`, which is replayed when the store is opened
+/// and removed once the memtable is flushed.
+///
+/// Every write is appended as a crc32 and a length followed by its encoded entries, so the
+/// entries of a write are replayed all together or not at all.
+pub struct Wal {
+    pub id: u64,
+    writer: BufWriter<File>,
+    // Whether bytes were appended since the last sync.
+    unsynced: bool,
+}
+
+impl Wal {
+    /// Create the write-ahead log `id`, which must not exist yet.
+    pub fn create(dir: &Path, id: u64) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(wal_path(dir, id))
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        Ok(Wal {
+            id,
+            writer: BufWriter::new(file),
+            unsynced: false,
+        })
+    }
+
+    /// Append the entries of a write and flush them to the operating system.
+    pub fn append(&mut self, entries: &[(Vec<u8>, Entry)]) -> Result<()> {
+        let mut body = Vec::new();
+        for (key, entry) in entries {
+            encode_entry(&mut body, key, entry)?;
+        }
+        let body_len = encode_len(body.len())?;
+        let mut hasher = Hasher::new();
+        hasher.update(&body);
+        self.writer
+            .write_all(&hasher.finalize().to_le_bytes())
+            .and_then(|_| self.writer.write_all(&body_len))
+            .and_then(|_| self.writer.write_all(&body))
+            .and_then(|_| self.writer.flush())
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        self.unsynced = true;
+        Ok(())
+    }
+
+    /// Wait until the appended entries reach the disk. Nothing is done when nothing was
+    /// appended since the last sync.
+    pub fn sync(&mut self) -> Result<()> {
+        if !self.unsynced {
+            return Ok(());
+        }
+        self.writer
+            .get_ref()
+            .sync_data()
+            .map_err(|_err| Error::from(ErrorKind::FileError))?;
+        self.unsynced = false;
+        Ok(())
+    }
+}
+
+/// Replay the write-ahead log `id` into `memtable`.
+///
+/// A process killed in the middle of a write leaves a damaged write at the end of the log,
+/// which is truncated away.
+pub fn replay_wal(dir: &Path, id: u64, memtable: &mut Memtable, logger: &Logger) -> Result<()> {
+    let path = wal_path(dir, id);
+    let bytes = fs::read(&path).map_err(|_err| Error::from(ErrorKind::FileError))?;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let entries = match decode_write(&bytes[offset..]) {
+            Some((entries, len)) => {
+                offset += len;
+                entries
+            }
+            None => {
+                warn!(
+                    logger,
+                    "Dropping {} bytes at the end of write-ahead log {} after offset {}",
+                    bytes.len() - offset,
+                    id,
+                    offset
+                );
+                OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_len(offset as u64))
+                    .map_err(|_err| Error::from(ErrorKind::FileError))?;
+                break;
+            }
+        };
+        for (key, entry) in entries {
+            memtable.insert(key, entry);
+        }
+    }
+    Ok(())
+}
+
+// Entries of a write with its length in the log
+type LoggedWrite = (Vec<(Vec<u8>, Entry)>, usize);
+
+// Decode the write at the start of `bytes`, `None` when it is damaged.
+fn decode_write(bytes: &[u8]) -> Option<LoggedWrite> {
+    if bytes.len() < WAL_HEADER_LEN {
+        return None;
+    }
+    let len = WAL_HEADER_LEN + le_u32(&bytes[4..8]) as usize;
+    if bytes.len() < len {
+        return None;
+    }
+    let body = &bytes[WAL_HEADER_LEN..len];
+    let mut hasher = Hasher::new();
+    hasher.update(body);
+    if hasher.finalize() != le_u32(&bytes[..4]) {
+        return None;
+    }
+    decode_entries(body).ok().map(|entries| (entries, len))
+}
@@ -0,0 +1,308 @@
+use super::expiry::{expiry_time, now_millis, time_left, Sweeper, SWEEP_INTERVAL};
+use super::scan::{is_empty_range, prefix_bounds};
+use super::{add_delta, BatchOp, KvsEngine, KvsSnapshot, Transaction, WriteBatch};
+use crate::error::{Error, ErrorKind, Result};
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+type Entries = BTreeMap<Vec<u8>, MemEntry>;
+
+#[derive(Clone, Debug)]
+struct MemEntry {
+    value: Vec<u8>,
+    expires_at: Option<u64>,
+}
+
+impl MemEntry {
+    fn new(value: Vec<u8>) -> Self {
+        MemEntry {
+            value,
+            expires_at: None,
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Engine keeping every key in memory, without any file.
+///
+/// It has the same semantics as the other engines, so it can replace them in tests or serve
+/// as a cache whose content is lost when the process stops. Every write takes the whole map
+/// for itself, and transactions run one at a time, so they never conflict.
+///
+/// ```
+/// use kvs::engines::MemStore;
+/// use kvs::KvsEngine;
+/// use kvs::error::Error;
+/// let store = MemStore::new();
+/// store.set(b"key1".to_vec(), b"value1".to_vec())?;
+/// assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+///# Ok::<(), Error>(())
+/// ```
+#[derive(Clone)]
+pub struct MemStore {
+    entries: Arc<RwLock<Entries>>,
+    // Only held to stop the sweep thread along with the last handle of the store.
+    _sweeper: Arc<Sweeper>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        let entries = Arc::new(RwLock::new(BTreeMap::new()));
+        let sweeper = {
+            let entries = entries.clone();
+            Sweeper::spawn(SWEEP_INTERVAL, move || sweep(&entries))
+        };
+        MemStore {
+            entries,
+            _sweeper: Arc::new(sweeper),
+        }
+    }
+
+    // Get the live entry of `key`, `None` when it is missing or expired.
+    fn live_entry(&self, key: &[u8]) -> Option<MemEntry> {
+        let entries = self.entries.read().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(now_millis()))
+            .cloned()
+    }
+
+    fn read_range(
+        &self,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        read_range(&self.entries.read().unwrap(), bounds, limit, now_millis())
+    }
+
+    // Change the expiry of a live key. Returns whether the key exists.
+    fn set_expiry(&self, key: &[u8], expires_at: Option<u64>) -> bool {
+        let mut entries = self.entries.write().unwrap();
+        match entries.get_mut(key) {
+            Some(entry) if !entry.is_expired(now_millis()) => {
+                entry.expires_at = expires_at;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for MemStore {
+    fn default() -> Self {
+        MemStore::new()
+    }
+}
+
+fn sweep(entries: &RwLock<Entries>) {
+    let now = now_millis();
+    entries
+        .write()
+        .unwrap()
+        .retain(|_key, entry| !entry.is_expired(now));
+}
+
+// Get the first `limit` keys of `entries` inside `bounds`, skipping the keys expired at `now`.
+fn read_range(
+    entries: &Entries,
+    bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    limit: usize,
+    now: u64,
+) -> Vec<(Vec<u8>, Vec<u8>)> {
+    if is_empty_range(&bounds) {
+        return Vec::new();
+    }
+    entries
+        .range(bounds)
+        .filter(|(_key, entry)| !entry.is_expired(now))
+        .take(limit)
+        .map(|(key, entry)| (key.clone(), entry.value.clone()))
+        .collect()
+}
+
+impl KvsEngine for MemStore {
+    type Snapshot = MemSnapshot;
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(self.live_entry(&key).map(|entry| entry.value))
+    }
+
+    fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.entries
+            .write()
+            .unwrap()
+            .insert(key, MemEntry::new(value));
+        Ok(())
+    }
+
+    fn set_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let entry = MemEntry {
+            value,
+            expires_at: Some(expiry_time(ttl)),
+        };
+        self.entries.write().unwrap().insert(key, entry);
+        Ok(())
+    }
+
+    fn expire(&self, key: Vec<u8>, ttl: Duration) -> Result<bool> {
+        Ok(self.set_expiry(&key, Some(expiry_time(ttl))))
+    }
+
+    fn ttl(&self, key: Vec<u8>) -> Result<Option<Duration>> {
+        match self.live_entry(&key) {
+            Some(entry) => Ok(entry.expires_at.map(time_left)),
+            None => Err(Error::from(ErrorKind::KeyNotFound)),
+        }
+    }
+
+    fn persist(&self, key: Vec<u8>) -> Result<bool> {
+        match self.live_entry(&key) {
+            Some(MemEntry {
+                expires_at: Some(_),
+                ..
+            }) => Ok(self.set_expiry(&key, None)),
+            Some(_) | None => Ok(false),
+        }
+    }
+
+    /// Add `delta` to the integer stored in a key while the map is locked, keeping the expiry
+    /// of the key.
+    fn increment(&self, key: Vec<u8>, delta: i64) -> Result<i64> {
+        let mut entries = self.entries.write().unwrap();
+        let now = now_millis();
+        let current = entries.get(&key).filter(|entry| !entry.is_expired(now));
+        let sum = add_delta(current.map(|entry| &entry.value[..]), delta)?;
+        let entry = MemEntry {
+            value: sum.to_string().into_bytes(),
+            expires_at: current.and_then(|entry| entry.expires_at),
+        };
+        entries.insert(key, entry);
+        Ok(sum)
+    }
+
+    fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        let mut entries = self.entries.write().unwrap();
+        for op in batch {
+            match op {
+                BatchOp::Set(key, value) => entries.insert(key, MemEntry::new(value)),
+                BatchOp::Remove(key) => entries.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.read_range((Bound::Included(start), Bound::Excluded(end)), limit))
+    }
+
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self.read_range(prefix_bounds(&prefix, after), limit))
+    }
+
+    /// Run `f` with the map locked, which makes `f` run exactly once.
+    fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&mut dyn Transaction) -> Result<T>,
+    {
+        let mut entries = self.entries.write().unwrap();
+        let mut tx = MemTransaction {
+            entries: &entries,
+            writes: BTreeMap::new(),
+        };
+        let value = f(&mut tx)?;
+        let writes = tx.writes;
+        for (key, value) in writes {
+            match value {
+                Some(value) => entries.insert(key, MemEntry::new(value)),
+                None => entries.remove(&key),
+            };
+        }
+        Ok(value)
+    }
+
+    /// Copy the map, leaving out the keys expired at that time.
+    fn snapshot(&self) -> Result<MemSnapshot> {
+        let now = now_millis();
+        let entries = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_key, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        Ok(MemSnapshot { entries })
+    }
+
+    fn remove(&self, key: Vec<u8>) -> Result<()> {
+        match self.entries.write().unwrap().remove(&key) {
+            Some(entry) if !entry.is_expired(now_millis()) => Ok(()),
+            Some(_) | None => Err(Error::from(ErrorKind::KeyNotFound)),
+        }
+    }
+}
+
+/// Read-only copy of a `MemStore` taken by `KvsEngine::snapshot`.
+pub struct MemSnapshot {
+    entries: Entries,
+}
+
+impl KvsSnapshot for MemSnapshot {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(self.entries.get(&key).map(|entry| entry.value.clone()))
+    }
+
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let bounds = (Bound::Included(start), Bound::Excluded(end));
+        Ok(read_range(&self.entries, bounds, limit, 0))
+    }
+
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let bounds = prefix_bounds(&prefix, after);
+        Ok(read_range(&self.entries, bounds, limit, 0))
+    }
+}
+
+// Transaction of a `MemStore`, buffering its writes until `f` succeeds.
+struct MemTransaction<'a> {
+    entries: &'a Entries,
+    writes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> Transaction for MemTransaction<'a> {
+    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.writes.get(&key) {
+            return Ok(value.clone());
+        }
+        let entry = self.entries.get(&key);
+        Ok(entry
+            .filter(|entry| !entry.is_expired(now_millis()))
+            .map(|entry| entry.value.clone()))
+    }
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.writes.insert(key, Some(value));
+        Ok(())
+    }
+
+    fn remove(&mut self, key: Vec<u8>) -> Result<()> {
+        self.writes.insert(key, None);
+        Ok(())
+    }
+}
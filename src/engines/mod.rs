@@ -1,30 +1,313 @@
-use crate::command::Command;
-use crate::error::Result;
+use crate::command::{Command, ScanCursor};
+use crate::error::{Error, ErrorKind, Result};
+use crate::protocol::Value;
 
+use std::time::Duration;
+
+mod batch;
+mod expiry;
 mod kvs;
+mod lsm;
+mod memory;
+mod scan;
 mod sled;
+mod syncer;
+mod transaction;
+
+pub use self::batch::{BatchOp, WriteBatch};
+pub use self::kvs::{
+    CacheStats, CompactionTrigger, EncryptionKey, KvStore, KvStoreOptions, KvStoreSnapshot,
+    RecoveryMode,
+};
+pub use self::lsm::{is_lsm_store, CompactionStyle, LsmSnapshot, LsmStore, LsmStoreOptions};
+pub use self::memory::{MemSnapshot, MemStore};
+pub use self::scan::PrefixScan;
+pub use self::sled::{SledSnapshot, SledStore};
+pub use self::transaction::Transaction;
+
+/// When the writes of an engine reach the disk.
+///
+/// Every policy survives a crash of the process once a write is acknowledged, they differ in
+/// what a power failure or a crash of the operating system can lose.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPolicy {
+    /// Every write is synced to the disk before it is acknowledged, so nothing acknowledged is
+    /// ever lost. Writes are as slow as the disk syncs.
+    Always,
+    /// A background thread syncs the writes every given number of milliseconds, so a power
+    /// failure loses at most the writes acknowledged during that interval.
+    EveryMillis(u64),
+    /// Writes are handed to the operating system, which decides when they reach the disk.
+    /// A power failure can lose any write that was not synced yet.
+    Never,
+}
+
+/// Which values an engine compresses before writing them.
+///
+/// Compression needs the `compression` cargo feature, opening an engine with any other policy
+/// than `Off` fails with `UnsupportedCodec` without it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompressionPolicy {
+    /// Values are written as they are.
+    Off,
+    /// Every value is compressed.
+    Always,
+    /// Only the values larger than this many bytes are compressed, which spares the cost of
+    /// compressing values too small to shrink.
+    Above(u64),
+}
+
+impl CompressionPolicy {
+    /// Whether a value of `len` bytes is compressed.
+    pub(crate) fn compresses(self, len: usize) -> bool {
+        match self {
+            CompressionPolicy::Off => false,
+            CompressionPolicy::Always => true,
+            CompressionPolicy::Above(size) => len as u64 > size,
+        }
+    }
 
-pub use self::kvs::KvStore;
-pub use self::sled::SledStore;
+    // Fail when the policy compresses values but the crate was built without compression.
+    pub(crate) fn check(self) -> Result<()> {
+        if self == CompressionPolicy::Off || cfg!(feature = "compression") {
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::UnsupportedCodec))
+        }
+    }
+}
 
+/// Storage engine of the server. Keys and values are arbitrary bytes.
 pub trait KvsEngine: Clone + Send + 'static {
-    fn set(&self, key: String, value: String) -> Result<()>;
+    /// Read-only view returned by `snapshot`.
+    type Snapshot: KvsSnapshot;
+
+    fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
 
-    fn get(&self, key: String) -> Result<Option<String>>;
+    fn remove(&self, key: Vec<u8>) -> Result<()>;
 
-    fn remove(&self, key: String) -> Result<()>;
+    /// Set `key` to `value`, expiring after `ttl`. An expired key is treated as missing right
+    /// away, its space is reclaimed later. A plain `set` of the key removes the expiry.
+    fn set_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()>;
 
-    fn exec_command(&self, command: Command) -> Result<Option<String>> {
+    /// Make `key` expire after `ttl`. Returns whether the key exists.
+    fn expire(&self, key: Vec<u8>, ttl: Duration) -> Result<bool>;
+
+    /// Get the time left before `key` expires, `None` when it never expires. Fails with
+    /// `KeyNotFound` when the key does not exist.
+    fn ttl(&self, key: Vec<u8>) -> Result<Option<Duration>>;
+
+    /// Remove the expiry of `key`. Returns whether the key had one.
+    fn persist(&self, key: Vec<u8>) -> Result<bool>;
+
+    /// Set `key` to `new`, or remove it when `new` is `None`, if its current value is
+    /// `expected`, `None` meaning that the key does not exist. Returns whether the key was
+    /// swapped.
+    fn compare_and_swap(
+        &self,
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<bool> {
+        self.transaction(|tx| {
+            let current = tx.get(key.clone())?;
+            if current != expected {
+                return Ok(false);
+            }
+            match &new {
+                Some(value) => tx.set(key.clone(), value.clone())?,
+                None if current.is_some() => tx.remove(key.clone())?,
+                None => {}
+            }
+            Ok(true)
+        })
+    }
+
+    /// Set `key` only if it does not exist. Returns whether the key was set.
+    fn set_if_absent(&self, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        self.compare_and_swap(key, None, Some(value))
+    }
+
+    /// Set `key` only if it already exists. Returns whether the key was set.
+    fn set_if_present(&self, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        self.transaction(|tx| {
+            if tx.get(key.clone())?.is_none() {
+                return Ok(false);
+            }
+            tx.set(key.clone(), value.clone())?;
+            Ok(true)
+        })
+    }
+
+    /// Add `delta` to the integer stored in `key` and return the result, a missing key counting
+    /// as 0. Fails with `NotAnInteger` when the value is not a decimal `i64`, and with
+    /// `IntegerOverflow` when the result does not fit in one.
+    fn increment(&self, key: Vec<u8>, delta: i64) -> Result<i64> {
+        self.transaction(|tx| {
+            let value = add_delta(tx.get(key.clone())?.as_deref(), delta)?;
+            tx.set(key.clone(), value.to_string().into_bytes())?;
+            Ok(value)
+        })
+    }
+
+    /// Apply every write of `batch`, or none of them.
+    fn write_batch(&self, batch: WriteBatch) -> Result<()>;
+
+    /// Get the first `limit` keys in `[start, end)` with their values, in lexicographic order
+    /// of the keys.
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Get the first `limit` keys starting with `prefix` with their values, in lexicographic
+    /// order of the keys. With `after` only the keys that come after it are returned, which
+    /// allows to resume a previous scan.
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Iterate over the keys starting with `prefix` and their values, in lexicographic order
+    /// of the keys.
+    fn scan_prefix(&self, prefix: Vec<u8>) -> PrefixScan<Self> {
+        PrefixScan::new(self.clone(), prefix)
+    }
+
+    /// Run `f` as a transaction: its writes are applied together, and only if none of the keys
+    /// it read was modified in the meantime, otherwise `f` is run again. When `f` fails the
+    /// transaction is aborted without writing anything.
+    ///
+    /// `f` can run several times, so it should have no other side effect than its reads and
+    /// writes through the transaction.
+    fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&mut dyn Transaction) -> Result<T>;
+
+    /// Take a read-only view of the engine as it is now. Reads through the snapshot are
+    /// consistent with each other, whatever is written to the engine afterwards.
+    fn snapshot(&self) -> Result<Self::Snapshot>;
+
+    fn exec_command(&self, command: Command) -> Result<Value> {
         match command {
             Command::Rm(key) => {
                 self.remove(key)?;
-                Ok(None)
+                Ok(Value::None)
             }
             Command::Set(key, value) => {
                 self.set(key, value)?;
-                Ok(None)
+                Ok(Value::None)
+            }
+            Command::Get(key) => Ok(self.get(key)?.map_or(Value::None, Value::Bytes)),
+            Command::SetWithTtl(key, value, ttl) => {
+                self.set_with_ttl(key, value, Duration::from_millis(ttl))?;
+                Ok(Value::None)
+            }
+            Command::Expire(key, ttl) => {
+                let exists = self.expire(key, Duration::from_millis(ttl))?;
+                Ok(Value::Integer(exists as i64))
+            }
+            Command::Ttl(key) => Ok(self
+                .ttl(key)?
+                .map_or(Value::None, |ttl| Value::Integer(ttl.as_millis() as i64))),
+            Command::Persist(key) => Ok(Value::Integer(self.persist(key)? as i64)),
+            Command::Incr(key) => Ok(Value::Integer(self.increment(key, 1)?)),
+            Command::Decr(key) => Ok(Value::Integer(self.increment(key, -1)?)),
+            Command::IncrBy(key, delta) => Ok(Value::Integer(self.increment(key, delta)?)),
+            Command::CompareAndSwap(key, expected, new) => {
+                let swapped = self.compare_and_swap(key, expected, new)?;
+                Ok(Value::Integer(swapped as i64))
+            }
+            Command::SetIfAbsent(key, value) => {
+                Ok(Value::Integer(self.set_if_absent(key, value)? as i64))
+            }
+            Command::SetIfPresent(key, value) => {
+                Ok(Value::Integer(self.set_if_present(key, value)? as i64))
+            }
+            Command::Scan(cursor, prefix, count) => {
+                let pairs = self.scan_prefix_after(prefix, cursor.into_key(), count)?;
+                let cursor = match pairs.last() {
+                    Some((key, _value)) if pairs.len() == count => ScanCursor::After(key.clone()),
+                    _ => ScanCursor::Start,
+                };
+                let pairs = pairs
+                    .into_iter()
+                    .flat_map(|(key, value)| vec![Value::Bytes(key), Value::Bytes(value)])
+                    .collect();
+                Ok(Value::Array(vec![
+                    Value::Bytes(cursor.encode()),
+                    Value::Array(pairs),
+                ]))
+            }
+            // Only valid within a connection, see `KvsServer`.
+            Command::Watch(_) | Command::Multi | Command::Exec | Command::Discard => {
+                Err(Error::from(ErrorKind::InvalidCommand))
             }
-            Command::Get(key) => self.get(key),
         }
     }
+
+    /// Run the commands queued between `Command::Multi` and `Command::Exec` in a transaction,
+    /// replying with an array of their replies. The watched keys are checked by the server.
+    fn exec_transaction(&self, commands: &[Command]) -> Result<Value> {
+        self.transaction(|tx| {
+            let mut replies = Vec::with_capacity(commands.len());
+            for command in commands {
+                let reply = match command {
+                    Command::Get(key) => tx.get(key.clone())?.map_or(Value::None, Value::Bytes),
+                    Command::Set(key, value) => {
+                        tx.set(key.clone(), value.clone())?;
+                        Value::None
+                    }
+                    Command::Rm(key) => match tx.get(key.clone())? {
+                        Some(_value) => {
+                            tx.remove(key.clone())?;
+                            Value::None
+                        }
+                        None => Value::Error("KeyNotFound".to_owned()),
+                    },
+                    _ => return Err(Error::from(ErrorKind::InvalidCommand)),
+                };
+                replies.push(reply);
+            }
+            Ok(Value::Array(replies))
+        })
+    }
+
+    /// Apply the commands of a `Value::Batch` frame as a single `WriteBatch`.
+    fn exec_batch(&self, commands: Vec<Command>) -> Result<Value> {
+        self.write_batch(WriteBatch::from_commands(commands)?)?;
+        Ok(Value::None)
+    }
+}
+
+/// Add `delta` to the integer stored in `value`, see `KvsEngine::increment`.
+fn add_delta(value: Option<&[u8]>, delta: i64) -> Result<i64> {
+    let current = match value {
+        Some(value) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .ok_or_else(|| Error::from(ErrorKind::NotAnInteger))?,
+        None => 0,
+    };
+    current
+        .checked_add(delta)
+        .ok_or_else(|| Error::from(ErrorKind::IntegerOverflow))
+}
+
+/// Read-only view of an engine at the time `KvsEngine::snapshot` was called.
+pub trait KvsSnapshot: Send + 'static {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+
+    /// Get the first `limit` keys in `[start, end)` with their values, see `KvsEngine::scan`.
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Get the first `limit` keys starting with `prefix` that come after `after`, see
+    /// `KvsEngine::scan_prefix_after`.
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
 }
@@ -0,0 +1,92 @@
+use super::KvsEngine;
+use crate::error::Result;
+
+use std::collections::VecDeque;
+use std::ops::Bound;
+
+// Number of keys fetched at once by `PrefixScan`.
+const PREFIX_SCAN_PAGE: usize = 128;
+
+/// Iterator over the keys starting with a prefix and their values, returned by
+/// `KvsEngine::scan_prefix`.
+///
+/// Keys are fetched in pages and the engine is not locked between pages, so concurrent
+/// writes are not blocked by a long iteration. Each page resumes after the last key returned:
+/// keys present during the whole iteration are returned exactly once, in order, while keys
+/// written or removed in the meantime may or may not be seen.
+pub struct PrefixScan<E: KvsEngine> {
+    engine: E,
+    prefix: Vec<u8>,
+    after: Option<Vec<u8>>,
+    page: VecDeque<(Vec<u8>, Vec<u8>)>,
+    done: bool,
+}
+
+impl<E: KvsEngine> PrefixScan<E> {
+    pub(super) fn new(engine: E, prefix: Vec<u8>) -> Self {
+        PrefixScan {
+            engine,
+            prefix,
+            after: None,
+            page: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl<E: KvsEngine> Iterator for PrefixScan<E> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && !self.done {
+            let page = self.engine.scan_prefix_after(
+                self.prefix.clone(),
+                self.after.take(),
+                PREFIX_SCAN_PAGE,
+            );
+            match page {
+                Ok(page) => {
+                    self.done = page.len() < PREFIX_SCAN_PAGE;
+                    self.after = page.last().map(|(key, _value)| key.clone());
+                    self.page = page.into();
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
+/// Bounds of the keys starting with `prefix` that come after `after`, or all of them when
+/// there is no `after`.
+pub fn prefix_bounds(prefix: &[u8], after: Option<Vec<u8>>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let start = match after {
+        Some(after) if after.as_slice() >= prefix => Bound::Excluded(after),
+        _ => Bound::Included(prefix.to_vec()),
+    };
+    // The first key after every key starting with `prefix`, none when the prefix only
+    // holds 0xff bytes.
+    let mut end = prefix.to_vec();
+    while let Some(&last) = end.last() {
+        if last < u8::MAX {
+            *end.last_mut().unwrap() += 1;
+            return (start, Bound::Excluded(end));
+        }
+        end.pop();
+    }
+    (start, Bound::Unbounded)
+}
+
+/// Whether no key can be inside `bounds`. Ordered maps panic on such bounds.
+pub fn is_empty_range(bounds: &(Bound<Vec<u8>>, Bound<Vec<u8>>)) -> bool {
+    match bounds {
+        (Bound::Included(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end))
+        | (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        _ => false,
+    }
+}
@@ -1,64 +1,682 @@
-use super::KvsEngine;
+use super::expiry::{expiry_time, now_millis, time_left, Sweeper, SWEEP_INTERVAL};
+use super::scan::{is_empty_range, prefix_bounds};
+use super::{
+    add_delta, BatchOp, CompressionPolicy, KvsEngine, KvsSnapshot, SyncPolicy, Transaction,
+    WriteBatch,
+};
 use crate::error::{Error, ErrorKind, Result};
-use sled::Db;
+use sled::{
+    ConflictableTransactionError, Db, IVec, TransactionError, Transactional, TransactionalTree,
+    Tree,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::ops::Bound;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Duration;
+
+// Tree holding the expiry time of the expiring keys, in milliseconds since the unix epoch.
+const EXPIRIES_TREE: &[u8] = b"kvs_expiries";
 
 #[derive(Clone)]
 pub struct SledStore {
     store: Db,
+    expiries: Tree,
+    sync_policy: SyncPolicy,
+    // Held for reading by every write, and for writing while a snapshot is taken, a
+    // transaction runs or the expiry of a key changes.
+    writes: Arc<RwLock<()>>,
+    snapshots: Snapshots,
+    // Only held to stop the sweep thread along with the last handle of the store.
+    _sweeper: Arc<Sweeper>,
 }
 impl SledStore {
+    /// Open the sled database stored in `path`, flushing it after every write.
     pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::open_with_sync_policy(path, SyncPolicy::Always)
+    }
+
+    /// Open the sled database stored in `path`. `SyncPolicy::EveryMillis` relies on the
+    /// periodic flush of sled.
+    pub fn open_with_sync_policy(
+        path: impl Into<PathBuf>,
+        sync_policy: SyncPolicy,
+    ) -> Result<Self> {
+        Self::open_with_compression(path, sync_policy, CompressionPolicy::Off)
+    }
+
+    /// Open the sled database stored in `path`, compressing its data with zstd unless
+    /// `compression` is `CompressionPolicy::Off`.
+    ///
+    /// Sled compresses whole pages rather than single values, so `CompressionPolicy::Above`
+    /// compresses everything like `CompressionPolicy::Always`. Sled does not allow turning
+    /// compression on or off once the database is created.
+    pub fn open_with_compression(
+        path: impl Into<PathBuf>,
+        sync_policy: SyncPolicy,
+        compression: CompressionPolicy,
+    ) -> Result<Self> {
+        compression.check()?;
         let path: PathBuf = path.into();
-        let st = sled::Config::new().path(path).flush_every_ms(None).open();
-        match st {
-            Ok(store) => Ok(SledStore { store }),
+        let flush_every_ms = match sync_policy {
+            SyncPolicy::EveryMillis(millis) => Some(millis),
+            SyncPolicy::Always | SyncPolicy::Never => None,
+        };
+        let st = sled::Config::new()
+            .path(path)
+            .flush_every_ms(flush_every_ms)
+            .use_compression(compression != CompressionPolicy::Off)
+            .open();
+        let store = match st {
+            Ok(store) => store,
+            Err(_err) => return Err(Error::from(ErrorKind::SledError)),
+        };
+        let expiries = store
+            .open_tree(EXPIRIES_TREE)
+            .map_err(|_err| Error::from(ErrorKind::SledError))?;
+        let writes = Arc::new(RwLock::new(()));
+        let snapshots = Snapshots {
+            store: Tree::clone(&store),
+            expiries: expiries.clone(),
+            live: Arc::default(),
+        };
+        let sweeper = {
+            let (store, expiries, writes) = (store.clone(), expiries.clone(), writes.clone());
+            let snapshots = snapshots.clone();
+            Sweeper::spawn(SWEEP_INTERVAL, move || {
+                let _writes = writes.write().unwrap();
+                let _ = sweep(&store, &expiries, &snapshots);
+            })
+        };
+        Ok(SledStore {
+            store,
+            expiries,
+            sync_policy,
+            writes,
+            snapshots,
+            _sweeper: Arc::new(sweeper),
+        })
+    }
+
+    fn read_range(
+        &self,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if is_empty_range(&bounds) {
+            return Ok(Vec::new());
+        }
+        let now = now_millis();
+        let mut pairs = Vec::new();
+        for result in self.store.range(bounds) {
+            if pairs.len() == limit {
+                break;
+            }
+            let (key, value) = result.map_err(|_err| Error::from(ErrorKind::SledError))?;
+            if !is_expired(&self.expiries, &key, now)? {
+                pairs.push((key.to_vec(), value.to_vec()));
+            }
+        }
+        Ok(pairs)
+    }
+
+    fn expires_at(&self, key: &[u8]) -> Result<Option<u64>> {
+        read_expiry(&self.expiries, key)
+    }
+
+    // Get the value of `key` with its expiry time, `None` when the key is missing or expired.
+    fn live_entry(&self, key: &[u8]) -> Result<Option<(IVec, Option<u64>)>> {
+        let value = match self.store.get(key) {
+            Ok(Some(value)) => value,
+            Ok(None) => return Ok(None),
+            Err(_err) => return Err(Error::from(ErrorKind::SledError)),
+        };
+        match self.expires_at(key)? {
+            Some(expires_at) if expires_at <= now_millis() => Ok(None),
+            expires_at => Ok(Some((value, expires_at))),
+        }
+    }
+
+    // Apply `ops` and drop the expiry of their keys in one transaction, returning the previous
+    // value of each key.
+    fn write_clearing_expiries(&self, ops: &[BatchOp]) -> Result<Vec<Option<IVec>>> {
+        let result = (&*self.store, &self.expiries).transaction(|(store, expiries)| {
+            let mut previous = Vec::with_capacity(ops.len());
+            for op in ops {
+                let (key, replaced) = match op {
+                    BatchOp::Set(key, value) => (key, store.insert(&key[..], &value[..])?),
+                    BatchOp::Remove(key) => (key, store.remove(&key[..])?),
+                };
+                expiries.remove(&key[..])?;
+                previous.push(replaced);
+            }
+            Ok(previous)
+        });
+        result.map_err(|_err: TransactionError<()>| Error::from(ErrorKind::SledError))
+    }
+
+    // Whether one of the keys written by `ops` has an expiry, which the writes must drop.
+    fn expiring(&self, ops: &[BatchOp]) -> Result<bool> {
+        for op in ops {
+            let key = match op {
+                BatchOp::Set(key, _) | BatchOp::Remove(key) => key,
+            };
+            if self.expires_at(key)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn written(&self) -> Result<()> {
+        if self.sync_policy != SyncPolicy::Always {
+            return Ok(());
+        }
+        match self.store.flush() {
+            Ok(_something) => Ok(()),
             Err(_err) => Err(Error::from(ErrorKind::SledError)),
         }
     }
 }
+
+// Remove the expired keys along with their expiry.
+fn sweep(store: &Tree, expiries: &Tree, snapshots: &Snapshots) -> Result<()> {
+    let now = now_millis();
+    for result in expiries.iter() {
+        let (key, _expires_at) = result.map_err(|_err| Error::from(ErrorKind::SledError))?;
+        drop_if_expired(store, expiries, snapshots, &key, now)?;
+    }
+    Ok(())
+}
+
+// Remove `key` and its expiry if the key expired at `now`. A crash in between leaves an expiry
+// without a key, which the next write of the key drops.
+fn drop_if_expired(
+    store: &Tree,
+    expiries: &Tree,
+    snapshots: &Snapshots,
+    key: &[u8],
+    now: u64,
+) -> Result<()> {
+    if is_expired(expiries, key, now)? {
+        snapshots.preserve(key)?;
+        store
+            .remove(key)
+            .and_then(|_removed| expiries.remove(key))
+            .map_err(|_err| Error::from(ErrorKind::SledError))?;
+    }
+    Ok(())
+}
+
+fn is_expired(expiries: &Tree, key: &[u8], now: u64) -> Result<bool> {
+    match expiries.get(key) {
+        Ok(expires_at) => {
+            Ok(expires_at.is_some_and(|expires_at| decode_expiry(&expires_at) <= now))
+        }
+        Err(_err) => Err(Error::from(ErrorKind::SledError)),
+    }
+}
+
+fn read_expiry(expiries: &Tree, key: &[u8]) -> Result<Option<u64>> {
+    match expiries.get(key) {
+        Ok(expires_at) => Ok(expires_at.map(|expires_at| decode_expiry(&expires_at))),
+        Err(_err) => Err(Error::from(ErrorKind::SledError)),
+    }
+}
+
+fn decode_expiry(bytes: &[u8]) -> u64 {
+    bytes.try_into().map_or(0, u64::from_le_bytes)
+}
+
+// Value of a key with its expiry time, whether expired or not.
+type Entry = (IVec, Option<u64>);
+
+// The value of `entry` if it is live at `now`.
+fn live_at(entry: Option<Entry>, now: u64) -> Option<IVec> {
+    match entry {
+        Some((_value, Some(expires_at))) if expires_at <= now => None,
+        Some((value, _expires_at)) => Some(value),
+        None => None,
+    }
+}
+
+// Entries a snapshot still sees but the trees do not: the entry of every key written since
+// the snapshot was taken, as it was then, `None` for the keys that were missing.
+#[derive(Default)]
+struct Preserved {
+    entries: Mutex<BTreeMap<Vec<u8>, Option<Entry>>>,
+}
+
+// The snapshots taken from a store and not dropped yet.
+//
+// Every write preserves the entries of its keys for these snapshots before changing them. A
+// snapshot looks a key up in its preserved entries and then in the trees while holding the
+// lock of its entries, so the key can not change in between. A snapshot is registered while
+// the writes are paused, so no write has preserved its keys without applying them yet.
+#[derive(Clone)]
+struct Snapshots {
+    store: Tree,
+    expiries: Tree,
+    live: Arc<Mutex<Vec<Weak<Preserved>>>>,
+}
+
+impl Snapshots {
+    // Register a new snapshot. The writes must be paused.
+    fn register(&self) -> Arc<Preserved> {
+        let preserved = Arc::new(Preserved::default());
+        let mut live = self.live.lock().unwrap();
+        live.retain(|snapshot| snapshot.strong_count() > 0);
+        live.push(Arc::downgrade(&preserved));
+        preserved
+    }
+
+    // Preserve the entry of `key` for the live snapshots which have not yet, before it is
+    // written.
+    fn preserve(&self, key: &[u8]) -> Result<()> {
+        let snapshots: Vec<Arc<Preserved>> = {
+            let live = self.live.lock().unwrap();
+            live.iter().filter_map(Weak::upgrade).collect()
+        };
+        for snapshot in snapshots {
+            let mut entries = snapshot.entries.lock().unwrap();
+            if !entries.contains_key(key) {
+                entries.insert(key.to_vec(), self.read_entry(key)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_entry(&self, key: &[u8]) -> Result<Option<Entry>> {
+        match self.store.get(key) {
+            Ok(Some(value)) => Ok(Some((value, read_expiry(&self.expiries, key)?))),
+            Ok(None) => Ok(None),
+            Err(_err) => Err(Error::from(ErrorKind::SledError)),
+        }
+    }
+}
+
 impl KvsEngine for SledStore {
-    fn get(&self, key: String) -> Result<Option<String>> {
-        let result = self.store.get(key);
+    type Snapshot = SledSnapshot;
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .live_entry(&key)?
+            .map(|(value, _expires_at)| value.to_vec()))
+    }
+
+    fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        {
+            let _writes = self.writes.read().unwrap();
+            self.snapshots.preserve(&key)?;
+            if self.expires_at(&key)?.is_some() {
+                self.write_clearing_expiries(&[BatchOp::Set(key, value)])?;
+            } else {
+                self.store
+                    .insert(key, value)
+                    .map_err(|_err| Error::from(ErrorKind::SledError))?;
+            }
+        }
+        self.written()
+    }
+
+    /// Set the key and its expiry time in a transaction over both trees.
+    ///
+    /// The expiry of a key changes while the other writes are paused, so a concurrent `set`
+    /// can not miss an expiry it has to drop.
+    fn set_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let expires_at = expiry_time(ttl).to_le_bytes();
+        let result = {
+            let _writes = self.writes.write().unwrap();
+            self.snapshots.preserve(&key)?;
+            (&*self.store, &self.expiries).transaction(|(store, expiries)| {
+                store.insert(&key[..], &value[..])?;
+                expiries.insert(&key[..], &expires_at[..])?;
+                Ok(())
+            })
+        };
         match result {
-            Ok(Some(s)) => {
-                let value = String::from_utf8(s.to_vec());
-                match value {
-                    Ok(vl) => Ok(Some(vl)),
-                    _ => Err(Error::from(ErrorKind::SledError)),
+            Ok(()) => self.written(),
+            Err(_err) => Err(Error::from(ErrorKind::SledError)),
+        }
+    }
+
+    fn expire(&self, key: Vec<u8>, ttl: Duration) -> Result<bool> {
+        {
+            let _writes = self.writes.write().unwrap();
+            if self.live_entry(&key)?.is_none() {
+                return Ok(false);
+            }
+            self.snapshots.preserve(&key)?;
+            self.expiries
+                .insert(key, &expiry_time(ttl).to_le_bytes()[..])
+                .map_err(|_err| Error::from(ErrorKind::SledError))?;
+        }
+        self.written().map(|()| true)
+    }
+
+    fn ttl(&self, key: Vec<u8>) -> Result<Option<Duration>> {
+        match self.live_entry(&key)? {
+            Some((_value, expires_at)) => Ok(expires_at.map(time_left)),
+            None => Err(Error::from(ErrorKind::KeyNotFound)),
+        }
+    }
+
+    fn persist(&self, key: Vec<u8>) -> Result<bool> {
+        {
+            let _writes = self.writes.write().unwrap();
+            match self.live_entry(&key)? {
+                Some((_value, Some(_expires_at))) => {
+                    self.snapshots.preserve(&key)?;
+                    self.expiries
+                        .remove(key)
+                        .map_err(|_err| Error::from(ErrorKind::SledError))?
+                }
+                Some((_, None)) | None => return Ok(false),
+            };
+        }
+        self.written().map(|()| true)
+    }
+
+    /// Add `delta` to the integer stored in a key with `sled::Tree::update_and_fetch`, which
+    /// keeps the expiry of the key.
+    ///
+    /// The expiry of a key only changes while the writes are paused, so a key found live stays
+    /// so until the update is done. An expired key is dropped first, and counts as 0.
+    fn increment(&self, key: Vec<u8>, delta: i64) -> Result<i64> {
+        loop {
+            {
+                let _writes = self.writes.read().unwrap();
+                if !is_expired(&self.expiries, &key, now_millis())? {
+                    self.snapshots.preserve(&key)?;
+                    let mut result = Ok(0);
+                    self.store
+                        .update_and_fetch(&key, |value| {
+                            result = add_delta(value, delta);
+                            match result {
+                                Ok(sum) => Some(sum.to_string().into_bytes()),
+                                // Returning the current value leaves the key untouched.
+                                Err(_) => value.map(<[u8]>::to_vec),
+                            }
+                        })
+                        .map_err(|_err| Error::from(ErrorKind::SledError))?;
+                    return result.and_then(|sum| self.written().map(|()| sum));
                 }
             }
-            Ok(None) => Ok(None),
-            Err(_) => Err(Error::from(ErrorKind::SledError)),
+            let _writes = self.writes.write().unwrap();
+            drop_if_expired(
+                &self.store,
+                &self.expiries,
+                &self.snapshots,
+                &key,
+                now_millis(),
+            )?;
         }
     }
 
-    fn set(&self, key: String, value: String) -> Result<()> {
-        let result = self.store.insert(key, value.as_bytes());
-        match result {
-            Ok(_something) => {
-                let res = self.store.flush();
-                match res {
-                    Ok(_something) => Ok(()),
-                    Err(_err) => Err(Error::from(ErrorKind::SledError)),
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range((Bound::Included(start), Bound::Excluded(end)), limit)
+    }
+
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range(prefix_bounds(&prefix, after), limit)
+    }
+
+    /// Apply the writes of `batch` with a `sled::Batch`, which sled applies atomically, or in
+    /// a transaction when the expiry of one of its keys has to be dropped.
+    fn write_batch(&self, batch: WriteBatch) -> Result<()> {
+        let ops: Vec<BatchOp> = batch.into_iter().collect();
+        {
+            let _writes = self.writes.read().unwrap();
+            for op in &ops {
+                match op {
+                    BatchOp::Set(key, _) | BatchOp::Remove(key) => self.snapshots.preserve(key)?,
+                }
+            }
+            if self.expiring(&ops)? {
+                self.write_clearing_expiries(&ops)?;
+            } else {
+                let mut sled_batch = sled::Batch::default();
+                for op in ops {
+                    match op {
+                        BatchOp::Set(key, value) => sled_batch.insert(key, value),
+                        BatchOp::Remove(key) => sled_batch.remove(key),
+                    }
                 }
+                self.store
+                    .apply_batch(sled_batch)
+                    .map_err(|_err| Error::from(ErrorKind::SledError))?;
             }
-            Err(_) => Err(Error::from(ErrorKind::SledError)),
         }
+        self.written()
     }
 
-    fn remove(&self, key: String) -> Result<()> {
-        let result = self.store.remove(key);
+    /// Run `f` in a sled transaction over the keys and their expiry times.
+    ///
+    /// sled runs its transactions one at a time, but its other writes do not wait for them, so
+    /// the writes through this store are paused while `f` runs. `f` must not write to the
+    /// store outside of the transaction.
+    fn transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: Fn(&mut dyn Transaction) -> Result<T>,
+    {
+        // sled only aborts transactions over several trees with `()`, the error of `f` is
+        // kept aside.
+        let failure = RefCell::new(None);
+        let result = {
+            let _writes = self.writes.write().unwrap();
+            (&*self.store, &self.expiries).transaction(|(tree, expiries)| {
+                let mut tx = SledTransaction {
+                    tree,
+                    expiries,
+                    snapshots: &self.snapshots,
+                    conflict: false,
+                };
+                match f(&mut tx) {
+                    _ if tx.conflict => Err(ConflictableTransactionError::Conflict),
+                    Ok(value) => Ok(value),
+                    Err(err) => {
+                        failure.replace(Some(err));
+                        Err(ConflictableTransactionError::Abort(()))
+                    }
+                }
+            })
+        };
         match result {
-            Ok(Some(_thing)) => {
-                let res = self.store.flush();
-                match res {
-                    Ok(_something) => Ok(()),
-                    Err(_err) => Err(Error::from(ErrorKind::SledError)),
+            Ok(value) => self.written().map(|()| value),
+            Err(TransactionError::Abort(())) => Err(failure
+                .into_inner()
+                .unwrap_or_else(|| Error::from(ErrorKind::SledError))),
+            Err(TransactionError::Storage(_err)) => Err(Error::from(ErrorKind::SledError)),
+        }
+    }
+
+    /// Take a copy-on-write view of the database.
+    ///
+    /// sled only guarantees that each single-key operation is atomic and that an iterator
+    /// sees every key at most once, not a consistent view of several keys. Instead of copying
+    /// the database, the snapshot reads the trees and every write through this store keeps
+    /// the previous entry of its keys for the live snapshots. A snapshot thus holds memory
+    /// proportional to the keys written while it lives, and each write reads its keys once
+    /// more per live snapshot. The writes are only paused to register the snapshot. The keys
+    /// expired at that time are left out.
+    fn snapshot(&self) -> Result<SledSnapshot> {
+        let _writes = self.writes.write().unwrap();
+        Ok(SledSnapshot {
+            snapshots: self.snapshots.clone(),
+            preserved: self.snapshots.register(),
+            now: now_millis(),
+        })
+    }
+
+    fn remove(&self, key: Vec<u8>) -> Result<()> {
+        let removed = {
+            let _writes = self.writes.read().unwrap();
+            self.snapshots.preserve(&key)?;
+            match self.expires_at(&key)? {
+                Some(expires_at) => {
+                    let removed = self.write_clearing_expiries(&[BatchOp::Remove(key)])?;
+                    removed[0].is_some() && expires_at > now_millis()
                 }
+                None => match self.store.remove(key) {
+                    Ok(removed) => removed.is_some(),
+                    Err(_err) => return Err(Error::from(ErrorKind::UnknownError)),
+                },
             }
-            Ok(None) => Err(Error::from(ErrorKind::KeyNotFound)),
-            Err(_err) => Err(Error::from(ErrorKind::UnknownError)),
+        };
+        if removed {
+            self.written()
+        } else {
+            Err(Error::from(ErrorKind::KeyNotFound))
         }
     }
 }
+
+/// Read-only view of a `SledStore` taken by `KvsEngine::snapshot`.
+pub struct SledSnapshot {
+    snapshots: Snapshots,
+    preserved: Arc<Preserved>,
+    // Time the snapshot was taken, the keys expired then are left out.
+    now: u64,
+}
+
+impl SledSnapshot {
+    // Merge the preserved entries in `bounds` with the keys of the tree which were not written
+    // since the snapshot was taken.
+    fn read_range(
+        &self,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        if is_empty_range(&bounds) {
+            return Ok(Vec::new());
+        }
+        let entries = self.preserved.entries.lock().unwrap();
+        let mut preserved = entries.range(bounds.clone()).peekable();
+        let mut tree = self.snapshots.store.range(bounds);
+        let mut next_in_tree = next_pair(&mut tree)?;
+        let mut pairs = Vec::new();
+        while pairs.len() < limit {
+            let from_preserved = match (&next_in_tree, preserved.peek()) {
+                (_, None) => false,
+                (None, Some(_)) => true,
+                (Some((tree_key, _)), Some((key, _))) => key[..] <= tree_key[..],
+            };
+            let (key, entry) = if from_preserved {
+                let (key, entry) = match preserved.next() {
+                    Some(preserved) => preserved,
+                    None => break,
+                };
+                if let Some((tree_key, _)) = &next_in_tree {
+                    if tree_key[..] == key[..] {
+                        next_in_tree = next_pair(&mut tree)?;
+                    }
+                }
+                (key.clone(), entry.clone())
+            } else {
+                let (key, value) = match next_in_tree.take() {
+                    Some(pair) => pair,
+                    None => break,
+                };
+                next_in_tree = next_pair(&mut tree)?;
+                let expires_at = read_expiry(&self.snapshots.expiries, &key)?;
+                (key.to_vec(), Some((value, expires_at)))
+            };
+            if let Some(value) = live_at(entry, self.now) {
+                pairs.push((key, value.to_vec()));
+            }
+        }
+        Ok(pairs)
+    }
+}
+
+fn next_pair(iter: &mut sled::Iter) -> Result<Option<(IVec, IVec)>> {
+    iter.next()
+        .transpose()
+        .map_err(|_err| Error::from(ErrorKind::SledError))
+}
+
+impl KvsSnapshot for SledSnapshot {
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let entries = self.preserved.entries.lock().unwrap();
+        let entry = match entries.get(&key) {
+            Some(entry) => entry.clone(),
+            None => self.snapshots.read_entry(&key)?,
+        };
+        Ok(live_at(entry, self.now).map(|value| value.to_vec()))
+    }
+
+    fn scan(&self, start: Vec<u8>, end: Vec<u8>, limit: usize) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range((Bound::Included(start), Bound::Excluded(end)), limit)
+    }
+
+    fn scan_prefix_after(
+        &self,
+        prefix: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_range(prefix_bounds(&prefix, after), limit)
+    }
+}
+
+// Adapter of a sled transaction to `Transaction`. A conflict reported by sled is remembered
+// so the transaction is retried, whatever the closure does with the error.
+struct SledTransaction<'a> {
+    tree: &'a TransactionalTree,
+    expiries: &'a TransactionalTree,
+    snapshots: &'a Snapshots,
+    conflict: bool,
+}
+
+impl<'a> SledTransaction<'a> {
+    fn check<T, E>(&mut self, result: std::result::Result<T, E>) -> Result<T>
+    where
+        E: Into<ConflictableTransactionError>,
+    {
+        match result.map_err(Into::into) {
+            Ok(value) => Ok(value),
+            Err(ConflictableTransactionError::Conflict) => {
+                self.conflict = true;
+                Err(Error::from(ErrorKind::TransactionConflict))
+            }
+            Err(_err) => Err(Error::from(ErrorKind::SledError)),
+        }
+    }
+}
+
+impl<'a> Transaction for SledTransaction<'a> {
+    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let result = self.expiries.get(&key);
+        if let Some(expires_at) = self.check(result)? {
+            if decode_expiry(&expires_at) <= now_millis() {
+                return Ok(None);
+            }
+        }
+        let result = self.tree.get(key);
+        self.check(result)
+            .map(|value| value.map(|value| value.to_vec()))
+    }
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.snapshots.preserve(&key)?;
+        let result = self.expiries.remove(&key[..]);
+        self.check(result)?;
+        let result = self.tree.insert(key, value);
+        self.check(result).map(|_replaced| ())
+    }
+
+    fn remove(&mut self, key: Vec<u8>) -> Result<()> {
+        self.snapshots.preserve(&key)?;
+        let result = self.expiries.remove(&key[..]);
+        self.check(result)?;
+        let result = self.tree.remove(key);
+        self.check(result).map(|_removed| ())
+    }
+}
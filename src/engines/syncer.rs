@@ -0,0 +1,47 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Handle of the thread that periodically syncs the files of an engine when it uses
+/// `SyncPolicy::EveryMillis`.
+///
+/// Dropping it stops the thread after a last sync, so closing the engine never leaves
+/// acknowledged writes unsynced.
+pub struct Syncer {
+    sender: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Syncer {
+    pub fn spawn<F>(interval: Duration, mut sync: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || loop {
+            // Nothing is ever sent, the channel only wakes up the thread when it is dropped.
+            let stop = match receiver.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => false,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+            };
+            sync();
+            if stop {
+                break;
+            }
+        });
+
+        Syncer {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Syncer {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use crate::error::Result;
+
+/// Reads and writes of a transaction run by `KvsEngine::transaction`.
+///
+/// The writes are only applied once the transaction commits, but they are visible to its own
+/// reads. Like in a `WriteBatch`, removing a key that does not exist is not an error.
+pub trait Transaction {
+    fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+
+    fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
+
+    fn remove(&mut self, key: Vec<u8>) -> Result<()>;
+}
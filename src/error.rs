@@ -16,6 +16,12 @@ pub enum KvsErrorKind {
     #[fail(display = "Data too short")]
     DataTooShort(usize),
 
+    #[fail(display = "Key or value too large")]
+    DataTooLarge,
+
+    #[fail(display = "Integer overflow")]
+    IntegerOverflow,
+
     #[fail(display = "Invalid Command.")]
     InvalidCommand,
 
@@ -25,12 +31,24 @@ pub enum KvsErrorKind {
     #[fail(display = "Invalid Engine.")]
     InvalidEngine,
 
+    #[fail(display = "Unsupported log format version")]
+    InvalidFormatVersion(u8),
+
     #[fail(display = "Invalid prefix")]
     InvalidPrefix(u8),
 
     #[fail(display = "Key not found")]
     KeyNotFound,
 
+    #[fail(display = "Value is not an integer")]
+    NotAnInteger,
+
+    #[fail(display = "Corrupted record in log file")]
+    CorruptedRecord,
+
+    #[fail(display = "Incomplete record in log file")]
+    TornRecord,
+
     #[fail(display = "Error with log file")]
     FileError,
 
@@ -40,6 +58,15 @@ pub enum KvsErrorKind {
     #[fail(display = "Error from sled crate")]
     SledError,
 
+    #[fail(display = "Missing or wrong encryption key")]
+    UnknownEncryptionKey(u32),
+
+    #[fail(display = "Unsupported compression codec")]
+    UnsupportedCodec,
+
+    #[fail(display = "Transaction conflict")]
+    TransactionConflict,
+
     #[fail(display = "Uncompatible Engine")]
     UncompatibleEngine,
 
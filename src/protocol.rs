@@ -1,25 +1,45 @@
 use crate::command::Command;
 use crate::error::{Error, ErrorKind, Result};
 
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::net::TcpStream;
-use std::str::FromStr;
 
 pub struct StreamHandler {
     pub reader: BufReader<TcpStream>,
 }
 
+/// Message exchanged between the client and the server.
+///
+/// Every message starts with a prefix byte and a line ended by CRLF. Bytes and commands are
+/// length prefixed, so they can hold any byte, including spaces and newlines:
+///
+/// ```text
+/// ;\r\n                                      None
+/// ?<message>\r\n                             Error
+/// #<integer>\r\n                             Integer
+/// $<len>\r\n<bytes>\r\n                      Bytes
+/// !<argc>\r\n$<len>\r\n<bytes>\r\n...        Command, as its name followed by its arguments
+/// &<len>\r\n<command>...                     Batch, followed by its commands
+/// *<len>\r\n<value>...                       Array, followed by its values
+/// ```
 #[derive(Debug)]
 pub enum Value {
     None,
     Command(Command),
+    /// Commands applied together as a `WriteBatch`.
+    Batch(Vec<Command>),
     Error(String),
-    String(String),
+    Bytes(Vec<u8>),
     Integer(i64),
+    Array(Vec<Value>),
 }
 
 const CRLF_BYTES: &[u8] = b"\r\n";
 
+/// Deepest nesting of arrays and batches accepted by `StreamHandler::decode`. Messages come
+/// from the network, so the recursion of the decoder must not be bounded by the peer only.
+pub const MAX_NESTING: usize = 32;
+
 impl Value {
     pub fn encode(&self) -> Vec<u8> {
         let mut res: Vec<u8> = Vec::new();
@@ -27,22 +47,35 @@ impl Value {
             Value::None => {
                 res.push(b';');
             }
-            Value::Command(cmd) => {
-                res.push(b'!');
-                res.extend_from_slice(cmd.to_string().as_bytes());
+            Value::Command(cmd) => encode_command(&mut res, cmd),
+            Value::Batch(commands) => {
+                res.push(b'&');
+                res.extend_from_slice(commands.len().to_string().as_bytes());
+                res.extend_from_slice(CRLF_BYTES);
+                for command in commands {
+                    encode_command(&mut res, command);
+                    res.extend_from_slice(CRLF_BYTES);
+                }
+                return res;
             }
             Value::Error(err) => {
                 res.push(b'?');
                 res.extend_from_slice(err.as_bytes())
             }
-            Value::String(s) => {
-                res.push(b'$');
-                res.extend_from_slice(s.as_bytes())
-            }
+            Value::Bytes(bytes) => encode_bytes(&mut res, bytes),
             Value::Integer(num) => {
                 res.push(b'#');
                 res.extend_from_slice(num.to_string().as_bytes());
             }
+            Value::Array(values) => {
+                res.push(b'*');
+                res.extend_from_slice(values.len().to_string().as_bytes());
+                res.extend_from_slice(CRLF_BYTES);
+                for value in values {
+                    res.extend_from_slice(&value.encode());
+                }
+                return res;
+            }
         }
         res.extend_from_slice(CRLF_BYTES);
         res
@@ -54,7 +87,60 @@ impl StreamHandler {
         Self { reader }
     }
 
+    /// Read the next message. A message nesting arrays or batches deeper than `MAX_NESTING`
+    /// fails with `InvalidData`.
     pub fn decode(&mut self) -> Result<Value> {
+        self.decode_nested(0)
+    }
+
+    fn decode_nested(&mut self, depth: usize) -> Result<Value> {
+        if depth > MAX_NESTING {
+            return Err(Error::from(ErrorKind::InvalidData));
+        }
+        let (prefix, bytes) = self.read_line()?;
+        match prefix {
+            b'!' => {
+                let argc = parse_length(&bytes)?;
+                let mut args = Vec::new();
+                for _ in 0..argc {
+                    match self.read_line()? {
+                        (b'$', bytes) => args.push(self.read_bytes(parse_length(&bytes)?)?),
+                        (prefix, _bytes) => {
+                            return Err(Error::from(ErrorKind::InvalidPrefix(prefix)))
+                        }
+                    }
+                }
+                Command::from_args(args).map(Value::Command)
+            }
+            b'&' => {
+                let len = parse_length(&bytes)?;
+                let mut commands = Vec::new();
+                for _ in 0..len {
+                    match self.decode_nested(depth + 1)? {
+                        Value::Command(command) => commands.push(command),
+                        _ => return Err(Error::from(ErrorKind::InvalidData)),
+                    }
+                }
+                Ok(Value::Batch(commands))
+            }
+            b'?' => parse_string(&bytes).map(Value::Error),
+            b'$' => self.read_bytes(parse_length(&bytes)?).map(Value::Bytes),
+            b'#' => parse_integer(&bytes).map(Value::Integer),
+            b'*' => {
+                let len = parse_length(&bytes)?;
+                let mut values = Vec::new();
+                for _ in 0..len {
+                    values.push(self.decode_nested(depth + 1)?);
+                }
+                Ok(Value::Array(values))
+            }
+            b';' => Ok(Value::None),
+            prefix => Err(Error::from(ErrorKind::InvalidPrefix(prefix))),
+        }
+    }
+
+    // Read a line ended by CRLF, returning its prefix and the bytes that follow it.
+    fn read_line(&mut self) -> Result<(u8, Vec<u8>)> {
         let mut res: Vec<u8> = Vec::new();
         self.reader
             .read_until(b'\n', &mut res)
@@ -69,21 +155,50 @@ impl StreamHandler {
         if !is_crlf(res[len - 2], res[len - 1]) {
             return Err(Error::from(ErrorKind::InvalidData));
         }
-        let bytes = res[1..len - 2].as_ref();
-        match res[0] {
-            // Value::String
-            b'!' => parse_command(bytes).map(Value::Command),
-            // Value::Error
-            b'?' => parse_string(bytes).map(Value::Error),
-            b'$' => parse_string(bytes).map(Value::String),
-            // Value::Integer
-            b'#' => parse_integer(bytes).map(Value::Integer),
-            b';' => Ok(Value::None),
-            prefix => Err(Error::from(ErrorKind::InvalidPrefix(prefix))),
+        res.truncate(len - 2);
+        Ok((res[0], res.split_off(1)))
+    }
+
+    // Read `len` bytes followed by CRLF.
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        // The length comes from the peer, so the buffer is not allocated up front.
+        let framed_len = len
+            .checked_add(2)
+            .ok_or_else(|| Error::from(ErrorKind::InvalidData))?;
+        let mut res = Vec::new();
+        let read = (&mut self.reader)
+            .take(framed_len as u64)
+            .read_to_end(&mut res)
+            .map_err(|_err| Error::from(ErrorKind::InvalidData))?;
+        if read != framed_len {
+            return Err(Error::from(ErrorKind::DataTooShort(read)));
+        }
+        if !is_crlf(res[len], res[len + 1]) {
+            return Err(Error::from(ErrorKind::InvalidData));
         }
+        res.truncate(len);
+        Ok(res)
     }
 }
 
+fn encode_command(res: &mut Vec<u8>, cmd: &Command) {
+    let args = cmd.args();
+    res.push(b'!');
+    res.extend_from_slice(args.len().to_string().as_bytes());
+    for arg in args {
+        res.extend_from_slice(CRLF_BYTES);
+        encode_bytes(res, &arg);
+    }
+}
+
+#[inline]
+fn encode_bytes(res: &mut Vec<u8>, bytes: &[u8]) {
+    res.push(b'$');
+    res.extend_from_slice(bytes.len().to_string().as_bytes());
+    res.extend_from_slice(CRLF_BYTES);
+    res.extend_from_slice(bytes);
+}
+
 #[inline]
 fn is_crlf(a: u8, b: u8) -> bool {
     a == b'\r' && b == b'\n'
@@ -101,7 +216,7 @@ fn parse_integer(bytes: &[u8]) -> Result<i64> {
 }
 
 #[inline]
-fn parse_command(bytes: &[u8]) -> Result<Command> {
-    let str_command = parse_string(bytes)?;
-    Command::from_str(&str_command)
+fn parse_length(bytes: &[u8]) -> Result<usize> {
+    let str_length = parse_string(bytes)?;
+    (str_length.parse::<usize>()).map_err(|_err| Error::from(ErrorKind::InvalidData))
 }
@@ -2,17 +2,22 @@ use crate::error::{Error, ErrorKind, Result};
 use std::net::ToSocketAddrs;
 use std::net::{TcpListener, TcpStream};
 
+use crate::command::Command;
 use crate::connection::Connection;
 use crate::engines::KvsEngine;
 use crate::protocol::Value;
 use crate::thread_pool::*;
 use slog::Logger;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
 
 pub struct KvsServer<TP: ThreadPool, Engine: KvsEngine> {
     listener: TcpListener,
     engine: Engine,
     pool: TP,
     logger: Logger,
+    watches: Watches,
 }
 
 impl<TP: ThreadPool, E: KvsEngine> KvsServer<TP, E> {
@@ -31,6 +36,7 @@ impl<TP: ThreadPool, E: KvsEngine> KvsServer<TP, E> {
             engine,
             logger,
             pool,
+            watches: Watches::default(),
         })
     }
 
@@ -39,8 +45,9 @@ impl<TP: ThreadPool, E: KvsEngine> KvsServer<TP, E> {
             let client = stream.map_err(|_err| Error::from(ErrorKind::ConnectionError))?;
             let engine = self.engine.clone();
             let logger = self.logger.clone();
+            let watches = self.watches.clone();
             self.pool.spawn(move || {
-                match handle_client(client, engine, &logger) {
+                match handle_client(client, engine, watches, &logger) {
                     Ok(_) => (),
                     Err(_err) => info!(logger, "There was a problem."),
                 };
@@ -53,28 +60,33 @@ impl<TP: ThreadPool, E: KvsEngine> KvsServer<TP, E> {
 fn handle_client<Engine: KvsEngine>(
     stream: TcpStream,
     engine: Engine,
+    watches: Watches,
     logger: &Logger,
 ) -> Result<()> {
     let mut conn = Connection::from_stream(stream);
+    let mut session = Session::new(watches);
     debug!(logger, "Handling new client");
     while match conn.read() {
         Ok(value) => {
-            if let Value::Command(command) = value {
-                let result = engine.exec_command(command);
+            let result = match value {
+                Value::Command(command) => Some(session.exec(&engine, command)),
+                Value::Batch(commands) => Some(session.exec_batch(&engine, commands)),
+                _ => None,
+            };
+            if let Some(result) = result {
                 match result {
-                    Ok(None) => {
-                        let val = Value::None;
-                        debug!(logger, "Sending {:?}", val);
-                        conn.write(&val.encode()).unwrap();
-                    }
-                    Ok(Some(s)) => {
-                        let val = Value::String(s);
+                    Ok(val) => {
                         debug!(logger, "Sending {:?}", val);
                         conn.write(&val.encode()).unwrap();
                     }
                     Err(ref err) => match err.kind() {
-                        ErrorKind::KeyNotFound => {
-                            let val = Value::Error("KeyNotFound".to_owned());
+                        // Errors caused by the request, replied with the name of their kind.
+                        ErrorKind::KeyNotFound
+                        | ErrorKind::DataTooLarge
+                        | ErrorKind::InvalidCommand
+                        | ErrorKind::NotAnInteger
+                        | ErrorKind::IntegerOverflow => {
+                            let val = Value::Error(format!("{:?}", err.kind()));
                             debug!(logger, "Sending {:?}", val);
                             conn.write(&val.encode()).unwrap();
                         }
@@ -92,3 +104,197 @@ fn handle_client<Engine: KvsEngine>(
 
     Ok(())
 }
+
+// Transaction state of a connection: the keys watched with their version at the time, and
+// the commands queued since `Command::Multi`.
+struct Session {
+    watches: Watches,
+    watched: Vec<(Vec<u8>, u64)>,
+    queued: Option<Vec<Command>>,
+}
+
+impl Session {
+    fn new(watches: Watches) -> Self {
+        Session {
+            watches,
+            watched: Vec::new(),
+            queued: None,
+        }
+    }
+
+    // Queue the command while a transaction is open, run it otherwise. The commands that can
+    // not be queued are refused without closing the transaction.
+    fn exec<E: KvsEngine>(&mut self, engine: &E, command: Command) -> Result<Value> {
+        if let Some(queued) = &mut self.queued {
+            return match command {
+                Command::Get(_) | Command::Set(_, _) | Command::Rm(_) => {
+                    queued.push(command);
+                    Ok(Value::None)
+                }
+                Command::Exec => {
+                    let commands = mem::take(queued);
+                    self.queued = None;
+                    let written: Vec<&[u8]> = commands.iter().filter_map(written_key).collect();
+                    let result = self.watches.exec(&self.watched, &written, || {
+                        engine.exec_transaction(&commands)
+                    });
+                    self.unwatch();
+                    result
+                }
+                Command::Discard => {
+                    self.queued = None;
+                    self.unwatch();
+                    Ok(Value::None)
+                }
+                _ => Err(Error::from(ErrorKind::InvalidCommand)),
+            };
+        }
+        match command {
+            Command::Watch(key) => {
+                let version = self.watches.watch(&key);
+                self.watched.push((key, version));
+                Ok(Value::None)
+            }
+            Command::Multi => {
+                self.queued = Some(Vec::new());
+                Ok(Value::None)
+            }
+            command => match written_key(&command) {
+                Some(key) => {
+                    let key = key.to_vec();
+                    self.watches.write(&[&key], || engine.exec_command(command))
+                }
+                None => engine.exec_command(command),
+            },
+        }
+    }
+
+    // Apply the commands of a `Value::Batch` frame, which is refused while a transaction is
+    // open rather than run ahead of the queued commands.
+    fn exec_batch<E: KvsEngine>(&mut self, engine: &E, commands: Vec<Command>) -> Result<Value> {
+        if self.queued.is_some() {
+            return Err(Error::from(ErrorKind::InvalidCommand));
+        }
+        let written: Vec<Vec<u8>> = commands
+            .iter()
+            .filter_map(written_key)
+            .map(<[u8]>::to_vec)
+            .collect();
+        let written: Vec<&[u8]> = written.iter().map(Vec::as_slice).collect();
+        self.watches.write(&written, || engine.exec_batch(commands))
+    }
+
+    fn unwatch(&mut self) {
+        for (key, _version) in self.watched.drain(..) {
+            self.watches.unwatch(&key);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.unwatch();
+    }
+}
+
+// Key written by `command`, if it writes one.
+fn written_key(command: &Command) -> Option<&[u8]> {
+    match command {
+        Command::Rm(key)
+        | Command::Set(key, _)
+        | Command::CompareAndSwap(key, _, _)
+        | Command::SetIfAbsent(key, _)
+        | Command::SetIfPresent(key, _)
+        | Command::SetWithTtl(key, _, _)
+        | Command::Expire(key, _)
+        | Command::Persist(key)
+        | Command::Incr(key)
+        | Command::Decr(key)
+        | Command::IncrBy(key, _) => Some(key),
+        Command::Get(_)
+        | Command::Scan(_, _, _)
+        | Command::Ttl(_)
+        | Command::Watch(_)
+        | Command::Multi
+        | Command::Exec
+        | Command::Discard => None,
+    }
+}
+
+// Modification counters of the keys watched by the connections of a server, with the number
+// of connections watching each of them.
+//
+// Comparing the values of the watched keys would miss a key written and then written back to
+// its watched value. Every write through the server bumps the counter of its keys before and
+// after it is applied, so a watch taken while a write is in progress is also invalidated by
+// it. `Watches::exec` checks the counters and runs the transaction while holding the lock, so
+// no write can start or end in between, at the cost of pausing the other writes through the
+// server meanwhile. A key dropped because it expired is not counted as written.
+#[derive(Clone, Default)]
+struct Watches {
+    keys: Arc<Mutex<HashMap<Vec<u8>, Watched>>>,
+}
+
+struct Watched {
+    version: u64,
+    watchers: usize,
+}
+
+impl Watches {
+    // Start watching `key`, returning its current version.
+    fn watch(&self, key: &[u8]) -> u64 {
+        let mut keys = self.keys.lock().unwrap();
+        let watched = keys.entry(key.to_vec()).or_insert(Watched {
+            version: 0,
+            watchers: 0,
+        });
+        watched.watchers += 1;
+        watched.version
+    }
+
+    fn unwatch(&self, key: &[u8]) {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(watched) = keys.get_mut(key) {
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                keys.remove(key);
+            }
+        }
+    }
+
+    // Run `write`, which writes `written`, bumping the versions of the watched ones.
+    fn write<T>(&self, written: &[&[u8]], write: impl FnOnce() -> T) -> T {
+        bump(&mut self.keys.lock().unwrap(), written);
+        let result = write();
+        bump(&mut self.keys.lock().unwrap(), written);
+        result
+    }
+
+    // Run the transaction `exec`, which writes `written`, unless one of the `watched` keys was
+    // written since it was watched. Replies `Value::None` without running it otherwise.
+    fn exec(
+        &self,
+        watched: &[(Vec<u8>, u64)],
+        written: &[&[u8]],
+        exec: impl FnOnce() -> Result<Value>,
+    ) -> Result<Value> {
+        let mut keys = self.keys.lock().unwrap();
+        let modified = watched
+            .iter()
+            .any(|(key, version)| keys.get(key).map(|watched| watched.version) != Some(*version));
+        if modified {
+            return Ok(Value::None);
+        }
+        let result = exec();
+        bump(&mut keys, written);
+        result
+    }
+}
+
+fn bump(keys: &mut HashMap<Vec<u8>, Watched>, written: &[&[u8]]) {
+    for key in written {
+        if let Some(watched) = keys.get_mut(*key) {
+            watched.version += 1;
+        }
+    }
+}
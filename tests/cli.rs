@@ -71,6 +71,27 @@ fn client_cli_invalid_set() {
         .assert()
         .failure();
 
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key", "value", "--nx", "--xx"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key", "value", "--ttl", "30s", "--nx"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key", "value", "--ttl", "30y"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
     Command::cargo_bin("kvs-client")
         .unwrap()
         .args(&["set", "key", "value", "--addr", "invalid-addr"])
@@ -210,6 +231,96 @@ fn cli_wrong_engine() {
             .assert()
             .failure();
     }
+
+    // lsm first, kvs second
+    {
+        let temp_dir = TempDir::new().unwrap();
+        let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+        let mut child = cmd
+            .args(&["--engine", "lsm", "--addr", "127.0.0.1:4002"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+        child.kill().expect("server exited before killed");
+
+        let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+        cmd.args(&["--engine", "kvs", "--addr", "127.0.0.1:4003"])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure();
+    }
+}
+
+// The memory engine should serve from a directory holding another engine without touching it,
+// and forget every key when the server stops.
+#[test]
+fn cli_memory_engine() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--engine", "sled", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+        let mut child = cmd
+            .args(&["--engine", "memory", "--addr", "127.0.0.1:4006"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(&["get", "key1", "--addr", "127.0.0.1:4006"])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+            .stdout("Key not found\n");
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(&["set", "key1", "value1", "--addr", "127.0.0.1:4006"])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+            .stdout(is_empty());
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(&["get", "key1", "--addr", "127.0.0.1:4006"])
+            .current_dir(&temp_dir)
+            .assert()
+            .success()
+            .stdout("value1\n");
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(&["rm", "key2", "--addr", "127.0.0.1:4006"])
+            .current_dir(&temp_dir)
+            .assert()
+            .failure();
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+
+    // The sled engine is still usable in the directory
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(&["--engine", "sled", "--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    assert!(
+        child.try_wait().unwrap().is_none(),
+        "sled engine refused the directory"
+    );
+    child.kill().expect("server exited before killed");
 }
 
 fn cli_access_server(engine: &str, addr: &str) {
@@ -291,6 +402,134 @@ fn cli_access_server(engine: &str, addr: &str) {
         .success()
         .stdout(is_empty());
 
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key 3", "multi word\nvalue", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key 3", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("multi word\nvalue\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key4", "value4", "--xx", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Key not set"));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key4", "value4", "--nx", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key4", "value5", "--nx", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Key not set"));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key4", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value4\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["incr", "counter", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["incr", "counter", "--by", "-5", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("-4\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["decr", "counter", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("-5\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["incr", "key4", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["set", "key5", "value5", "--ttl", "1h", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["ttl", "key5", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("3599"));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["persist", "key5", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["ttl", "key5", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("No expiry\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["expire", "key5", "100ms", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("1\n");
+    thread::sleep(Duration::from_millis(150));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(&["get", "key5", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("Key not found\n");
+
     sender.send(()).unwrap();
     handle.join().unwrap();
 
@@ -335,3 +574,8 @@ fn cli_access_server_kvs_engine() {
 fn cli_access_server_sled_engine() {
     cli_access_server("sled", "127.0.0.1:4005");
 }
+
+#[test]
+fn cli_access_server_lsm_engine() {
+    cli_access_server("lsm", "127.0.0.1:4007");
+}
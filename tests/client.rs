@@ -0,0 +1,383 @@
+use kvs::client::create_client;
+use kvs::command::{Command, ScanCursor};
+use kvs::engines::{KvStore, KvsEngine, SledStore, WriteBatch};
+use kvs::error::ErrorKind;
+use kvs::protocol::Value;
+use kvs::server::KvsServer;
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::Result;
+use slog::{o, Discard, Logger};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn serve<E: KvsEngine>(engine: E, addr: &'static str) -> Result<()> {
+    let pool = SharedQueueThreadPool::new(2)?;
+    let mut server = KvsServer::new(addr, engine, pool, Logger::root(Discard, o!()))?;
+    thread::spawn(move || server.listen_and_serve().unwrap());
+    thread::sleep(Duration::from_millis(100));
+    Ok(())
+}
+
+// Keys and values holding spaces, newlines or invalid utf8 should go through the protocol
+// unchanged.
+fn binary_keys_and_values(addr: &str) -> Result<()> {
+    let mut client = create_client(addr)?;
+    let key = b"key with spaces\r\n".to_vec();
+    let value = vec![0, 159, 146, 150, b'\r', b'\n', b' ', 255];
+
+    assert_eq!(client.get(key.clone())?, None);
+    client.set(key.clone(), value.clone())?;
+    client.set(Vec::new(), Vec::new())?;
+    assert_eq!(client.get(key.clone())?, Some(value));
+    assert_eq!(client.get(Vec::new())?, Some(Vec::new()));
+    client.remove(key.clone())?;
+    assert_eq!(client.get(key.clone())?, None);
+    let err = client.remove(key).expect_err("removed a missing key");
+    assert_eq!(err.kind(), ErrorKind::KeyNotFound);
+
+    Ok(())
+}
+
+// A scan should walk the keys of a prefix in pages, until the cursor goes back to the start.
+fn scan_pages(addr: &str) -> Result<()> {
+    let mut client = create_client(addr)?;
+    for key_id in 0..10 {
+        client.set(format!("user:1:{}", key_id).into_bytes(), b"1".to_vec())?;
+        client.set(format!("user:2:{}", key_id).into_bytes(), b"2".to_vec())?;
+    }
+
+    let mut cursor = ScanCursor::Start;
+    let mut pages = 0;
+    let mut keys = Vec::new();
+    loop {
+        let (next, pairs) = client.scan(cursor, b"user:2:".to_vec(), 3)?;
+        pages += 1;
+        for (key, value) in pairs {
+            assert_eq!(value, b"2");
+            keys.push(key);
+        }
+        // Keys written during the scan do not disturb it.
+        client.set(format!("user:2:0{}", pages).into_bytes(), b"2".to_vec())?;
+        if next == ScanCursor::Start {
+            break;
+        }
+        cursor = next;
+    }
+    assert!(pages >= 4);
+    let mut sorted = keys.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(keys, sorted);
+    for key_id in 0..10 {
+        assert!(keys.contains(&format!("user:2:{}", key_id).into_bytes()));
+    }
+
+    Ok(())
+}
+
+// A batch frame should apply all its writes, and only accept sets and removes.
+fn write_batch(addr: &str) -> Result<()> {
+    let mut client = create_client(addr)?;
+    client.set(b"key1".to_vec(), b"value1".to_vec())?;
+
+    let mut batch = WriteBatch::new();
+    batch
+        .set(b"key2".to_vec(), b"value 2\r\n".to_vec())
+        .set(b"key3".to_vec(), b"value3".to_vec())
+        .remove(b"key1".to_vec())
+        .remove(b"missing".to_vec());
+    client.write_batch(batch)?;
+    assert_eq!(client.get(b"key1".to_vec())?, None);
+    assert_eq!(client.get(b"key2".to_vec())?, Some(b"value 2\r\n".to_vec()));
+    assert_eq!(client.get(b"key3".to_vec())?, Some(b"value3".to_vec()));
+
+    let batch = Value::Batch(vec![
+        Command::Set(b"key4".to_vec(), b"value4".to_vec()),
+        Command::Get(b"key2".to_vec()),
+    ]);
+    client.send(&batch.encode())?;
+    match client.read()? {
+        Value::Error(_) => {}
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+    assert_eq!(client.get(b"key4".to_vec())?, None);
+
+    Ok(())
+}
+
+// EXEC should run the queued commands, unless a watched key was modified by another client.
+fn multi_exec(addr: &str) -> Result<()> {
+    let mut client = create_client(addr)?;
+    let mut other = create_client(addr)?;
+    client.set(b"key1".to_vec(), b"value1".to_vec())?;
+
+    client.watch(b"key1".to_vec())?;
+    client.multi()?;
+    client.set(b"key2".to_vec(), b"value2".to_vec())?;
+    assert_eq!(client.get(b"key1".to_vec())?, None);
+    client.remove(b"key1".to_vec())?;
+    client.remove(b"missing".to_vec())?;
+    let err = client
+        .scan(ScanCursor::Start, Vec::new(), 1)
+        .expect_err("scan was queued");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    let replies = client.exec()?.expect("transaction aborted");
+    assert_eq!(replies.len(), 4);
+    match &replies[1] {
+        Value::Bytes(value) => assert_eq!(value, b"value1"),
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+    match &replies[3] {
+        Value::Error(_) => {}
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+    assert_eq!(other.get(b"key1".to_vec())?, None);
+    assert_eq!(other.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+
+    client.watch(b"key2".to_vec())?;
+    other.set(b"key2".to_vec(), b"other".to_vec())?;
+    client.multi()?;
+    client.set(b"key3".to_vec(), b"value3".to_vec())?;
+    assert!(client.exec()?.is_none());
+    assert_eq!(client.get(b"key3".to_vec())?, None);
+
+    // Writing a watched key back to its watched value still aborts the transaction.
+    client.watch(b"key2".to_vec())?;
+    other.set(b"key2".to_vec(), b"value2".to_vec())?;
+    other.set(b"key2".to_vec(), b"other".to_vec())?;
+    client.multi()?;
+    client.set(b"key3".to_vec(), b"value3".to_vec())?;
+    assert!(client.exec()?.is_none());
+    assert_eq!(client.get(b"key3".to_vec())?, None);
+
+    // A batch is refused while a transaction is open.
+    client.multi()?;
+    let mut batch = WriteBatch::new();
+    batch.set(b"key4".to_vec(), b"value4".to_vec());
+    client
+        .write_batch(batch)
+        .expect_err("batch ran within a transaction");
+    assert_eq!(other.get(b"key4".to_vec())?, None);
+    client.set(b"key3".to_vec(), b"value3".to_vec())?;
+    assert_eq!(client.exec()?.map(|replies| replies.len()), Some(1));
+    assert_eq!(client.get(b"key3".to_vec())?, Some(b"value3".to_vec()));
+    client.remove(b"key3".to_vec())?;
+
+    client.multi()?;
+    client.set(b"key3".to_vec(), b"value3".to_vec())?;
+    client.discard()?;
+    assert_eq!(client.get(b"key3".to_vec())?, None);
+    let err = client.exec().expect_err("exec outside of a transaction");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    Ok(())
+}
+
+// Conditional sets should go through the protocol, telling an empty value from a missing key.
+fn conditional_set(addr: &str) -> Result<()> {
+    let mut client = create_client(addr)?;
+
+    assert!(client.set_if_absent(b"key1".to_vec(), Vec::new())?);
+    assert!(!client.set_if_absent(b"key1".to_vec(), b"value1".to_vec())?);
+    assert!(!client.set_if_present(b"key2".to_vec(), b"value2".to_vec())?);
+    assert!(!client.compare_and_swap(b"key1".to_vec(), None, Some(b"value1".to_vec()))?);
+    assert!(client.compare_and_swap(
+        b"key1".to_vec(),
+        Some(Vec::new()),
+        Some(b"value1".to_vec())
+    )?);
+    assert!(client.set_if_present(b"key1".to_vec(), b"value2".to_vec())?);
+    assert_eq!(client.get(b"key1".to_vec())?, Some(b"value2".to_vec()));
+    assert!(client.compare_and_swap(b"key1".to_vec(), Some(b"value2".to_vec()), None)?);
+    assert_eq!(client.get(b"key1".to_vec())?, None);
+
+    Ok(())
+}
+
+// Expiries should go through the protocol in milliseconds.
+fn expiry(addr: &str) -> Result<()> {
+    let mut client = create_client(addr)?;
+
+    client.set_with_ttl(
+        b"key1".to_vec(),
+        b"value1".to_vec(),
+        Duration::from_millis(100),
+    )?;
+    client.set(b"key2".to_vec(), b"value2".to_vec())?;
+    assert!(client.ttl(b"key1".to_vec())?.unwrap() <= Duration::from_millis(100));
+    assert_eq!(client.ttl(b"key2".to_vec())?, None);
+    assert_eq!(
+        client.ttl(b"key3".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    assert!(client.expire(b"key2".to_vec(), Duration::from_secs(60))?);
+    assert!(!client.expire(b"key3".to_vec(), Duration::from_secs(60))?);
+    assert!(client.persist(b"key2".to_vec())?);
+    assert!(!client.persist(b"key2".to_vec())?);
+
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(client.get(b"key1".to_vec())?, None);
+    assert_eq!(client.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn expiry_kvs_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(KvStore::open(temp_dir.path())?, "127.0.0.1:4020")?;
+    expiry("127.0.0.1:4020")
+}
+
+#[test]
+fn expiry_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(SledStore::open(temp_dir.path())?, "127.0.0.1:4021")?;
+    expiry("127.0.0.1:4021")
+}
+
+// Counters should go through the protocol, with their errors.
+fn counters(addr: &str) -> Result<()> {
+    let mut client = create_client(addr)?;
+
+    assert_eq!(client.increment(b"counter".to_vec(), 1)?, 1);
+    assert_eq!(client.increment(b"counter".to_vec(), -1)?, 0);
+    assert_eq!(client.increment(b"counter".to_vec(), -10)?, -10);
+    client.set(b"name".to_vec(), b"alice".to_vec())?;
+    assert_eq!(
+        client.increment(b"name".to_vec(), 5).unwrap_err().kind(),
+        ErrorKind::NotAnInteger
+    );
+    assert_eq!(client.increment(b"counter".to_vec(), 15)?, 5);
+
+    Ok(())
+}
+
+#[test]
+fn counters_kvs_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(KvStore::open(temp_dir.path())?, "127.0.0.1:4022")?;
+    counters("127.0.0.1:4022")
+}
+
+#[test]
+fn counters_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(SledStore::open(temp_dir.path())?, "127.0.0.1:4023")?;
+    counters("127.0.0.1:4023")
+}
+
+#[test]
+fn conditional_set_kvs_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(KvStore::open(temp_dir.path())?, "127.0.0.1:4018")?;
+    conditional_set("127.0.0.1:4018")
+}
+
+#[test]
+fn conditional_set_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(SledStore::open(temp_dir.path())?, "127.0.0.1:4019")?;
+    conditional_set("127.0.0.1:4019")
+}
+
+#[test]
+fn multi_exec_kvs_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(KvStore::open(temp_dir.path())?, "127.0.0.1:4016")?;
+    multi_exec("127.0.0.1:4016")
+}
+
+#[test]
+fn multi_exec_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(SledStore::open(temp_dir.path())?, "127.0.0.1:4017")?;
+    multi_exec("127.0.0.1:4017")
+}
+
+#[test]
+fn write_batch_kvs_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(KvStore::open(temp_dir.path())?, "127.0.0.1:4014")?;
+    write_batch("127.0.0.1:4014")
+}
+
+#[test]
+fn write_batch_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(SledStore::open(temp_dir.path())?, "127.0.0.1:4015")?;
+    write_batch("127.0.0.1:4015")
+}
+
+#[test]
+fn scan_pages_kvs_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(KvStore::open(temp_dir.path())?, "127.0.0.1:4012")?;
+    scan_pages("127.0.0.1:4012")
+}
+
+#[test]
+fn scan_pages_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(SledStore::open(temp_dir.path())?, "127.0.0.1:4013")?;
+    scan_pages("127.0.0.1:4013")
+}
+
+#[test]
+fn binary_keys_and_values_kvs_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(KvStore::open(temp_dir.path())?, "127.0.0.1:4010")?;
+    binary_keys_and_values("127.0.0.1:4010")
+}
+
+#[test]
+fn binary_keys_and_values_sled_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(SledStore::open(temp_dir.path())?, "127.0.0.1:4011")?;
+    binary_keys_and_values("127.0.0.1:4011")
+}
+
+// A message nesting arrays too deeply should only close its connection, without bringing the
+// server down.
+#[test]
+fn deeply_nested_message() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    serve(KvStore::open(temp_dir.path())?, "127.0.0.1:4024")?;
+
+    let mut stream = TcpStream::connect("127.0.0.1:4024").expect("unable to connect");
+    let mut message = b"*1\r\n".repeat(100_000);
+    message.extend_from_slice(b";\r\n");
+    let _ = stream.write_all(&message);
+    let mut reply = Vec::new();
+    let _ = stream.read_to_end(&mut reply);
+    assert!(reply.is_empty());
+
+    let mut client = create_client("127.0.0.1:4024")?;
+    client.set(b"key".to_vec(), b"value".to_vec())?;
+    assert_eq!(client.get(b"key".to_vec())?, Some(b"value".to_vec()));
+    Ok(())
+}
+
+// A bulk string announcing a length that overflows with its CRLF should be rejected as invalid
+// data rather than panic the reader.
+#[test]
+fn overflowing_length() -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:4025").expect("unable to bind");
+    let server = thread::spawn(move || {
+        let (mut stream, _addr) = listener.accept().expect("unable to accept");
+        let mut request = [0; 64];
+        let _ = stream.read(&mut request);
+        let _ = stream.write_all(format!("${}\r\n", usize::MAX).as_bytes());
+    });
+
+    let mut client = create_client("127.0.0.1:4025")?;
+    let err = client
+        .get(b"key".to_vec())
+        .expect_err("overflowing length was accepted");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    server.join().unwrap();
+    Ok(())
+}
@@ -1,6 +1,15 @@
+use kvs::engines::{
+    CacheStats, CompactionTrigger, CompressionPolicy, EncryptionKey, KvsSnapshot, RecoveryMode,
+    SyncPolicy, WriteBatch,
+};
+use kvs::error::{Error, ErrorKind};
 use kvs::{KvStore, KvsEngine, Result};
+use slog::{o, Discard, Logger};
+use std::fs;
+use std::io;
 use std::sync::{Arc, Barrier};
 use std::thread;
+use std::time::Duration;
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -10,17 +19,17 @@ fn get_stored_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let store = KvStore::open(temp_dir.path())?;
 
-    store.set("key1".to_owned(), "value1".to_owned())?;
-    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    store.set(b"key2".to_vec(), b"value2".to_vec())?;
 
-    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
-    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
 
     // Open from disk again and check persistent data
     drop(store);
     let store = KvStore::open(temp_dir.path())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
-    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
 
     Ok(())
 }
@@ -31,17 +40,17 @@ fn overwrite_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let store = KvStore::open(temp_dir.path())?;
 
-    store.set("key1".to_owned(), "value1".to_owned())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
-    store.set("key1".to_owned(), "value2".to_owned())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    store.set(b"key1".to_vec(), b"value2".to_vec())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value2".to_vec()));
 
     // Open from disk again and check persistent data
     drop(store);
     let store = KvStore::open(temp_dir.path())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
-    store.set("key1".to_owned(), "value3".to_owned())?;
-    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value2".to_vec()));
+    store.set(b"key1".to_vec(), b"value3".to_vec())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value3".to_vec()));
 
     Ok(())
 }
@@ -52,13 +61,13 @@ fn get_non_existent_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let store = KvStore::open(temp_dir.path())?;
 
-    store.set("key1".to_owned(), "value1".to_owned())?;
-    assert_eq!(store.get("key2".to_owned())?, None);
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    assert_eq!(store.get(b"key2".to_vec())?, None);
 
     // Open from disk again and check persistent data
     drop(store);
     let store = KvStore::open(temp_dir.path())?;
-    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get(b"key2".to_vec())?, None);
 
     Ok(())
 }
@@ -67,7 +76,7 @@ fn get_non_existent_value() -> Result<()> {
 fn remove_non_existent_key() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let store = KvStore::open(temp_dir.path())?;
-    assert!(store.remove("key1".to_owned()).is_err());
+    assert!(store.remove(b"key1".to_vec()).is_err());
     Ok(())
 }
 
@@ -75,9 +84,190 @@ fn remove_non_existent_key() -> Result<()> {
 fn remove_key() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let store = KvStore::open(temp_dir.path())?;
-    store.set("key1".to_owned(), "value1".to_owned())?;
-    assert!(store.remove("key1".to_owned()).is_ok());
-    assert_eq!(store.get("key1".to_owned())?, None);
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    assert!(store.remove(b"key1".to_vec()).is_ok());
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+    Ok(())
+}
+
+// Should store keys and values holding any byte.
+#[test]
+fn binary_keys_and_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let key = vec![0, 159, 146, 150, b' ', b'\n'];
+    let value = vec![b'\r', b'\n', 0, 255, b' ', 254];
+    store.set(key.clone(), value.clone())?;
+    store.set(Vec::new(), Vec::new())?;
+    assert_eq!(store.get(key.clone())?, Some(value.clone()));
+    assert_eq!(store.get(Vec::new())?, Some(Vec::new()));
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(key.clone())?, Some(value));
+    assert_eq!(store.get(Vec::new())?, Some(Vec::new()));
+    store.remove(key.clone())?;
+    assert_eq!(store.get(key)?, None);
+
+    Ok(())
+}
+
+// Should refuse the keys and values whose length does not fit in a record, writing nothing.
+// The zeroed buffers are never touched, so they take no memory.
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn oversized_keys_and_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let too_large = u32::MAX as usize + 1;
+
+    let err = store
+        .set(vec![0; too_large], b"value".to_vec())
+        .expect_err("oversized key was written");
+    assert_eq!(err.kind(), ErrorKind::DataTooLarge);
+    let err = store
+        .set(b"key".to_vec(), vec![0; too_large])
+        .expect_err("oversized value was written");
+    assert_eq!(err.kind(), ErrorKind::DataTooLarge);
+    assert_eq!(store.get(b"key".to_vec())?, None);
+
+    store.set(b"key".to_vec(), b"value".to_vec())?;
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key".to_vec())?, Some(b"value".to_vec()));
+
+    Ok(())
+}
+
+// Should return the keys of a range in lexicographic order, across reopens and compactions.
+#[test]
+fn scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    for key_id in (0..100).rev() {
+        store.set(
+            format!("key{:03}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    store.remove(b"key011".to_vec())?;
+    store.set(b"key012".to_vec(), b"new".to_vec())?;
+
+    let expected = vec![
+        (b"key010".to_vec(), b"value10".to_vec()),
+        (b"key012".to_vec(), b"new".to_vec()),
+        (b"key013".to_vec(), b"value13".to_vec()),
+    ];
+    assert_eq!(
+        store.scan(b"key010".to_vec(), b"key014".to_vec(), 10)?,
+        expected
+    );
+    assert_eq!(
+        store.scan(b"key010".to_vec(), b"key099".to_vec(), 3)?,
+        expected
+    );
+    assert_eq!(
+        store.scan(b"key".to_vec(), b"kez".to_vec(), 1000)?.len(),
+        99
+    );
+    assert!(store
+        .scan(b"key014".to_vec(), b"key010".to_vec(), 10)?
+        .is_empty());
+    assert!(store
+        .scan(b"key010".to_vec(), b"key014".to_vec(), 0)?
+        .is_empty());
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(
+        store.scan(b"key010".to_vec(), b"key014".to_vec(), 10)?,
+        expected
+    );
+
+    Ok(())
+}
+
+// Should iterate over the keys of a prefix in order, across several pages.
+#[test]
+fn scan_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    for user_id in 1..4 {
+        for key_id in 0..300 {
+            let key = format!("user:{}:{:03}", user_id, key_id).into_bytes();
+            store.set(key, format!("{}", key_id).into_bytes())?;
+        }
+    }
+    store.set(vec![255, 255], b"max".to_vec())?;
+    store.set(vec![255, 255, 0], b"after max".to_vec())?;
+
+    let pairs = store
+        .scan_prefix(b"user:2:".to_vec())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(pairs.len(), 300);
+    for (key_id, (key, value)) in pairs.into_iter().enumerate() {
+        assert_eq!(key, format!("user:2:{:03}", key_id).into_bytes());
+        assert_eq!(value, format!("{}", key_id).into_bytes());
+    }
+
+    assert_eq!(store.scan_prefix(b"user:".to_vec()).count(), 900);
+    assert_eq!(store.scan_prefix(Vec::new()).count(), 902);
+    assert_eq!(store.scan_prefix(b"user:4:".to_vec()).count(), 0);
+    let pairs = store.scan_prefix(vec![255]).collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        pairs,
+        vec![
+            (vec![255, 255], b"max".to_vec()),
+            (vec![255, 255, 0], b"after max".to_vec())
+        ]
+    );
+
+    let page = store.scan_prefix_after(b"user:1:".to_vec(), Some(b"user:1:297".to_vec()), 10)?;
+    assert_eq!(page.len(), 2);
+    let page = store.scan_prefix_after(b"user:1:".to_vec(), Some(b"user:2:".to_vec()), 10)?;
+    assert!(page.is_empty());
+
+    Ok(())
+}
+
+// Should return every key present during the whole iteration exactly once, in order, while
+// other keys are written and removed.
+#[test]
+fn scan_prefix_during_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..1000 {
+        store.set(
+            format!("key{:04}a", key_id).into_bytes(),
+            b"stable".to_vec(),
+        )?;
+    }
+
+    let writer = {
+        let store = store.clone();
+        thread::spawn(move || {
+            for key_id in 0..1000 {
+                let key = format!("key{:04}b", key_id).into_bytes();
+                store.set(key.clone(), b"new".to_vec()).unwrap();
+                store.remove(key).unwrap();
+            }
+        })
+    };
+    let keys: Vec<Vec<u8>> = store
+        .scan_prefix(b"key".to_vec())
+        .map(|pair| pair.map(|(key, _value)| key))
+        .collect::<Result<_>>()?;
+    writer.join().unwrap();
+
+    let stable: Vec<Vec<u8>> = keys.into_iter().filter(|key| key.ends_with(b"a")).collect();
+    let expected: Vec<Vec<u8>> = (0..1000)
+        .map(|key_id| format!("key{:04}a", key_id).into_bytes())
+        .collect();
+    assert_eq!(stable, expected);
+
     Ok(())
 }
 
@@ -88,12 +278,18 @@ fn compaction() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     let store = KvStore::open(temp_dir.path())?;
 
+    // The compaction thread may delete a segment while the directory is walked.
     let dir_size = || {
         let entries = WalkDir::new(temp_dir.path()).into_iter();
         let len: walkdir::Result<u64> = entries
-            .map(|res| {
-                res.and_then(|entry| entry.metadata())
-                    .map(|metadata| metadata.len())
+            .map(|res| match res.and_then(|entry| entry.metadata()) {
+                Ok(metadata) => Ok(metadata.len()),
+                Err(ref err)
+                    if err.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound) =>
+                {
+                    Ok(0)
+                }
+                Err(err) => Err(err),
             })
             .sum();
         len.expect("fail to get directory size")
@@ -102,8 +298,8 @@ fn compaction() -> Result<()> {
     let mut current_size = dir_size();
     for iter in 0..1000 {
         for key_id in 0..1000 {
-            let key = format!("key{}", key_id);
-            let value = format!("{}", iter);
+            let key = format!("key{}", key_id).into_bytes();
+            let value = format!("{}", iter).into_bytes();
             store.set(key, value)?;
         }
 
@@ -114,15 +310,15 @@ fn compaction() -> Result<()> {
         }
         // Compaction triggered
         for key_id in 0..1000 {
-            let key = format!("key{}", key_id);
-            assert_eq!(store.get(key)?, Some(format!("{}", iter)));
+            let key = format!("key{}", key_id).into_bytes();
+            assert_eq!(store.get(key)?, Some(format!("{}", iter).into_bytes()));
         }
         drop(store);
         // reopen and check content
         let store = KvStore::open(temp_dir.path())?;
         for key_id in 0..1000 {
-            let key = format!("key{}", key_id);
-            assert_eq!(store.get(key)?, Some(format!("{}", iter)));
+            let key = format!("key{}", key_id).into_bytes();
+            assert_eq!(store.get(key)?, Some(format!("{}", iter).into_bytes()));
         }
         return Ok(());
     }
@@ -140,7 +336,10 @@ fn concurrent_set() -> Result<()> {
         let barrier = barrier.clone();
         thread::spawn(move || {
             store
-                .set(format!("key{}", i), format!("value{}", i))
+                .set(
+                    format!("key{}", i).into_bytes(),
+                    format!("value{}", i).into_bytes(),
+                )
                 .unwrap();
             barrier.wait();
         });
@@ -148,14 +347,20 @@ fn concurrent_set() -> Result<()> {
     barrier.wait();
 
     for i in 0..1000 {
-        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+        assert_eq!(
+            store.get(format!("key{}", i).into_bytes())?,
+            Some(format!("value{}", i).into_bytes())
+        );
     }
 
     // Open from disk again and check persistent data
     drop(store);
     let store = KvStore::open(temp_dir.path())?;
     for i in 0..1000 {
-        assert_eq!(store.get(format!("key{}", i))?, Some(format!("value{}", i)));
+        assert_eq!(
+            store.get(format!("key{}", i).into_bytes())?,
+            Some(format!("value{}", i).into_bytes())
+        );
     }
 
     Ok(())
@@ -167,7 +372,10 @@ fn concurrent_get() -> Result<()> {
     let store = KvStore::open(temp_dir.path())?;
     for i in 0..100 {
         store
-            .set(format!("key{}", i), format!("value{}", i))
+            .set(
+                format!("key{}", i).into_bytes(),
+                format!("value{}", i).into_bytes(),
+            )
             .unwrap();
     }
 
@@ -178,8 +386,8 @@ fn concurrent_get() -> Result<()> {
             for i in 0..100 {
                 let key_id = (i + thread_id) % 100;
                 assert_eq!(
-                    store.get(format!("key{}", key_id)).unwrap(),
-                    Some(format!("value{}", key_id))
+                    store.get(format!("key{}", key_id).into_bytes()).unwrap(),
+                    Some(format!("value{}", key_id).into_bytes())
                 );
             }
         });
@@ -199,8 +407,8 @@ fn concurrent_get() -> Result<()> {
             for i in 0..100 {
                 let key_id = (i + thread_id) % 100;
                 assert_eq!(
-                    store.get(format!("key{}", key_id)).unwrap(),
-                    Some(format!("value{}", key_id))
+                    store.get(format!("key{}", key_id).into_bytes()).unwrap(),
+                    Some(format!("value{}", key_id).into_bytes())
                 );
             }
         });
@@ -212,3 +420,1586 @@ fn concurrent_get() -> Result<()> {
 
     Ok(())
 }
+
+// Should split the log in several segments and read from all of them.
+#[test]
+fn multiple_segments() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let value = b"v".repeat(1024);
+    for key_id in 0..2000 {
+        store.set(format!("key{}", key_id).into_bytes(), value.clone())?;
+    }
+
+    let segments = WalkDir::new(temp_dir.path())
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.path().extension() == Some("log".as_ref()))
+        .count();
+    assert!(segments > 1);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..2000 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(value.clone())
+        );
+    }
+
+    Ok(())
+}
+
+// Should keep reading stores written as a single `kvs.log`.
+#[test]
+fn open_legacy_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    fs::write(
+        temp_dir.path().join("kvs.log"),
+        "{\"Set\":[\"key1\",\"value1\"]}\n{\"Set\":[\"key2\",\"value2\"]}\n{\"Rm\":\"key2\"}\n",
+    )
+    .expect("unable to write legacy log");
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, None);
+    assert!(!temp_dir.path().join("kvs.log").exists());
+
+    // The legacy segment is upgraded to the binary format
+    let segment = fs::read(temp_dir.path().join("1.log")).expect("unable to read segment");
+    assert_eq!(&segment[..3], b"KVS");
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+
+    Ok(())
+}
+
+// Should detect records modified on disk instead of returning garbage.
+#[test]
+fn detect_corrupted_record() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+
+    let path = temp_dir.path().join("1.log");
+    let mut segment = fs::read(&path).expect("unable to read segment");
+    let last = segment.len() - 1;
+    segment[last] ^= 0xff;
+    fs::write(&path, segment).expect("unable to write segment");
+
+    let err = store.get(b"key1".to_vec()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::CorruptedRecord);
+
+    Ok(())
+}
+
+// Should drop a record torn by a crash in the middle of a write and keep the previous ones.
+#[test]
+fn recover_torn_tail() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    store.set(b"key2".to_vec(), b"value2".to_vec())?;
+    drop(store);
+
+    let path = temp_dir.path().join("1.log");
+    let segment = fs::read(&path).expect("unable to read segment");
+    fs::write(&path, &segment[..segment.len() - 3]).expect("unable to write segment");
+
+    let err = KvStore::builder()
+        .recovery(RecoveryMode::Strict)
+        .open(temp_dir.path())
+        .err()
+        .expect("strict mode opened a torn log");
+    assert_eq!(err.kind(), ErrorKind::TornRecord);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, None);
+    store.set(b"key3".to_vec(), b"value3".to_vec())?;
+
+    drop(store);
+    let logger = Logger::root(Discard, o!());
+    let store = KvStore::builder()
+        .recovery(RecoveryMode::Strict)
+        .logger(logger)
+        .open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"key3".to_vec())?, Some(b"value3".to_vec()));
+
+    Ok(())
+}
+
+// Should start a new segment once the configured size is reached.
+#[test]
+fn configured_segment_size() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .segment_max_size(4 * 1024)
+        .sync_policy(SyncPolicy::Always)
+        .open(temp_dir.path())?;
+
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id).into_bytes(), b"v".repeat(100))?;
+    }
+
+    let segments = WalkDir::new(temp_dir.path())
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.path().extension() == Some("log".as_ref()))
+        .count();
+    assert!(segments >= 3);
+
+    drop(store);
+    let store = KvStore::builder()
+        .read_buffer_size(64)
+        .open(temp_dir.path())?;
+    for key_id in 0..100 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(b"v".repeat(100))
+        );
+    }
+
+    Ok(())
+}
+
+// Should hand every acknowledged write to the operating system, whatever the sync policy,
+// and keep it across a reopen.
+#[test]
+fn sync_policies() -> Result<()> {
+    let policies = [
+        SyncPolicy::Always,
+        SyncPolicy::EveryMillis(10),
+        SyncPolicy::Never,
+    ];
+    for &policy in &policies {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = KvStore::builder()
+            .sync_policy(policy)
+            .segment_max_size(1024)
+            .open(temp_dir.path())?;
+
+        let log_size = || {
+            WalkDir::new(temp_dir.path())
+                .into_iter()
+                .flatten()
+                .filter(|entry| entry.path().extension() == Some("log".as_ref()))
+                .map(|entry| entry.metadata().expect("fail to get segment size").len())
+                .sum::<u64>()
+        };
+        for key_id in 0..100 {
+            let size = log_size();
+            store.set(
+                format!("key{}", key_id).into_bytes(),
+                format!("value{}", key_id).into_bytes(),
+            )?;
+            assert!(log_size() > size, "{:?} kept a write buffered", policy);
+        }
+        thread::sleep(Duration::from_millis(20));
+
+        drop(store);
+        let store = KvStore::open(temp_dir.path())?;
+        for key_id in 0..100 {
+            assert_eq!(
+                store.get(format!("key{}", key_id).into_bytes())?,
+                Some(format!("value{}", key_id).into_bytes())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Should acknowledge every concurrent write batched by the group commit, across segments.
+#[test]
+fn concurrent_set_synced() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .sync_policy(SyncPolicy::Always)
+        .segment_max_size(4 * 1024)
+        .open(temp_dir.path())?;
+    let barrier = Arc::new(Barrier::new(101));
+    for i in 0..100 {
+        let store = store.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            for j in 0..10 {
+                store
+                    .set(
+                        format!("key{}", i).into_bytes(),
+                        format!("value{}-{}", i, j).into_bytes(),
+                    )
+                    .unwrap();
+            }
+            store
+                .set(format!("other{}", i).into_bytes(), b"value".to_vec())
+                .unwrap();
+            barrier.wait();
+        });
+    }
+    barrier.wait();
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for i in 0..100 {
+        assert_eq!(
+            store.get(format!("key{}", i).into_bytes())?,
+            Some(format!("value{}-9", i).into_bytes())
+        );
+        assert_eq!(
+            store.get(format!("other{}", i).into_bytes())?,
+            Some(b"value".to_vec())
+        );
+    }
+
+    Ok(())
+}
+
+// Should only create the store directory when asked to.
+#[test]
+fn create_dir() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let path = temp_dir.path().join("nested").join("store");
+
+    assert!(KvStore::open(&path).is_err());
+
+    let store = KvStore::builder().create_dir(true).open(&path)?;
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    drop(store);
+
+    let store = KvStore::open(&path)?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+
+    Ok(())
+}
+
+// Should compact the log once enough of it is taken by overwritten records.
+#[test]
+fn compaction_dead_bytes_ratio() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .compaction_trigger(CompactionTrigger::DeadBytesRatio(0.5))
+        .segment_max_size(16 * 1024)
+        .open(temp_dir.path())?;
+
+    // The compaction thread may delete a segment while the directory is walked.
+    let dir_size = || {
+        let entries = WalkDir::new(temp_dir.path()).into_iter();
+        let len: walkdir::Result<u64> = entries
+            .map(|res| match res.and_then(|entry| entry.metadata()) {
+                Ok(metadata) => Ok(metadata.len()),
+                Err(ref err)
+                    if err.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound) =>
+                {
+                    Ok(0)
+                }
+                Err(err) => Err(err),
+            })
+            .sum();
+        len.expect("fail to get directory size")
+    };
+
+    let mut current_size = dir_size();
+    for iter in 0..1000 {
+        for key_id in 0..100 {
+            store.set(
+                format!("key{}", key_id).into_bytes(),
+                format!("{}", iter).into_bytes(),
+            )?;
+        }
+
+        let new_size = dir_size();
+        if new_size > current_size {
+            current_size = new_size;
+            continue;
+        }
+        drop(store);
+        let store = KvStore::open(temp_dir.path())?;
+        for key_id in 0..100 {
+            assert_eq!(
+                store.get(format!("key{}", key_id).into_bytes())?,
+                Some(format!("{}", iter).into_bytes())
+            );
+        }
+        return Ok(());
+    }
+
+    panic!("No compaction detected");
+}
+
+// Should write hint files for merged segments and rebuild the same index from them.
+#[test]
+fn load_index_from_hints() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for iter in 0..5 {
+        for key_id in 0..1000 {
+            store.set(
+                format!("key{}", key_id).into_bytes(),
+                format!("{}", iter).into_bytes(),
+            )?;
+        }
+    }
+    store.set(b"key0".to_vec(), b"last".to_vec())?;
+    drop(store);
+
+    let hints: Vec<_> = WalkDir::new(temp_dir.path())
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.path().extension() == Some("hint".as_ref()))
+        .map(|entry| entry.into_path())
+        .collect();
+    assert!(!hints.is_empty());
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key0".to_vec())?, Some(b"last".to_vec()));
+    for key_id in 1..1000 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(b"4".to_vec())
+        );
+    }
+    drop(store);
+
+    // A damaged hint file falls back to replaying its segment
+    for hint in hints {
+        let bytes = fs::read(&hint).expect("unable to read hint");
+        fs::write(&hint, &bytes[..bytes.len() / 2]).expect("unable to write hint");
+    }
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key0".to_vec())?, Some(b"last".to_vec()));
+    for key_id in 1..1000 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(b"4".to_vec())
+        );
+    }
+
+    Ok(())
+}
+
+// Should keep every write done while the log is compacted in the background.
+#[test]
+fn concurrent_set_during_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let mut handles = Vec::new();
+    for thread_id in 0..4 {
+        let store = store.clone();
+        let handle = thread::spawn(move || {
+            for iter in 0..5 {
+                for key_id in 0..1000 {
+                    store
+                        .set(
+                            format!("key{}_{}", thread_id, key_id).into_bytes(),
+                            format!("value{}", iter).into_bytes(),
+                        )
+                        .unwrap();
+                }
+            }
+            store
+                .remove(format!("key{}_0", thread_id).into_bytes())
+                .unwrap();
+        });
+        handles.push(handle);
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let check = |store: &KvStore| -> Result<()> {
+        for thread_id in 0..4 {
+            assert_eq!(store.get(format!("key{}_0", thread_id).into_bytes())?, None);
+            for key_id in 1..1000 {
+                assert_eq!(
+                    store.get(format!("key{}_{}", thread_id, key_id).into_bytes())?,
+                    Some(b"value4".to_vec())
+                );
+            }
+        }
+        Ok(())
+    };
+    check(&store)?;
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    check(&store)
+}
+
+// Should keep answering reads while the segments they point to are compacted away.
+#[test]
+fn concurrent_get_during_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..100 {
+        store.set(
+            format!("key{}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+
+    let writer = {
+        let store = store.clone();
+        thread::spawn(move || {
+            for iter in 0..10_000 {
+                store
+                    .set(
+                        format!("other{}", iter % 500).into_bytes(),
+                        format!("{}", iter).into_bytes(),
+                    )
+                    .unwrap();
+            }
+        })
+    };
+
+    let mut handles = Vec::new();
+    for thread_id in 0..8 {
+        let store = store.clone();
+        let handle = thread::spawn(move || {
+            for i in 0..2000 {
+                let key_id = (i + thread_id) % 100;
+                assert_eq!(
+                    store.get(format!("key{}", key_id).into_bytes()).unwrap(),
+                    Some(format!("value{}", key_id).into_bytes())
+                );
+            }
+        });
+        handles.push(handle);
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    writer.join().unwrap();
+
+    Ok(())
+}
+
+// Should keep reading the values of a snapshot after they are overwritten and compacted away.
+#[test]
+fn snapshot() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .compaction_trigger(CompactionTrigger::Operations(1000))
+        .segment_max_size(16 * 1024)
+        .open(temp_dir.path())?;
+
+    for key_id in 0..100 {
+        store.set(
+            format!("key{:02}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    let snapshot = store.snapshot()?;
+
+    store.remove(b"key00".to_vec())?;
+    store.set(b"other".to_vec(), b"value".to_vec())?;
+    for iter in 0..100 {
+        for key_id in 1..100 {
+            store.set(
+                format!("key{:02}", key_id).into_bytes(),
+                format!("{}", iter).into_bytes(),
+            )?;
+        }
+    }
+    drop(store);
+
+    assert_eq!(snapshot.get(b"other".to_vec())?, None);
+    for key_id in 0..100 {
+        assert_eq!(
+            snapshot.get(format!("key{:02}", key_id).into_bytes())?,
+            Some(format!("value{}", key_id).into_bytes())
+        );
+    }
+    let pairs = snapshot.scan(b"key".to_vec(), b"kez".to_vec(), 1000)?;
+    assert_eq!(pairs.len(), 100);
+    assert_eq!(pairs[0], (b"key00".to_vec(), b"value0".to_vec()));
+    assert_eq!(
+        snapshot
+            .scan_prefix_after(b"key9".to_vec(), Some(b"key95".to_vec()), 1000)?
+            .len(),
+        4
+    );
+
+    Ok(())
+}
+
+// Should see either all or none of the keys written together, while writers continue.
+#[test]
+fn snapshot_during_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set(b"a".to_vec(), b"0".to_vec())?;
+    store.set(b"b".to_vec(), b"0".to_vec())?;
+
+    let writer = {
+        let store = store.clone();
+        thread::spawn(move || {
+            for iter in 1..2000 {
+                let value = format!("{}", iter).into_bytes();
+                store.set(b"a".to_vec(), value.clone()).unwrap();
+                store.set(b"b".to_vec(), value).unwrap();
+            }
+        })
+    };
+
+    for _ in 0..200 {
+        let snapshot = store.snapshot()?;
+        let a = snapshot.get(b"a".to_vec())?.expect("key a not found");
+        let b = snapshot.get(b"b".to_vec())?.expect("key b not found");
+        let a: u64 = String::from_utf8(a).unwrap().parse().unwrap();
+        let b: u64 = String::from_utf8(b).unwrap().parse().unwrap();
+        assert!(a == b || a == b + 1);
+    }
+    writer.join().unwrap();
+
+    Ok(())
+}
+
+// Should apply the writes of a batch together, and keep them across a reopen.
+#[test]
+fn write_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+
+    let mut batch = WriteBatch::new();
+    batch
+        .set(b"key2".to_vec(), b"value2".to_vec())
+        .set(b"key3".to_vec(), b"value3".to_vec())
+        .set(b"key2".to_vec(), b"value4".to_vec())
+        .remove(b"key1".to_vec())
+        .remove(b"missing".to_vec());
+    store.write_batch(batch)?;
+    store.write_batch(WriteBatch::new())?;
+
+    let check = |store: &KvStore| -> Result<()> {
+        assert_eq!(store.get(b"key1".to_vec())?, None);
+        assert_eq!(store.get(b"key2".to_vec())?, Some(b"value4".to_vec()));
+        assert_eq!(store.get(b"key3".to_vec())?, Some(b"value3".to_vec()));
+        Ok(())
+    };
+    check(&store)?;
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    check(&store)
+}
+
+// Should drop a batch torn by a crash as a whole, and keep the writes before it.
+#[test]
+fn recover_torn_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    let mut batch = WriteBatch::new();
+    batch
+        .set(b"key2".to_vec(), b"value2".to_vec())
+        .set(b"key3".to_vec(), b"value3".to_vec())
+        .remove(b"key1".to_vec());
+    store.write_batch(batch)?;
+    drop(store);
+
+    // Cut the batch right after the record of key2.
+    let path = temp_dir.path().join("1.log");
+    let segment = fs::read(&path).expect("unable to read segment");
+    let key3 = segment
+        .windows(4)
+        .position(|window| window == b"key3")
+        .expect("key3 not found in the segment");
+    fs::write(&path, &segment[..key3 - 21]).expect("unable to write segment");
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, None);
+    assert_eq!(store.get(b"key3".to_vec())?, None);
+
+    Ok(())
+}
+
+// Should apply the batches of concurrent writers, when they are synced together.
+#[test]
+fn concurrent_write_batch_synced() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .sync_policy(SyncPolicy::Always)
+        .open(temp_dir.path())?;
+
+    let mut handles = Vec::new();
+    for thread_id in 0..4 {
+        let store = store.clone();
+        handles.push(thread::spawn(move || {
+            for iter in 0..50 {
+                let mut batch = WriteBatch::new();
+                batch
+                    .set(format!("a{}", thread_id).into_bytes(), vec![iter])
+                    .set(format!("b{}", thread_id).into_bytes(), vec![iter]);
+                store.write_batch(batch).unwrap();
+                store
+                    .set(format!("c{}", thread_id).into_bytes(), vec![iter])
+                    .unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for thread_id in 0..4 {
+        for prefix in &["a", "b", "c"] {
+            let key = format!("{}{}", prefix, thread_id).into_bytes();
+            assert_eq!(store.get(key)?, Some(vec![49]));
+        }
+    }
+
+    Ok(())
+}
+
+// Should apply the writes of a transaction together with the values it read.
+#[test]
+fn transaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set(b"from".to_vec(), b"value1".to_vec())?;
+
+    let moved = store.transaction(|tx| {
+        let value = tx.get(b"from".to_vec())?.expect("key from not found");
+        tx.set(b"to".to_vec(), value.clone())?;
+        tx.remove(b"from".to_vec())?;
+        assert_eq!(tx.get(b"from".to_vec())?, None);
+        assert_eq!(tx.get(b"to".to_vec())?, Some(value.clone()));
+        Ok(value)
+    })?;
+    assert_eq!(moved, b"value1");
+
+    let err = store
+        .transaction(|tx| -> Result<()> {
+            tx.set(b"aborted".to_vec(), b"value".to_vec())?;
+            Err(Error::from(ErrorKind::InvalidData))
+        })
+        .expect_err("aborted transaction succeeded");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let check = |store: &KvStore| -> Result<()> {
+        assert_eq!(store.get(b"from".to_vec())?, None);
+        assert_eq!(store.get(b"to".to_vec())?, Some(b"value1".to_vec()));
+        assert_eq!(store.get(b"aborted".to_vec())?, None);
+        Ok(())
+    };
+    check(&store)?;
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    check(&store)
+}
+
+// Should not lose any increment when concurrent transactions read and write the same key.
+#[test]
+fn concurrent_transactions() -> Result<()> {
+    for &policy in &[SyncPolicy::Always, SyncPolicy::Never] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = KvStore::builder()
+            .sync_policy(policy)
+            .open(temp_dir.path())?;
+
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let store = store.clone();
+            handles.push(thread::spawn(move || {
+                for _ in 0..50 {
+                    store
+                        .transaction(|tx| {
+                            let counter = tx.get(b"counter".to_vec())?.unwrap_or_default();
+                            tx.set(b"counter".to_vec(), [&counter[..], b"+"].concat())
+                        })
+                        .unwrap();
+                }
+            }));
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(store.get(b"counter".to_vec())?, Some(b"+".repeat(200)));
+    }
+
+    Ok(())
+}
+
+// Should only swap or set a key when its current value is the expected one.
+#[test]
+fn compare_and_swap() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    assert!(store.set_if_absent(b"key1".to_vec(), b"value1".to_vec())?);
+    assert!(!store.set_if_absent(b"key1".to_vec(), b"value2".to_vec())?);
+    assert!(!store.set_if_present(b"key2".to_vec(), b"value2".to_vec())?);
+    assert!(store.set_if_present(b"key1".to_vec(), b"value3".to_vec())?);
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value3".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, None);
+
+    let swap = |expected: Option<&[u8]>, new: Option<&[u8]>| {
+        store.compare_and_swap(
+            b"key1".to_vec(),
+            expected.map(<[u8]>::to_vec),
+            new.map(<[u8]>::to_vec),
+        )
+    };
+    assert!(!swap(Some(b"value1"), Some(b"value4"))?);
+    assert!(!swap(None, Some(b"value4"))?);
+    assert!(swap(Some(b"value3"), Some(b""))?);
+    assert!(!swap(None, None)?);
+    assert!(swap(Some(b""), None)?);
+    assert!(swap(None, None)?);
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+
+    Ok(())
+}
+
+// Should let only one of several concurrent writers take a lock with `set_if_absent`.
+#[test]
+fn concurrent_set_if_absent() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    for round in 0..20 {
+        let barrier = Arc::new(Barrier::new(4));
+        let mut handles = Vec::new();
+        for thread_id in 0..4 {
+            let store = store.clone();
+            let barrier = barrier.clone();
+            handles.push(thread::spawn(move || {
+                barrier.wait();
+                let lock = format!("lock{}", round).into_bytes();
+                store
+                    .set_if_absent(lock, format!("{}", thread_id).into_bytes())
+                    .unwrap()
+            }));
+        }
+        let taken = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|&taken| taken)
+            .count();
+        assert_eq!(taken, 1);
+    }
+
+    Ok(())
+}
+
+// Should hide expired keys from every read, and keep the expiry of a key until it is set again.
+#[test]
+fn expire_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set_with_ttl(
+        b"key1".to_vec(),
+        b"value1".to_vec(),
+        Duration::from_millis(100),
+    )?;
+    store.set(b"key2".to_vec(), b"value2".to_vec())?;
+    store.set(b"key3".to_vec(), b"value3".to_vec())?;
+    assert!(store.expire(b"key2".to_vec(), Duration::from_millis(100))?);
+    assert!(store.expire(b"key3".to_vec(), Duration::from_millis(100))?);
+    assert!(!store.expire(b"key4".to_vec(), Duration::from_millis(100))?);
+    assert!(store.persist(b"key3".to_vec())?);
+    assert!(!store.persist(b"key3".to_vec())?);
+    store.set_with_ttl(
+        b"key4".to_vec(),
+        b"value4".to_vec(),
+        Duration::from_millis(100),
+    )?;
+    store.set(b"key4".to_vec(), b"value4".to_vec())?;
+
+    let ttl = store.ttl(b"key1".to_vec())?.expect("key1 should expire");
+    assert!(ttl <= Duration::from_millis(100));
+    assert_eq!(store.ttl(b"key3".to_vec())?, None);
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    let snapshot = store.snapshot()?;
+
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+    assert_eq!(store.get(b"key2".to_vec())?, None);
+    assert_eq!(store.get(b"key3".to_vec())?, Some(b"value3".to_vec()));
+    assert_eq!(store.get(b"key4".to_vec())?, Some(b"value4".to_vec()));
+    assert_eq!(
+        store.scan(b"key".to_vec(), b"kez".to_vec(), 10)?,
+        vec![
+            (b"key3".to_vec(), b"value3".to_vec()),
+            (b"key4".to_vec(), b"value4".to_vec()),
+        ]
+    );
+    assert_eq!(
+        store.ttl(b"key1".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    assert_eq!(
+        store.remove(b"key2".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    assert!(store.set_if_absent(b"key2".to_vec(), b"value5".to_vec())?);
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value5".to_vec()));
+    // The snapshot was taken before the keys expired
+    assert_eq!(snapshot.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+
+    Ok(())
+}
+
+// Should read the expiry of the keys back from the log and from the hint files.
+#[test]
+fn expiry_survives_restart() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_with_ttl(
+        b"short".to_vec(),
+        b"value1".to_vec(),
+        Duration::from_millis(200),
+    )?;
+    store.set_with_ttl(
+        b"long".to_vec(),
+        b"value2".to_vec(),
+        Duration::from_secs(3600),
+    )?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"short".to_vec())?, Some(b"value1".to_vec()));
+    let ttl = store.ttl(b"long".to_vec())?.expect("long should expire");
+    assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(store.get(b"short".to_vec())?, None);
+
+    // Merge the log to load it from hint files
+    for iter in 0..5 {
+        for key_id in 0..1000 {
+            store.set(
+                format!("key{}", key_id).into_bytes(),
+                format!("{}", iter).into_bytes(),
+            )?;
+        }
+    }
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"short".to_vec())?, None);
+    let ttl = store.ttl(b"long".to_vec())?.expect("long should expire");
+    assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+
+    Ok(())
+}
+
+fn log_contains(dir: &TempDir, needle: &[u8]) -> bool {
+    WalkDir::new(dir.path())
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.file_type().is_file())
+        .any(|entry| {
+            let bytes = fs::read(entry.path()).unwrap_or_default();
+            bytes.windows(needle.len()).any(|window| window == needle)
+        })
+}
+
+// Should drop the records of expired keys when the log is compacted.
+#[test]
+fn compaction_drops_expired_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .compaction_trigger(CompactionTrigger::Operations(100))
+        .sweep_interval(Duration::from_secs(3600))
+        .open(temp_dir.path())?;
+
+    store.set_with_ttl(
+        b"temporary".to_vec(),
+        b"expiring".to_vec(),
+        Duration::from_millis(100),
+    )?;
+    thread::sleep(Duration::from_millis(150));
+    for key_id in 0..200 {
+        store.set(format!("key{}", key_id).into_bytes(), b"value".to_vec())?;
+    }
+    for _ in 0..100 {
+        if !log_contains(&temp_dir, b"expiring") {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    panic!("Expired record still in the log");
+}
+
+// Should count the keys dropped by the sweeper as dead, so they trigger the compaction.
+#[test]
+fn sweep_expired_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .compaction_trigger(CompactionTrigger::DeadBytesRatio(0.5))
+        .segment_max_size(16 * 1024)
+        .sweep_interval(Duration::from_millis(50))
+        .open(temp_dir.path())?;
+
+    for key_id in 0..1000 {
+        store.set_with_ttl(
+            format!("key{}", key_id).into_bytes(),
+            b"expiring".to_vec(),
+            Duration::from_millis(100),
+        )?;
+    }
+    thread::sleep(Duration::from_millis(300));
+    store.set(b"key".to_vec(), b"value".to_vec())?;
+    for _ in 0..100 {
+        if !log_contains(&temp_dir, b"expiring") {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    panic!("Expired records still in the log");
+}
+
+// Should add to the integers stored in keys, refusing other values.
+#[test]
+fn increment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    assert_eq!(store.increment(b"counter".to_vec(), 1)?, 1);
+    assert_eq!(store.increment(b"counter".to_vec(), 41)?, 42);
+    assert_eq!(store.increment(b"counter".to_vec(), -50)?, -8);
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"-8".to_vec()));
+
+    store.set(b"name".to_vec(), b"alice".to_vec())?;
+    assert_eq!(
+        store.increment(b"name".to_vec(), 1).unwrap_err().kind(),
+        ErrorKind::NotAnInteger
+    );
+    assert_eq!(store.get(b"name".to_vec())?, Some(b"alice".to_vec()));
+    store.set(b"max".to_vec(), i64::MAX.to_string().into_bytes())?;
+    assert_eq!(
+        store.increment(b"max".to_vec(), 1).unwrap_err().kind(),
+        ErrorKind::IntegerOverflow
+    );
+
+    // The expiry of the counter is kept
+    store.set_with_ttl(b"visits".to_vec(), b"1".to_vec(), Duration::from_secs(60))?;
+    assert_eq!(store.increment(b"visits".to_vec(), 1)?, 2);
+    assert!(store.ttl(b"visits".to_vec())?.is_some());
+
+    Ok(())
+}
+
+// Should not lose any increment done by concurrent threads.
+#[test]
+fn concurrent_increment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let barrier = Arc::new(Barrier::new(4));
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let store = store.clone();
+        let barrier = barrier.clone();
+        handles.push(thread::spawn(move || {
+            barrier.wait();
+            for _ in 0..100 {
+                store.increment(b"counter".to_vec(), 1).unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"400".to_vec()));
+
+    Ok(())
+}
+
+// Files with `extension` and their length. The background threads may delete a file while the
+// directory is walked, it is then left out.
+fn files_with_extension(dir: &TempDir, extension: &str) -> Vec<(String, u64)> {
+    let mut files: Vec<_> = WalkDir::new(dir.path())
+        .into_iter()
+        .flatten()
+        .filter(|entry| entry.path().extension() == Some(extension.as_ref()))
+        .filter_map(|entry| {
+            let len = entry.metadata().ok()?.len();
+            Some((entry.file_name().to_string_lossy().into_owned(), len))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// Should store the large values in blob files and read them back from there.
+#[test]
+fn large_values_in_blob_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .blob_threshold(1024)
+        .open(temp_dir.path())?;
+
+    let large = |key_id: u8| vec![key_id; 16 * 1024];
+    for key_id in 0..10 {
+        store.set(format!("large{}", key_id).into_bytes(), large(key_id))?;
+    }
+    store.set(b"small".to_vec(), b"value".to_vec())?;
+    store.set_with_ttl(b"expiring".to_vec(), large(42), Duration::from_secs(3600))?;
+
+    let log_size: u64 = files_with_extension(&temp_dir, "log")
+        .iter()
+        .map(|(_name, len)| len)
+        .sum();
+    assert!(log_size < 16 * 1024, "large values were written to the log");
+    assert!(!files_with_extension(&temp_dir, "blob").is_empty());
+
+    assert_eq!(store.get(b"large3".to_vec())?, Some(large(3)));
+    assert_eq!(store.get(b"small".to_vec())?, Some(b"value".to_vec()));
+    let snapshot = store.snapshot()?;
+    store.remove(b"large3".to_vec())?;
+    assert_eq!(snapshot.get(b"large3".to_vec())?, Some(large(3)));
+    assert_eq!(store.get(b"large3".to_vec())?, None);
+    assert_eq!(
+        store.scan(b"large2".to_vec(), b"largf".to_vec(), 2)?,
+        vec![
+            (b"large2".to_vec(), large(2)),
+            (b"large4".to_vec(), large(4))
+        ]
+    );
+
+    // A new expiry time keeps the value where it is
+    let blobs = files_with_extension(&temp_dir, "blob");
+    assert!(store.persist(b"expiring".to_vec())?);
+    assert!(store.expire(b"large0".to_vec(), Duration::from_secs(3600))?);
+    assert_eq!(files_with_extension(&temp_dir, "blob"), blobs);
+    assert_eq!(store.ttl(b"expiring".to_vec())?, None);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in (0..10).filter(|&key_id| key_id != 3) {
+        assert_eq!(
+            store.get(format!("large{}", key_id).into_bytes())?,
+            Some(large(key_id))
+        );
+    }
+    assert_eq!(store.get(b"large3".to_vec())?, None);
+    assert_eq!(store.get(b"expiring".to_vec())?, Some(large(42)));
+    assert!(store.ttl(b"large0".to_vec())?.is_some());
+
+    Ok(())
+}
+
+// Should merge the log without copying the values stored in blob files.
+#[test]
+fn compaction_keeps_blob_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .blob_threshold(1024)
+        .compaction_trigger(CompactionTrigger::Operations(100))
+        .open(temp_dir.path())?;
+
+    for key_id in 0..10 {
+        store.set(
+            format!("large{}", key_id).into_bytes(),
+            vec![key_id; 64 * 1024],
+        )?;
+    }
+    let blobs = files_with_extension(&temp_dir, "blob");
+    for iter in 0..1000 {
+        store.set(b"counter".to_vec(), format!("{}", iter).into_bytes())?;
+    }
+    drop(store);
+
+    assert_eq!(files_with_extension(&temp_dir, "blob"), blobs);
+    assert!(!files_with_extension(&temp_dir, "hint").is_empty());
+    let store = KvStore::open(temp_dir.path())?;
+    for key_id in 0..10 {
+        assert_eq!(
+            store.get(format!("large{}", key_id).into_bytes())?,
+            Some(vec![key_id; 64 * 1024])
+        );
+    }
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"999".to_vec()));
+
+    Ok(())
+}
+
+// Should move the live values out of the blob files taken by overwritten values and delete
+// these files, while keys keep being written.
+#[test]
+fn collect_blob_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = || {
+        KvStore::builder()
+            .blob_threshold(1024)
+            .segment_max_size(64 * 1024)
+    };
+    let store = options().open(temp_dir.path())?;
+
+    let value = |key_id: u32, iter: u32| format!("{}:{}", key_id, iter).repeat(1000).into_bytes();
+    for iter in 0..20 {
+        for key_id in 0..20 {
+            store.set(format!("key{}", key_id).into_bytes(), value(key_id, iter))?;
+        }
+        store.remove(b"key0".to_vec())?;
+    }
+
+    // 20 versions of every value were written, only the last one is live.
+    let live_size = 20 * value(10, 19).len() as u64;
+    let blob_size = || -> u64 {
+        files_with_extension(&temp_dir, "blob")
+            .iter()
+            .map(|(_name, len)| len)
+            .sum()
+    };
+    let mut collected = false;
+    for _ in 0..100 {
+        if blob_size() < 4 * live_size {
+            collected = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(collected, "blob files were not collected");
+
+    assert_eq!(store.get(b"key0".to_vec())?, None);
+    for key_id in 1..20 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(value(key_id, 19))
+        );
+    }
+    drop(store);
+    let store = options().open(temp_dir.path())?;
+    for key_id in 1..20 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(value(key_id, 19))
+        );
+    }
+
+    Ok(())
+}
+
+// Should compress the values according to the policy, and read back the values written with
+// any other policy.
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let document = |key_id: u32| {
+        format!(
+            "{{\"id\": {}, \"tags\": [{}]}}",
+            key_id,
+            vec!["\"compressible\""; 200].join(", ")
+        )
+        .into_bytes()
+    };
+
+    let store = KvStore::builder()
+        .compression(CompressionPolicy::Above(64))
+        .compaction_trigger(CompactionTrigger::Operations(100))
+        .open(temp_dir.path())?;
+    for key_id in 0..100 {
+        store.set(format!("doc{}", key_id).into_bytes(), document(key_id))?;
+    }
+    store.set(b"small".to_vec(), b"value".to_vec())?;
+    store.set_with_ttl(
+        b"expiring".to_vec(),
+        document(42),
+        Duration::from_secs(3600),
+    )?;
+
+    let log_size: u64 = files_with_extension(&temp_dir, "log")
+        .iter()
+        .map(|(_name, len)| len)
+        .sum();
+    assert!(
+        log_size < 100 * document(0).len() as u64 / 5,
+        "values were not compressed"
+    );
+    assert_eq!(store.get(b"doc7".to_vec())?, Some(document(7)));
+    assert_eq!(store.get(b"small".to_vec())?, Some(b"value".to_vec()));
+    assert!(store.ttl(b"expiring".to_vec())?.is_some());
+    assert_eq!(
+        store.scan(b"doc1".to_vec(), b"doc11".to_vec(), 3)?,
+        vec![
+            (b"doc1".to_vec(), document(1)),
+            (b"doc10".to_vec(), document(10)),
+        ]
+    );
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    store.set(b"raw".to_vec(), document(1000))?;
+    assert_eq!(store.get(b"doc7".to_vec())?, Some(document(7)));
+    assert_eq!(store.get(b"expiring".to_vec())?, Some(document(42)));
+    assert!(store.ttl(b"expiring".to_vec())?.is_some());
+
+    drop(store);
+    let store = KvStore::builder()
+        .compression(CompressionPolicy::Always)
+        .open(temp_dir.path())?;
+    for key_id in 0..100 {
+        assert_eq!(
+            store.get(format!("doc{}", key_id).into_bytes())?,
+            Some(document(key_id))
+        );
+    }
+    assert_eq!(store.get(b"raw".to_vec())?, Some(document(1000)));
+
+    Ok(())
+}
+
+// Should refuse to compress values when the crate is built without compression.
+#[cfg(not(feature = "compression"))]
+#[test]
+fn compression_needs_feature() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let result = KvStore::builder()
+        .compression(CompressionPolicy::Always)
+        .open(temp_dir.path());
+    match result {
+        Err(err) => assert_eq!(err.kind(), ErrorKind::UnsupportedCodec),
+        Ok(_store) => panic!("compression is not built in"),
+    }
+}
+
+fn encryption_key(id: u32) -> EncryptionKey {
+    EncryptionKey::new(id, [id as u8; 32])
+}
+
+// Should never write the keys and values of an encrypted store in plain text, and refuse to
+// open it without its key.
+#[test]
+fn encrypted_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = || {
+        KvStore::builder()
+            .encryption_key(encryption_key(1))
+            .blob_threshold(1024)
+            .compaction_trigger(CompactionTrigger::Operations(100))
+    };
+    let store = options().open(temp_dir.path())?;
+
+    let large = b"large-secret".repeat(1000);
+    store.set(b"secret-key".to_vec(), b"secret-value".to_vec())?;
+    store.set(b"large-key".to_vec(), large.clone())?;
+    store.set_with_ttl(
+        b"expiring-key".to_vec(),
+        b"expiring-value".to_vec(),
+        Duration::from_secs(3600),
+    )?;
+    let mut batch = WriteBatch::new();
+    batch
+        .set(b"batch-key".to_vec(), b"batch-value".to_vec())
+        .remove(b"secret-key".to_vec());
+    store.write_batch(batch)?;
+    store.transaction(|tx| tx.set(b"tx-key".to_vec(), b"tx-value".to_vec()))?;
+    for iter in 0..300 {
+        store.set(b"counter".to_vec(), format!("count-{}", iter).into_bytes())?;
+    }
+
+    let check = |store: &KvStore| -> Result<()> {
+        assert_eq!(store.get(b"secret-key".to_vec())?, None);
+        assert_eq!(store.get(b"large-key".to_vec())?, Some(large.clone()));
+        assert_eq!(
+            store.get(b"expiring-key".to_vec())?,
+            Some(b"expiring-value".to_vec())
+        );
+        assert!(store.ttl(b"expiring-key".to_vec())?.is_some());
+        assert_eq!(
+            store.get(b"batch-key".to_vec())?,
+            Some(b"batch-value".to_vec())
+        );
+        assert_eq!(store.get(b"tx-key".to_vec())?, Some(b"tx-value".to_vec()));
+        assert_eq!(store.get(b"counter".to_vec())?, Some(b"count-299".to_vec()));
+        Ok(())
+    };
+    check(&store)?;
+    drop(store);
+
+    assert!(!files_with_extension(&temp_dir, "hint").is_empty());
+    for needle in &[
+        &b"secret"[..],
+        b"large-key",
+        b"expiring",
+        b"batch-",
+        b"tx-",
+        b"counter",
+        b"count-",
+    ] {
+        assert!(!log_contains(&temp_dir, needle), "plain text in the files");
+    }
+
+    let store = options().open(temp_dir.path())?;
+    check(&store)?;
+    drop(store);
+
+    // A missing or wrong key fails before anything is read, and damages nothing.
+    for options in [
+        KvStore::builder(),
+        KvStore::builder().encryption_key(EncryptionKey::new(1, [42; 32])),
+    ] {
+        match options.open(temp_dir.path()) {
+            Err(err) => assert_eq!(err.kind(), ErrorKind::UnknownEncryptionKey(1)),
+            Ok(_store) => panic!("opened without the encryption key"),
+        }
+    }
+    let store = KvStore::open_encrypted(temp_dir.path(), encryption_key(1))?;
+    check(&store)
+}
+
+// Reads the id of the key of every segment and blob file, `None` for a plain text file.
+fn file_key_ids(dir: &TempDir) -> Vec<Option<u32>> {
+    let mut ids: Vec<_> = WalkDir::new(dir.path())
+        .into_iter()
+        .flatten()
+        .filter(|entry| {
+            let extension = entry.path().extension();
+            extension == Some("log".as_ref()) || extension == Some("blob".as_ref())
+        })
+        .map(|entry| {
+            let bytes = fs::read(entry.path()).expect("unable to read file");
+            match bytes[3] {
+                2 => Some(u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])),
+                _ => None,
+            }
+        })
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+// Should keep the writes made after the compaction of a plain text store that is encrypted
+// from then on, although the merged records grow with their tags.
+#[test]
+fn compact_newly_encrypted_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .segment_max_size(4096)
+        .open(temp_dir.path())?;
+    for key_id in 0..3000 {
+        store.set(format!("key{}", key_id).into_bytes(), b"value".to_vec())?;
+    }
+    drop(store);
+
+    let options = || {
+        KvStore::builder()
+            .encryption_key(encryption_key(1))
+            .segment_max_size(4096)
+            .compaction_trigger(CompactionTrigger::Operations(1))
+    };
+    let store = options().open(temp_dir.path())?;
+    for _ in 0..3 {
+        store.set(b"before".to_vec(), b"value".to_vec())?;
+    }
+    let mut compacted = false;
+    for _ in 0..100 {
+        if file_key_ids(&temp_dir) == vec![Some(1)] {
+            compacted = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(compacted, "the log was not compacted");
+    store.set(b"after".to_vec(), b"after-value".to_vec())?;
+    assert_eq!(store.get(b"after".to_vec())?, Some(b"after-value".to_vec()));
+    drop(store);
+
+    let store = options().open(temp_dir.path())?;
+    assert_eq!(store.get(b"after".to_vec())?, Some(b"after-value".to_vec()));
+    for key_id in 0..3000 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(b"value".to_vec())
+        );
+    }
+    Ok(())
+}
+
+// Should encrypt a plain text store once a key is added, and rewrite every file with the
+// newest key after a rotation.
+#[test]
+fn rotate_encryption_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let large = |key_id: u8| vec![key_id; 4 * 1024];
+    let store = KvStore::builder()
+        .blob_threshold(1024)
+        .open(temp_dir.path())?;
+    store.set(b"plain".to_vec(), b"value1".to_vec())?;
+    store.set(b"large-plain".to_vec(), large(1))?;
+    drop(store);
+
+    let store = KvStore::builder()
+        .encryption_key(encryption_key(1))
+        .blob_threshold(1024)
+        .open(temp_dir.path())?;
+    store.set(b"old-key".to_vec(), b"value2".to_vec())?;
+    store.set(b"large-old-key".to_vec(), large(2))?;
+    drop(store);
+    assert_eq!(file_key_ids(&temp_dir), vec![None, Some(1)]);
+
+    let store = KvStore::builder()
+        .encryption_key(encryption_key(1))
+        .encryption_key(encryption_key(2))
+        .blob_threshold(1024)
+        .compaction_trigger(CompactionTrigger::Operations(100))
+        .open(temp_dir.path())?;
+    for iter in 0..200 {
+        store.set(b"counter".to_vec(), format!("{}", iter).into_bytes())?;
+    }
+    let mut rotated = false;
+    for _ in 0..100 {
+        if file_key_ids(&temp_dir) == vec![Some(2)] {
+            rotated = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(rotated, "files were not rewritten with the newest key");
+    drop(store);
+
+    match KvStore::open_encrypted(temp_dir.path(), encryption_key(1)) {
+        Err(err) => assert_eq!(err.kind(), ErrorKind::UnknownEncryptionKey(2)),
+        Ok(_store) => panic!("opened without the encryption key"),
+    }
+    let store = KvStore::builder()
+        .encryption_key(encryption_key(2))
+        .blob_threshold(1024)
+        .open(temp_dir.path())?;
+    assert_eq!(store.get(b"plain".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"large-plain".to_vec())?, Some(large(1)));
+    assert_eq!(store.get(b"old-key".to_vec())?, Some(b"value2".to_vec()));
+    assert_eq!(store.get(b"large-old-key".to_vec())?, Some(large(2)));
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"199".to_vec()));
+
+    Ok(())
+}
+
+// Should answer the gets of a key from the cache until it is written, and keep the cache
+// within its size.
+#[test]
+fn value_cache() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::builder()
+        .cache_size(1024)
+        .compaction_trigger(CompactionTrigger::Operations(100))
+        .open(temp_dir.path())?;
+
+    store.set(b"hot".to_vec(), b"value1".to_vec())?;
+    assert_eq!(store.get(b"missing".to_vec())?, None);
+    for _ in 0..10 {
+        assert_eq!(store.get(b"hot".to_vec())?, Some(b"value1".to_vec()));
+    }
+    let stats = store.cache_stats();
+    assert_eq!((stats.hits, stats.misses), (9, 1));
+    assert_eq!(stats.size, 9);
+
+    store.set(b"hot".to_vec(), b"value2".to_vec())?;
+    assert_eq!(store.get(b"hot".to_vec())?, Some(b"value2".to_vec()));
+    let mut batch = WriteBatch::new();
+    batch.set(b"hot".to_vec(), b"value3".to_vec());
+    store.write_batch(batch)?;
+    assert_eq!(store.get(b"hot".to_vec())?, Some(b"value3".to_vec()));
+    store.remove(b"hot".to_vec())?;
+    assert_eq!(store.get(b"hot".to_vec())?, None);
+    assert_eq!(store.cache_stats().misses, 3);
+
+    // The values cached before a compaction are still the right ones after it.
+    for key_id in 0..10 {
+        store.set(format!("key{}", key_id).into_bytes(), b"value".to_vec())?;
+        store.get(format!("key{}", key_id).into_bytes())?;
+    }
+    for iter in 0..1000 {
+        store.set(b"counter".to_vec(), format!("{}", iter).into_bytes())?;
+    }
+    for key_id in 0..10 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(b"value".to_vec())
+        );
+    }
+    assert_eq!(store.cache_stats().misses, 13);
+
+    for key_id in 0..100 {
+        store.set(format!("large{}", key_id).into_bytes(), vec![0; 100])?;
+        store.get(format!("large{}", key_id).into_bytes())?;
+    }
+    assert!(store.cache_stats().size <= 1024);
+    assert_eq!(store.get(b"large99".to_vec())?, Some(vec![0; 100]));
+    assert_eq!(store.cache_stats().misses, 113);
+
+    Ok(())
+}
+
+// Should count nothing when no cache size is configured.
+#[test]
+fn no_value_cache() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set(b"key".to_vec(), b"value".to_vec())?;
+    assert_eq!(store.get(b"key".to_vec())?, Some(b"value".to_vec()));
+    assert_eq!(store.cache_stats(), CacheStats::default());
+    Ok(())
+}
+
+// Should read the sealed segments through their mapping, and keep reading the right records
+// while the compaction replaces the segments.
+#[test]
+fn mmap_reads() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = || {
+        KvStore::builder()
+            .mmap_reads(true)
+            .segment_max_size(1024)
+            .compaction_trigger(CompactionTrigger::Operations(500))
+    };
+    let store = options().open(temp_dir.path())?;
+
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id).into_bytes(), b"value0".to_vec())?;
+    }
+    let snapshot = store.snapshot()?;
+    for iter in 1..20 {
+        for key_id in 0..100 {
+            store.set(
+                format!("key{}", key_id).into_bytes(),
+                format!("value{}", iter).into_bytes(),
+            )?;
+        }
+        for key_id in 0..100 {
+            assert_eq!(
+                store.get(format!("key{}", key_id).into_bytes())?,
+                Some(format!("value{}", iter).into_bytes())
+            );
+        }
+        assert_eq!(snapshot.get(b"key42".to_vec())?, Some(b"value0".to_vec()));
+    }
+    assert_eq!(
+        store.scan(b"key1".to_vec(), b"key11".to_vec(), 3)?,
+        vec![
+            (b"key1".to_vec(), b"value19".to_vec()),
+            (b"key10".to_vec(), b"value19".to_vec()),
+        ]
+    );
+
+    drop(snapshot);
+    drop(store);
+    let store = options().open(temp_dir.path())?;
+    for key_id in 0..100 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(b"value19".to_vec())
+        );
+    }
+
+    Ok(())
+}
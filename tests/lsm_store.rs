@@ -0,0 +1,624 @@
+use kvs::engines::{
+    CompactionStyle, KvsSnapshot, LsmStore, LsmStoreOptions, SyncPolicy, WriteBatch,
+};
+use kvs::error::{Error, ErrorKind};
+use kvs::{KvsEngine, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Options flushing and compacting after a few writes, so tests go through the tables.
+fn small_options() -> LsmStoreOptions {
+    LsmStore::builder()
+        .memtable_size(4 * 1024)
+        .table_size(2 * 1024)
+        .block_size(256)
+}
+
+fn files_with_extension(dir: &TempDir, extension: &str) -> Vec<Vec<u8>> {
+    fs::read_dir(dir.path())
+        .unwrap()
+        .flatten()
+        .filter(|entry| entry.path().extension() == Some(extension.as_ref()))
+        .map(|entry| fs::read(entry.path()).unwrap())
+        .collect()
+}
+
+// Number of entries of a table, stored in its footer after the index and bloom offsets.
+fn table_entries(table: &[u8]) -> u64 {
+    let footer = &table[table.len() - 32..];
+    let mut entries = [0; 8];
+    entries.copy_from_slice(&footer[16..24]);
+    u64::from_le_bytes(entries)
+}
+
+// Should get previously stored value.
+#[test]
+fn get_stored_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmStore::open(temp_dir.path())?;
+
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    store.set(b"key2".to_vec(), b"value2".to_vec())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+    assert_eq!(store.get(b"key3".to_vec())?, None);
+
+    // Open from disk again and check persistent data.
+    drop(store);
+    let store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn remove_non_existent_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(
+        store.remove(b"key1".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    Ok(())
+}
+
+// Should keep the acknowledged writes across a reopen, whatever the sync policy.
+#[test]
+fn sync_policies() -> Result<()> {
+    let policies = [
+        SyncPolicy::Always,
+        SyncPolicy::EveryMillis(10),
+        SyncPolicy::Never,
+    ];
+    for &policy in &policies {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = small_options().sync_policy(policy).open(temp_dir.path())?;
+        for key_id in 0..300 {
+            store.set(
+                format!("key{}", key_id).into_bytes(),
+                format!("value{}", key_id).into_bytes(),
+            )?;
+        }
+        store.remove(b"key0".to_vec())?;
+
+        drop(store);
+        let store = LsmStore::open(temp_dir.path())?;
+        assert_eq!(store.get(b"key0".to_vec())?, None);
+        for key_id in 1..300 {
+            assert_eq!(
+                store.get(format!("key{}", key_id).into_bytes())?,
+                Some(format!("value{}", key_id).into_bytes())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Should flush the memtable to tables and read the values back from them.
+#[test]
+fn flush_memtable() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = small_options().open(temp_dir.path())?;
+
+    for key_id in 0..1000 {
+        store.set(
+            format!("key{:04}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    drop(store);
+    assert!(!files_with_extension(&temp_dir, "sst").is_empty());
+
+    let store = small_options().open(temp_dir.path())?;
+    for key_id in 0..1000 {
+        assert_eq!(
+            store.get(format!("key{:04}", key_id).into_bytes())?,
+            Some(format!("value{}", key_id).into_bytes())
+        );
+    }
+    assert_eq!(store.get(b"key".to_vec())?, None);
+    assert_eq!(store.get(b"key1000".to_vec())?, None);
+
+    Ok(())
+}
+
+// Should retry a failed flush on its own, although the writes do not trigger it again while
+// the memtable is frozen.
+#[test]
+fn retry_failed_flush() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = small_options().open(temp_dir.path())?;
+    // A directory in place of the temporary file of a table makes its flush fail.
+    let blockers: Vec<_> = (0..100)
+        .map(|id| temp_dir.path().join(format!("{}.tmp", id)))
+        .collect();
+    for blocker in &blockers {
+        fs::create_dir(blocker).expect("unable to create directory");
+    }
+
+    for key_id in 0..200 {
+        store.set(
+            format!("key{:04}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    thread::sleep(Duration::from_millis(50));
+    assert!(files_with_extension(&temp_dir, "sst").is_empty());
+
+    for blocker in &blockers {
+        fs::remove_dir(blocker).expect("unable to remove directory");
+    }
+    let mut flushed = false;
+    for _ in 0..100 {
+        if !files_with_extension(&temp_dir, "sst").is_empty() {
+            flushed = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(flushed, "the failed flush was not retried");
+
+    drop(store);
+    let store = small_options().open(temp_dir.path())?;
+    for key_id in 0..200 {
+        assert_eq!(
+            store.get(format!("key{:04}", key_id).into_bytes())?,
+            Some(format!("value{}", key_id).into_bytes())
+        );
+    }
+
+    Ok(())
+}
+
+// Should refuse the keys, values and batches whose length does not fit in the write-ahead log,
+// writing nothing. The zeroed buffers are never touched, so they take no memory.
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn oversized_keys_and_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmStore::open(temp_dir.path())?;
+    let too_large = u32::MAX as usize + 1;
+
+    let err = store
+        .set(vec![0; too_large], b"value".to_vec())
+        .expect_err("oversized key was written");
+    assert_eq!(err.kind(), ErrorKind::DataTooLarge);
+    let err = store
+        .set(b"key".to_vec(), vec![0; too_large])
+        .expect_err("oversized value was written");
+    assert_eq!(err.kind(), ErrorKind::DataTooLarge);
+    let mut batch = WriteBatch::new();
+    batch
+        .set(b"key1".to_vec(), vec![0; too_large / 2])
+        .set(b"key2".to_vec(), vec![0; too_large / 2]);
+    let err = store
+        .write_batch(batch)
+        .expect_err("oversized batch was written");
+    assert_eq!(err.kind(), ErrorKind::DataTooLarge);
+    assert_eq!(store.get(b"key".to_vec())?, None);
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+
+    store.set(b"key".to_vec(), b"value".to_vec())?;
+    drop(store);
+    let store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key".to_vec())?, Some(b"value".to_vec()));
+
+    Ok(())
+}
+
+// Should merge the overwritten values and the removed keys away, with both compaction styles.
+#[test]
+fn compaction() -> Result<()> {
+    let styles = [
+        CompactionStyle::Leveled {
+            level0_tables: 2,
+            size_ratio: 4,
+        },
+        CompactionStyle::Tiered { tier_width: 2 },
+    ];
+    for &style in &styles {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = small_options()
+            .compaction_style(style)
+            .open(temp_dir.path())?;
+
+        for iter in 0..20 {
+            for key_id in 0..200 {
+                store.set(
+                    format!("key{:03}", key_id).into_bytes(),
+                    format!("value{}:{}", iter, key_id).into_bytes(),
+                )?;
+            }
+        }
+        for key_id in 0..100 {
+            store.remove(format!("key{:03}", key_id).into_bytes())?;
+        }
+        store.set(b"marker".to_vec(), b"removed-value".to_vec())?;
+        store.remove(b"marker".to_vec())?;
+
+        // Dropping the store waits for the compactions in progress.
+        drop(store);
+        let store = small_options()
+            .compaction_style(style)
+            .open(temp_dir.path())?;
+        for key_id in 0..100 {
+            assert_eq!(store.get(format!("key{:03}", key_id).into_bytes())?, None);
+        }
+        for key_id in 100..200 {
+            assert_eq!(
+                store.get(format!("key{:03}", key_id).into_bytes())?,
+                Some(format!("value19:{}", key_id).into_bytes())
+            );
+        }
+        assert_eq!(
+            store.scan(b"key".to_vec(), b"kez".to_vec(), 1000)?.len(),
+            100
+        );
+        drop(store);
+
+        // 20 versions of 200 keys, 100 tombstones and the marker were written, the compaction
+        // drops most of them.
+        let written = 20 * 200 + 100 + 2;
+        let entries: u64 = files_with_extension(&temp_dir, "sst")
+            .iter()
+            .map(|table| table_entries(table))
+            .sum();
+        assert!(entries >= 100, "live keys were dropped");
+        assert!(entries < written / 4, "tables were not compacted");
+    }
+
+    Ok(())
+}
+
+// Should keep the writes acknowledged before a torn write at the end of the log.
+#[test]
+fn recover_torn_wal() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmStore::open(temp_dir.path())?;
+    for key_id in 0..10 {
+        store.set(
+            format!("key{}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    drop(store);
+
+    let wal = fs::read_dir(temp_dir.path())
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.extension() == Some("wal".as_ref()))
+        .expect("no write-ahead log");
+    let mut file = OpenOptions::new().append(true).open(&wal).unwrap();
+    file.write_all(&[42, 0, 0, 0, 200, 0, 0, 0, 1, 2, 3])
+        .unwrap();
+    drop(file);
+
+    let store = LsmStore::open(temp_dir.path())?;
+    for key_id in 0..10 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(format!("value{}", key_id).into_bytes())
+        );
+    }
+    store.set(b"key10".to_vec(), b"value10".to_vec())?;
+    drop(store);
+    let store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key10".to_vec())?, Some(b"value10".to_vec()));
+
+    Ok(())
+}
+
+// Should return the keys of a range in lexicographic order, from the memtable and the tables.
+#[test]
+fn scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = small_options().open(temp_dir.path())?;
+
+    for key_id in (0..300).rev() {
+        store.set(
+            format!("key{:03}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    store.remove(b"key011".to_vec())?;
+    store.set(b"key012".to_vec(), b"other".to_vec())?;
+
+    let expected = vec![
+        (b"key010".to_vec(), b"value10".to_vec()),
+        (b"key012".to_vec(), b"other".to_vec()),
+    ];
+    assert_eq!(
+        store.scan(b"key010".to_vec(), b"key013".to_vec(), 10)?,
+        expected
+    );
+    assert_eq!(
+        store.scan(b"key010".to_vec(), b"key299".to_vec(), 2)?,
+        expected
+    );
+    assert!(store
+        .scan(b"key013".to_vec(), b"key010".to_vec(), 10)?
+        .is_empty());
+    assert_eq!(
+        store.scan(b"key".to_vec(), b"kez".to_vec(), 1000)?.len(),
+        299
+    );
+
+    Ok(())
+}
+
+// Should iterate over the keys of a prefix in order, across several pages.
+#[test]
+fn scan_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = small_options().open(temp_dir.path())?;
+
+    for user_id in 1..4 {
+        for key_id in 0..300 {
+            let key = format!("user:{}:{:03}", user_id, key_id).into_bytes();
+            store.set(key, format!("{}", key_id).into_bytes())?;
+        }
+    }
+
+    let pairs = store
+        .scan_prefix(b"user:2:".to_vec())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(pairs.len(), 300);
+    for (key_id, (key, value)) in pairs.into_iter().enumerate() {
+        assert_eq!(key, format!("user:2:{:03}", key_id).into_bytes());
+        assert_eq!(value, format!("{}", key_id).into_bytes());
+    }
+    assert_eq!(store.scan_prefix(Vec::new()).count(), 900);
+    assert_eq!(store.scan_prefix(b"user:4:".to_vec()).count(), 0);
+
+    Ok(())
+}
+
+// Should keep the values of a snapshot after they are overwritten, removed or compacted.
+#[test]
+fn snapshot() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = small_options().open(temp_dir.path())?;
+
+    for key_id in 0..100 {
+        store.set(
+            format!("key{:02}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    let snapshot = store.snapshot()?;
+
+    store.remove(b"key00".to_vec())?;
+    store.set(b"key01".to_vec(), b"other".to_vec())?;
+    store.set(b"key100".to_vec(), b"value100".to_vec())?;
+    for iter in 0..10 {
+        for key_id in 0..100 {
+            store.set(
+                format!("key{:02}", key_id).into_bytes(),
+                format!("new{}", iter).into_bytes(),
+            )?;
+        }
+    }
+
+    assert_eq!(snapshot.get(b"key100".to_vec())?, None);
+    for key_id in 0..100 {
+        assert_eq!(
+            snapshot.get(format!("key{:02}", key_id).into_bytes())?,
+            Some(format!("value{}", key_id).into_bytes())
+        );
+    }
+    let pairs = snapshot.scan(b"key".to_vec(), b"kez".to_vec(), 1000)?;
+    assert_eq!(pairs.len(), 100);
+    assert_eq!(pairs[1], (b"key01".to_vec(), b"value1".to_vec()));
+    assert_eq!(
+        snapshot
+            .scan_prefix_after(b"key9".to_vec(), Some(b"key95".to_vec()), 1000)?
+            .len(),
+        4
+    );
+
+    Ok(())
+}
+
+// Should apply the writes of a batch together, and keep them across a reopen.
+#[test]
+fn write_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmStore::open(temp_dir.path())?;
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+
+    let mut batch = WriteBatch::new();
+    batch
+        .set(b"key2".to_vec(), b"value2".to_vec())
+        .set(b"key3".to_vec(), b"value3".to_vec())
+        .remove(b"key1".to_vec())
+        .remove(b"missing".to_vec());
+    store.write_batch(batch)?;
+
+    drop(store);
+    let store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+    assert_eq!(store.get(b"key3".to_vec())?, Some(b"value3".to_vec()));
+
+    Ok(())
+}
+
+// Should not lose any increment when concurrent transactions read and write the same key,
+// and write nothing when a transaction fails.
+#[test]
+fn transaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = small_options().open(temp_dir.path())?;
+
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let store = store.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..50 {
+                store
+                    .transaction(|tx| {
+                        let counter = tx.get(b"counter".to_vec())?.unwrap_or_default();
+                        tx.set(b"counter".to_vec(), [&counter[..], b"+"].concat())
+                    })
+                    .unwrap();
+                store.set(b"other".to_vec(), b"value".to_vec()).unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"+".repeat(200)));
+
+    let err = store
+        .transaction(|tx| -> Result<()> {
+            tx.remove(b"counter".to_vec())?;
+            Err(Error::from(ErrorKind::InvalidData))
+        })
+        .expect_err("aborted transaction succeeded");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"+".repeat(200)));
+
+    Ok(())
+}
+
+// Should only swap or set a key when its current value is the expected one.
+#[test]
+fn compare_and_swap() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmStore::open(temp_dir.path())?;
+
+    assert!(store.set_if_absent(b"key1".to_vec(), b"value1".to_vec())?);
+    assert!(!store.set_if_absent(b"key1".to_vec(), b"value2".to_vec())?);
+    assert!(!store.set_if_present(b"key2".to_vec(), b"value2".to_vec())?);
+    assert!(store.set_if_present(b"key1".to_vec(), b"value3".to_vec())?);
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value3".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, None);
+
+    let key = b"key1".to_vec();
+    assert!(!store.compare_and_swap(key.clone(), None, Some(b"value4".to_vec()))?);
+    assert!(store.compare_and_swap(key.clone(), Some(b"value3".to_vec()), Some(Vec::new()))?);
+    assert!(store.compare_and_swap(key.clone(), Some(Vec::new()), None)?);
+    assert_eq!(store.get(key)?, None);
+
+    Ok(())
+}
+
+// Should hide expired keys from every read and keep expiries across restarts.
+#[test]
+fn expire_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmStore::open(temp_dir.path())?;
+
+    store.set_with_ttl(
+        b"key1".to_vec(),
+        b"value1".to_vec(),
+        Duration::from_millis(200),
+    )?;
+    store.set_with_ttl(
+        b"key2".to_vec(),
+        b"value2".to_vec(),
+        Duration::from_millis(200),
+    )?;
+    store.set(b"key2".to_vec(), b"value2".to_vec())?;
+    store.set(b"key3".to_vec(), b"value3".to_vec())?;
+    assert!(store.expire(b"key3".to_vec(), Duration::from_secs(3600))?);
+    assert!(!store.expire(b"key4".to_vec(), Duration::from_secs(3600))?);
+    assert_eq!(store.ttl(b"key2".to_vec())?, None);
+    drop(store);
+
+    let store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    let ttl = store.ttl(b"key3".to_vec())?.expect("key3 should expire");
+    assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+    assert!(store.persist(b"key3".to_vec())?);
+    assert!(!store.persist(b"key3".to_vec())?);
+
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+    assert_eq!(
+        store.scan(b"key".to_vec(), b"kez".to_vec(), 10)?,
+        vec![
+            (b"key2".to_vec(), b"value2".to_vec()),
+            (b"key3".to_vec(), b"value3".to_vec()),
+        ]
+    );
+    assert_eq!(
+        store.remove(b"key1".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    assert!(store.set_if_absent(b"key1".to_vec(), b"value4".to_vec())?);
+    assert_eq!(store.ttl(b"key1".to_vec())?, None);
+
+    Ok(())
+}
+
+// Should add to the integers stored in keys, refusing other values.
+#[test]
+fn increment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = LsmStore::open(temp_dir.path())?;
+
+    assert_eq!(store.increment(b"counter".to_vec(), 1)?, 1);
+    assert_eq!(store.increment(b"counter".to_vec(), 41)?, 42);
+    assert_eq!(store.increment(b"counter".to_vec(), -50)?, -8);
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"-8".to_vec()));
+
+    store.set(b"name".to_vec(), b"alice".to_vec())?;
+    assert_eq!(
+        store.increment(b"name".to_vec(), 1).unwrap_err().kind(),
+        ErrorKind::NotAnInteger
+    );
+    assert_eq!(store.get(b"name".to_vec())?, Some(b"alice".to_vec()));
+    store.set(b"max".to_vec(), i64::MAX.to_string().into_bytes())?;
+    assert_eq!(
+        store.increment(b"max".to_vec(), 1).unwrap_err().kind(),
+        ErrorKind::IntegerOverflow
+    );
+
+    // The expiry of the counter is kept
+    store.set_with_ttl(b"visits".to_vec(), b"1".to_vec(), Duration::from_secs(60))?;
+    assert_eq!(store.increment(b"visits".to_vec(), 1)?, 2);
+    assert!(store.ttl(b"visits".to_vec())?.is_some());
+
+    Ok(())
+}
+
+// Should not lose any increment done by concurrent threads, while the memtable is flushed.
+#[test]
+fn concurrent_increment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = small_options().open(temp_dir.path())?;
+
+    let barrier = Arc::new(Barrier::new(4));
+    let mut handles = Vec::new();
+    for thread_id in 0..4 {
+        let store = store.clone();
+        let barrier = barrier.clone();
+        handles.push(thread::spawn(move || {
+            barrier.wait();
+            for key_id in 0..100 {
+                store.increment(b"counter".to_vec(), 1).unwrap();
+                store
+                    .set(
+                        format!("key{}:{}", thread_id, key_id).into_bytes(),
+                        b"value".repeat(10),
+                    )
+                    .unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"400".to_vec()));
+
+    Ok(())
+}
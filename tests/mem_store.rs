@@ -0,0 +1,292 @@
+use kvs::engines::{KvsSnapshot, MemStore, WriteBatch};
+use kvs::error::{Error, ErrorKind};
+use kvs::{KvsEngine, Result};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+// Should get previously stored values, and the last one of an overwritten key.
+#[test]
+fn get_stored_value() -> Result<()> {
+    let store = MemStore::new();
+
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    store.set(b"key2".to_vec(), b"value2".to_vec())?;
+    store.set(b"key1".to_vec(), b"value3".to_vec())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value3".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+    assert_eq!(store.get(b"key3".to_vec())?, None);
+
+    // Every clone shares the same keys
+    let other = store.clone();
+    other.remove(b"key2".to_vec())?;
+    assert_eq!(store.get(b"key2".to_vec())?, None);
+
+    Ok(())
+}
+
+#[test]
+fn remove_non_existent_key() -> Result<()> {
+    let store = MemStore::new();
+    assert_eq!(
+        store.remove(b"key1".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+    store.remove(b"key1".to_vec())?;
+    assert_eq!(
+        store.remove(b"key1".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    Ok(())
+}
+
+// Should return the keys of a range and of a prefix in lexicographic order.
+#[test]
+fn scan() -> Result<()> {
+    let store = MemStore::new();
+
+    for key_id in (0..100).rev() {
+        store.set(
+            format!("key{:03}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    store.remove(b"key011".to_vec())?;
+
+    let expected = vec![
+        (b"key010".to_vec(), b"value10".to_vec()),
+        (b"key012".to_vec(), b"value12".to_vec()),
+        (b"key013".to_vec(), b"value13".to_vec()),
+    ];
+    assert_eq!(
+        store.scan(b"key010".to_vec(), b"key020".to_vec(), 3)?,
+        expected
+    );
+    assert_eq!(
+        store.scan(b"key020".to_vec(), b"key010".to_vec(), 3)?,
+        vec![]
+    );
+
+    let pairs = store
+        .scan_prefix(b"key09".to_vec())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(pairs.len(), 10);
+    assert_eq!(pairs[0], (b"key090".to_vec(), b"value90".to_vec()));
+    assert_eq!(store.scan_prefix(Vec::new()).count(), 99);
+
+    Ok(())
+}
+
+// Should keep the values of a snapshot after they are overwritten or removed.
+#[test]
+fn snapshot() -> Result<()> {
+    let store = MemStore::new();
+
+    for key_id in 0..100 {
+        store.set(
+            format!("key{:02}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    let snapshot = store.snapshot()?;
+
+    store.remove(b"key00".to_vec())?;
+    store.set(b"key01".to_vec(), b"other".to_vec())?;
+    store.set(b"key100".to_vec(), b"value100".to_vec())?;
+
+    assert_eq!(snapshot.get(b"key100".to_vec())?, None);
+    assert_eq!(snapshot.get(b"key00".to_vec())?, Some(b"value0".to_vec()));
+    let pairs = snapshot.scan(b"key".to_vec(), b"kez".to_vec(), 1000)?;
+    assert_eq!(pairs.len(), 100);
+    assert_eq!(pairs[1], (b"key01".to_vec(), b"value1".to_vec()));
+    assert_eq!(
+        snapshot
+            .scan_prefix_after(b"key9".to_vec(), Some(b"key95".to_vec()), 1000)?
+            .len(),
+        4
+    );
+
+    Ok(())
+}
+
+// Should apply the writes of a batch together.
+#[test]
+fn write_batch() -> Result<()> {
+    let store = MemStore::new();
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+
+    let mut batch = WriteBatch::new();
+    batch
+        .set(b"key2".to_vec(), b"value2".to_vec())
+        .set(b"key3".to_vec(), b"value3".to_vec())
+        .remove(b"key1".to_vec())
+        .remove(b"missing".to_vec());
+    store.write_batch(batch)?;
+
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+    assert_eq!(store.get(b"key3".to_vec())?, Some(b"value3".to_vec()));
+
+    Ok(())
+}
+
+// Should not lose any update when concurrent transactions read and write the same key,
+// and write nothing when a transaction fails.
+#[test]
+fn transaction() -> Result<()> {
+    let store = MemStore::new();
+
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let store = store.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..50 {
+                store
+                    .transaction(|tx| {
+                        let counter = tx.get(b"counter".to_vec())?.unwrap_or_default();
+                        tx.set(b"counter".to_vec(), [&counter[..], b"+"].concat())
+                    })
+                    .unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"+".repeat(200)));
+
+    let err = store
+        .transaction(|tx| -> Result<()> {
+            tx.remove(b"counter".to_vec())?;
+            assert_eq!(tx.get(b"counter".to_vec())?, None);
+            Err(Error::from(ErrorKind::InvalidData))
+        })
+        .expect_err("aborted transaction succeeded");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"+".repeat(200)));
+
+    Ok(())
+}
+
+// Should only swap or set a key when its current value is the expected one.
+#[test]
+fn compare_and_swap() -> Result<()> {
+    let store = MemStore::new();
+
+    assert!(store.set_if_absent(b"key1".to_vec(), b"value1".to_vec())?);
+    assert!(!store.set_if_absent(b"key1".to_vec(), b"value2".to_vec())?);
+    assert!(!store.set_if_present(b"key2".to_vec(), b"value2".to_vec())?);
+    assert!(store.set_if_present(b"key1".to_vec(), b"value3".to_vec())?);
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value3".to_vec()));
+
+    let key = b"key1".to_vec();
+    assert!(!store.compare_and_swap(key.clone(), None, Some(b"value4".to_vec()))?);
+    assert!(store.compare_and_swap(key.clone(), Some(b"value3".to_vec()), None)?);
+    assert_eq!(store.get(key)?, None);
+
+    Ok(())
+}
+
+// Should hide expired keys from every read, snapshots included.
+#[test]
+fn expire_keys() -> Result<()> {
+    let store = MemStore::new();
+
+    store.set_with_ttl(
+        b"key1".to_vec(),
+        b"value1".to_vec(),
+        Duration::from_millis(200),
+    )?;
+    store.set_with_ttl(
+        b"key2".to_vec(),
+        b"value2".to_vec(),
+        Duration::from_millis(200),
+    )?;
+    store.set(b"key2".to_vec(), b"value2".to_vec())?;
+    store.set(b"key3".to_vec(), b"value3".to_vec())?;
+    assert!(store.expire(b"key3".to_vec(), Duration::from_secs(3600))?);
+    assert!(!store.expire(b"key4".to_vec(), Duration::from_secs(3600))?);
+    assert_eq!(store.ttl(b"key2".to_vec())?, None);
+    assert_eq!(
+        store.ttl(b"key4".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+
+    let ttl = store.ttl(b"key3".to_vec())?.expect("key3 should expire");
+    assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+    assert!(store.persist(b"key3".to_vec())?);
+    assert!(!store.persist(b"key3".to_vec())?);
+
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+    assert_eq!(store.snapshot()?.get(b"key1".to_vec())?, None);
+    assert_eq!(
+        store.scan(b"key".to_vec(), b"kez".to_vec(), 10)?,
+        vec![
+            (b"key2".to_vec(), b"value2".to_vec()),
+            (b"key3".to_vec(), b"value3".to_vec()),
+        ]
+    );
+    assert_eq!(
+        store.remove(b"key1".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    assert!(store.set_if_absent(b"key1".to_vec(), b"value4".to_vec())?);
+    assert_eq!(store.ttl(b"key1".to_vec())?, None);
+
+    Ok(())
+}
+
+// Should add to the integers stored in keys, refusing other values.
+#[test]
+fn increment() -> Result<()> {
+    let store = MemStore::new();
+
+    assert_eq!(store.increment(b"counter".to_vec(), 1)?, 1);
+    assert_eq!(store.increment(b"counter".to_vec(), -9)?, -8);
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"-8".to_vec()));
+
+    store.set(b"name".to_vec(), b"alice".to_vec())?;
+    assert_eq!(
+        store.increment(b"name".to_vec(), 1).unwrap_err().kind(),
+        ErrorKind::NotAnInteger
+    );
+    store.set(b"max".to_vec(), i64::MAX.to_string().into_bytes())?;
+    assert_eq!(
+        store.increment(b"max".to_vec(), 1).unwrap_err().kind(),
+        ErrorKind::IntegerOverflow
+    );
+
+    // The expiry of the counter is kept
+    store.set_with_ttl(b"visits".to_vec(), b"1".to_vec(), Duration::from_secs(60))?;
+    assert_eq!(store.increment(b"visits".to_vec(), 1)?, 2);
+    assert!(store.ttl(b"visits".to_vec())?.is_some());
+
+    Ok(())
+}
+
+// Should not lose any increment done by concurrent threads.
+#[test]
+fn concurrent_increment() -> Result<()> {
+    let store = MemStore::new();
+
+    let barrier = Arc::new(Barrier::new(4));
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let store = store.clone();
+        let barrier = barrier.clone();
+        handles.push(thread::spawn(move || {
+            barrier.wait();
+            for _ in 0..100 {
+                store.increment(b"counter".to_vec(), 1).unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"400".to_vec()));
+
+    Ok(())
+}
@@ -0,0 +1,461 @@
+use kvs::engines::{CompressionPolicy, KvsSnapshot, SledStore, SyncPolicy, WriteBatch};
+use kvs::error::{Error, ErrorKind};
+use kvs::{KvsEngine, Result};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Should keep the acknowledged writes across a reopen, whatever the sync policy.
+#[test]
+fn sync_policies() -> Result<()> {
+    let policies = [
+        SyncPolicy::Always,
+        SyncPolicy::EveryMillis(10),
+        SyncPolicy::Never,
+    ];
+    for &policy in &policies {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = SledStore::open_with_sync_policy(temp_dir.path(), policy)?;
+        for key_id in 0..100 {
+            store.set(
+                format!("key{}", key_id).into_bytes(),
+                format!("value{}", key_id).into_bytes(),
+            )?;
+        }
+        store.remove(b"key0".to_vec())?;
+
+        drop(store);
+        let store = SledStore::open(temp_dir.path())?;
+        assert_eq!(store.get(b"key0".to_vec())?, None);
+        for key_id in 1..100 {
+            assert_eq!(
+                store.get(format!("key{}", key_id).into_bytes())?,
+                Some(format!("value{}", key_id).into_bytes())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn remove_non_existent_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open_with_sync_policy(temp_dir.path(), SyncPolicy::Never)?;
+    assert!(store.remove(b"key1".to_vec()).is_err());
+    Ok(())
+}
+
+// Should store keys and values holding any byte.
+#[test]
+fn binary_keys_and_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open(temp_dir.path())?;
+
+    let key = vec![0, 159, 146, 150, b' ', b'\n'];
+    let value = vec![b'\r', b'\n', 0, 255, b' ', 254];
+    store.set(key.clone(), value.clone())?;
+    assert_eq!(store.get(key.clone())?, Some(value));
+    store.remove(key.clone())?;
+    assert_eq!(store.get(key)?, None);
+
+    Ok(())
+}
+
+// Should return the keys of a range in lexicographic order.
+#[test]
+fn scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open(temp_dir.path())?;
+
+    for key_id in (0..100).rev() {
+        store.set(
+            format!("key{:03}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    store.remove(b"key011".to_vec())?;
+
+    let expected = vec![
+        (b"key010".to_vec(), b"value10".to_vec()),
+        (b"key012".to_vec(), b"value12".to_vec()),
+    ];
+    assert_eq!(
+        store.scan(b"key010".to_vec(), b"key013".to_vec(), 10)?,
+        expected
+    );
+    assert_eq!(
+        store.scan(b"key010".to_vec(), b"key099".to_vec(), 2)?,
+        expected
+    );
+    assert!(store
+        .scan(b"key013".to_vec(), b"key010".to_vec(), 10)?
+        .is_empty());
+
+    Ok(())
+}
+
+// Should iterate over the keys of a prefix in order, across several pages.
+#[test]
+fn scan_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open_with_sync_policy(temp_dir.path(), SyncPolicy::Never)?;
+
+    for user_id in 1..4 {
+        for key_id in 0..300 {
+            let key = format!("user:{}:{:03}", user_id, key_id).into_bytes();
+            store.set(key, format!("{}", key_id).into_bytes())?;
+        }
+    }
+
+    let pairs = store
+        .scan_prefix(b"user:2:".to_vec())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(pairs.len(), 300);
+    for (key_id, (key, value)) in pairs.into_iter().enumerate() {
+        assert_eq!(key, format!("user:2:{:03}", key_id).into_bytes());
+        assert_eq!(value, format!("{}", key_id).into_bytes());
+    }
+    assert_eq!(store.scan_prefix(Vec::new()).count(), 900);
+    assert_eq!(store.scan_prefix(b"user:4:".to_vec()).count(), 0);
+
+    Ok(())
+}
+
+// Should keep the values of a snapshot after they are overwritten or removed.
+#[test]
+fn snapshot() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open_with_sync_policy(temp_dir.path(), SyncPolicy::Never)?;
+
+    for key_id in 0..100 {
+        store.set(
+            format!("key{:02}", key_id).into_bytes(),
+            format!("value{}", key_id).into_bytes(),
+        )?;
+    }
+    let snapshot = store.snapshot()?;
+
+    store.remove(b"key00".to_vec())?;
+    store.set(b"key01".to_vec(), b"other".to_vec())?;
+    store.set(b"key100".to_vec(), b"value100".to_vec())?;
+
+    assert_eq!(snapshot.get(b"key100".to_vec())?, None);
+    for key_id in 0..100 {
+        assert_eq!(
+            snapshot.get(format!("key{:02}", key_id).into_bytes())?,
+            Some(format!("value{}", key_id).into_bytes())
+        );
+    }
+    let pairs = snapshot.scan(b"key".to_vec(), b"kez".to_vec(), 1000)?;
+    assert_eq!(pairs.len(), 100);
+    assert_eq!(pairs[1], (b"key01".to_vec(), b"value1".to_vec()));
+    assert_eq!(
+        snapshot
+            .scan_prefix_after(b"key9".to_vec(), Some(b"key95".to_vec()), 1000)?
+            .len(),
+        4
+    );
+
+    Ok(())
+}
+
+// Should see the writes of a batch all or not at all while concurrent batches move amounts
+// between keys, keeping their sum.
+#[test]
+fn snapshot_while_writing() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open_with_sync_policy(temp_dir.path(), SyncPolicy::Never)?;
+    for key_id in 0..10 {
+        store.set(format!("key{}", key_id).into_bytes(), b"100".to_vec())?;
+    }
+
+    let writer = {
+        let store = store.clone();
+        thread::spawn(move || -> Result<()> {
+            for round in 0..500 {
+                let (from, to) = (round % 10, (round * 7 + 3) % 10);
+                let amount = |key_id| -> Result<i64> {
+                    let value = store.get(format!("key{}", key_id).into_bytes())?.unwrap();
+                    Ok(String::from_utf8(value).unwrap().parse().unwrap())
+                };
+                let (from_amount, to_amount) = (amount(from)?, amount(to)?);
+                if from == to {
+                    continue;
+                }
+                let mut batch = WriteBatch::new();
+                batch
+                    .set(
+                        format!("key{}", from).into_bytes(),
+                        (from_amount - 1).to_string().into_bytes(),
+                    )
+                    .set(
+                        format!("key{}", to).into_bytes(),
+                        (to_amount + 1).to_string().into_bytes(),
+                    );
+                store.write_batch(batch)?;
+            }
+            Ok(())
+        })
+    };
+
+    for _ in 0..50 {
+        let snapshot = store.snapshot()?;
+        let sum = |pairs: Vec<(Vec<u8>, Vec<u8>)>| -> i64 {
+            pairs
+                .into_iter()
+                .map(|(_key, value)| String::from_utf8(value).unwrap().parse::<i64>().unwrap())
+                .sum()
+        };
+        assert_eq!(
+            sum(snapshot.scan(b"key".to_vec(), b"kez".to_vec(), 100)?),
+            1000
+        );
+        thread::sleep(Duration::from_millis(1));
+        assert_eq!(
+            sum(snapshot.scan(b"key".to_vec(), b"kez".to_vec(), 100)?),
+            1000
+        );
+    }
+    writer.join().unwrap()?;
+
+    Ok(())
+}
+
+// Should apply the writes of a batch together, and keep them across a reopen.
+#[test]
+fn write_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open(temp_dir.path())?;
+    store.set(b"key1".to_vec(), b"value1".to_vec())?;
+
+    let mut batch = WriteBatch::new();
+    batch
+        .set(b"key2".to_vec(), b"value2".to_vec())
+        .set(b"key3".to_vec(), b"value3".to_vec())
+        .remove(b"key1".to_vec())
+        .remove(b"missing".to_vec());
+    store.write_batch(batch)?;
+
+    drop(store);
+    let store = SledStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+    assert_eq!(store.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+    assert_eq!(store.get(b"key3".to_vec())?, Some(b"value3".to_vec()));
+
+    Ok(())
+}
+
+// Should not lose any increment when concurrent transactions read and write the same key,
+// and write nothing when a transaction fails.
+#[test]
+fn transaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open_with_sync_policy(temp_dir.path(), SyncPolicy::Never)?;
+
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let store = store.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..50 {
+                store
+                    .transaction(|tx| {
+                        let counter = tx.get(b"counter".to_vec())?.unwrap_or_default();
+                        tx.set(b"counter".to_vec(), [&counter[..], b"+"].concat())
+                    })
+                    .unwrap();
+                store.set(b"other".to_vec(), b"value".to_vec()).unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"+".repeat(200)));
+
+    let err = store
+        .transaction(|tx| -> Result<()> {
+            tx.remove(b"counter".to_vec())?;
+            Err(Error::from(ErrorKind::InvalidData))
+        })
+        .expect_err("aborted transaction succeeded");
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"+".repeat(200)));
+
+    Ok(())
+}
+
+// Should only swap or set a key when its current value is the expected one.
+#[test]
+fn compare_and_swap() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open_with_sync_policy(temp_dir.path(), SyncPolicy::Never)?;
+
+    assert!(store.set_if_absent(b"key1".to_vec(), b"value1".to_vec())?);
+    assert!(!store.set_if_absent(b"key1".to_vec(), b"value2".to_vec())?);
+    assert!(!store.set_if_present(b"key2".to_vec(), b"value2".to_vec())?);
+    assert!(store.set_if_present(b"key1".to_vec(), b"value3".to_vec())?);
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value3".to_vec()));
+    assert_eq!(store.get(b"key2".to_vec())?, None);
+
+    let key = b"key1".to_vec();
+    assert!(!store.compare_and_swap(key.clone(), None, Some(b"value4".to_vec()))?);
+    assert!(store.compare_and_swap(key.clone(), Some(b"value3".to_vec()), Some(Vec::new()))?);
+    assert!(store.compare_and_swap(key.clone(), Some(Vec::new()), None)?);
+    assert_eq!(store.get(key)?, None);
+
+    Ok(())
+}
+
+// Should hide expired keys from every read and keep expiries across restarts.
+#[test]
+fn expire_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open(temp_dir.path())?;
+
+    store.set_with_ttl(
+        b"key1".to_vec(),
+        b"value1".to_vec(),
+        Duration::from_millis(200),
+    )?;
+    store.set_with_ttl(
+        b"key2".to_vec(),
+        b"value2".to_vec(),
+        Duration::from_millis(200),
+    )?;
+    store.set(b"key2".to_vec(), b"value2".to_vec())?;
+    store.set(b"key3".to_vec(), b"value3".to_vec())?;
+    assert!(store.expire(b"key3".to_vec(), Duration::from_secs(3600))?);
+    assert!(!store.expire(b"key4".to_vec(), Duration::from_secs(3600))?);
+    assert_eq!(store.ttl(b"key2".to_vec())?, None);
+    drop(store);
+
+    let store = SledStore::open(temp_dir.path())?;
+    assert_eq!(store.get(b"key1".to_vec())?, Some(b"value1".to_vec()));
+    let ttl = store.ttl(b"key3".to_vec())?.expect("key3 should expire");
+    assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+    assert!(store.persist(b"key3".to_vec())?);
+    assert!(!store.persist(b"key3".to_vec())?);
+
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(store.get(b"key1".to_vec())?, None);
+    assert_eq!(
+        store.scan(b"key".to_vec(), b"kez".to_vec(), 10)?,
+        vec![
+            (b"key2".to_vec(), b"value2".to_vec()),
+            (b"key3".to_vec(), b"value3".to_vec()),
+        ]
+    );
+    assert_eq!(
+        store.remove(b"key1".to_vec()).unwrap_err().kind(),
+        ErrorKind::KeyNotFound
+    );
+    assert!(store.set_if_absent(b"key1".to_vec(), b"value4".to_vec())?);
+    assert_eq!(store.ttl(b"key1".to_vec())?, None);
+
+    Ok(())
+}
+
+// Should add to the integers stored in keys, refusing other values.
+#[test]
+fn increment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open(temp_dir.path())?;
+
+    assert_eq!(store.increment(b"counter".to_vec(), 1)?, 1);
+    assert_eq!(store.increment(b"counter".to_vec(), 41)?, 42);
+    assert_eq!(store.increment(b"counter".to_vec(), -50)?, -8);
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"-8".to_vec()));
+
+    store.set(b"name".to_vec(), b"alice".to_vec())?;
+    assert_eq!(
+        store.increment(b"name".to_vec(), 1).unwrap_err().kind(),
+        ErrorKind::NotAnInteger
+    );
+    assert_eq!(store.get(b"name".to_vec())?, Some(b"alice".to_vec()));
+    store.set(b"max".to_vec(), i64::MAX.to_string().into_bytes())?;
+    assert_eq!(
+        store.increment(b"max".to_vec(), 1).unwrap_err().kind(),
+        ErrorKind::IntegerOverflow
+    );
+
+    // The expiry of the counter is kept
+    store.set_with_ttl(b"visits".to_vec(), b"1".to_vec(), Duration::from_secs(60))?;
+    assert_eq!(store.increment(b"visits".to_vec(), 1)?, 2);
+    assert!(store.ttl(b"visits".to_vec())?.is_some());
+
+    Ok(())
+}
+
+// Should not lose any increment done by concurrent threads.
+#[test]
+fn concurrent_increment() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open(temp_dir.path())?;
+
+    let barrier = Arc::new(Barrier::new(4));
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let store = store.clone();
+        let barrier = barrier.clone();
+        handles.push(thread::spawn(move || {
+            barrier.wait();
+            for _ in 0..100 {
+                store.increment(b"counter".to_vec(), 1).unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(store.get(b"counter".to_vec())?, Some(b"400".to_vec()));
+
+    Ok(())
+}
+
+// Should keep the values of a compressed database across a reopen.
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_database() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledStore::open_with_compression(
+        temp_dir.path(),
+        SyncPolicy::Always,
+        CompressionPolicy::Always,
+    )?;
+    for key_id in 0..100 {
+        store.set(format!("key{}", key_id).into_bytes(), vec![b'x'; 4096])?;
+    }
+
+    drop(store);
+    let store = SledStore::open_with_compression(
+        temp_dir.path(),
+        SyncPolicy::Always,
+        CompressionPolicy::Always,
+    )?;
+    for key_id in 0..100 {
+        assert_eq!(
+            store.get(format!("key{}", key_id).into_bytes())?,
+            Some(vec![b'x'; 4096])
+        );
+    }
+
+    Ok(())
+}
+
+// Should refuse to compress the database when the crate is built without compression.
+#[cfg(not(feature = "compression"))]
+#[test]
+fn compression_needs_feature() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let result = SledStore::open_with_compression(
+        temp_dir.path(),
+        SyncPolicy::Always,
+        CompressionPolicy::Always,
+    );
+    match result {
+        Err(err) => assert_eq!(err.kind(), ErrorKind::UnsupportedCodec),
+        Ok(_store) => panic!("compression is not built in"),
+    }
+}